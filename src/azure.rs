@@ -0,0 +1,562 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::JjstackError;
+use crate::forge::{AsyncForge, Comment, MergeMethod};
+use crate::http::{self, Pacer, RetryPolicy};
+use crate::PullRequest;
+
+/// Version of the REST API the requests are written against.
+const API_VERSION: &str = "7.1";
+
+/// Longest PR description Azure DevOps accepts, in characters.
+const MAX_BODY_LEN: usize = 4_000;
+
+/// Prefix of the full names of branches, which the API uses throughout.
+const HEADS: &str = "refs/heads/";
+
+/// List is a collection returned by the API.
+#[derive(Debug, Deserialize)]
+struct List<T> {
+    #[serde(rename = "value")]
+    value: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzurePullRequest {
+    #[serde(rename = "pullRequestId")]
+    pull_request_id: i32,
+    #[serde(rename = "title")]
+    title: String,
+    #[serde(rename = "description", default)]
+    description: Option<String>,
+    #[serde(rename = "status")]
+    status: String,
+    #[serde(rename = "sourceRefName")]
+    source_ref_name: String,
+    #[serde(rename = "targetRefName")]
+    target_ref_name: String,
+    #[serde(rename = "isDraft", default)]
+    is_draft: bool,
+    #[serde(rename = "mergeStatus", default)]
+    merge_status: Option<String>,
+    #[serde(rename = "labels", default)]
+    labels: Vec<AzureLabel>,
+    #[serde(rename = "creationDate", default)]
+    creation_date: Option<String>,
+    #[serde(rename = "lastMergeSourceCommit", default)]
+    last_merge_source_commit: Option<AzureCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureLabel {
+    #[serde(rename = "id", default)]
+    id: String,
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureCommit {
+    #[serde(rename = "commitId")]
+    commit_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureRepository {
+    #[serde(rename = "defaultBranch", default)]
+    default_branch: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureRef {
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureThread {
+    #[serde(rename = "id")]
+    id: u64,
+    #[serde(rename = "comments", default)]
+    comments: Vec<AzureComment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AzureComment {
+    #[serde(rename = "id")]
+    id: u64,
+    #[serde(rename = "content", default)]
+    content: Option<String>,
+    #[serde(rename = "commentType", default)]
+    comment_type: Option<String>,
+}
+
+impl AzurePullRequest {
+    fn into_pull_request(self, url: String) -> PullRequest {
+        PullRequest {
+            number: self.pull_request_id,
+            title: self.title,
+            url,
+            head_owner: None,
+            head: branch_name(&self.source_ref_name).to_string(),
+            head_sha: self.last_merge_source_commit.map(|commit| commit.commit_id),
+            base: branch_name(&self.target_ref_name).to_string(),
+            body: self.description.unwrap_or_default(),
+            mergeable: match self.merge_status.as_deref() {
+                Some("succeeded") => Some(true),
+                Some("conflicts") => Some(false),
+                _ => None,
+            },
+            draft: self.is_draft,
+            labels: self.labels.into_iter().map(|label| label.name).collect(),
+            checks: None,
+            review: None,
+            created_at: self.creation_date,
+            diff_stat: None,
+        }
+    }
+}
+
+/// Returns the short name of a full branch name such as refs/heads/main.
+fn branch_name(name: &str) -> &str {
+    name.strip_prefix(HEADS).unwrap_or(name)
+}
+
+/// Splits the repository path of a git remote on host into the
+/// organization, project and repository names. It understands the HTTPS
+/// (ORG/PROJECT/_git/REPO) and SSH (v3/ORG/PROJECT/REPO) paths of
+/// dev.azure.com, those of the older ORG.visualstudio.com hosts, and the
+/// ORG/PROJECT/REPO given with --repo.
+pub fn parse_repo_path(host: &str, path: &str) -> Option<(String, String, String)> {
+    let mut parts: Vec<&str> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != "_git")
+        .collect();
+    if parts.first() == Some(&"v3") {
+        parts.remove(0);
+    }
+    if let Some(org) = host.strip_suffix(".visualstudio.com") {
+        let org = org.split('.').next_back().unwrap_or(org);
+        if parts.first() == Some(&"DefaultCollection") {
+            parts.remove(0);
+        }
+        if parts.len() == 2 {
+            parts.insert(0, org);
+        }
+    }
+    match parts[..] {
+        [org, project, repo] => Some((org.to_string(), project.to_string(), repo.to_string())),
+        _ => None,
+    }
+}
+
+/// Returns the URL of the organization org, which the API lives under.
+pub fn api_url(host: &str, org: &str) -> String {
+    if host.ends_with("visualstudio.com") {
+        format!("https://{}.visualstudio.com", org)
+    } else {
+        format!("https://dev.azure.com/{}", org)
+    }
+}
+
+/// AzureClient talks to the REST API of Azure Repos, in Azure DevOps
+/// Services or Server, authenticating with the personal access token found
+/// in AZURE_DEVOPS_TOKEN, AZURE_DEVOPS_EXT_PAT or, in pipelines,
+/// SYSTEM_ACCESSTOKEN.
+pub struct AzureClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    pacer: Pacer,
+    auth: String,
+    api_url: String,
+    project: String,
+    repo: String,
+}
+
+impl AzureClient {
+    pub fn new(
+        api_url: String,
+        project: String,
+        repo: String,
+        retry: RetryPolicy,
+    ) -> Result<Self, JjstackError> {
+        let token = env::var("AZURE_DEVOPS_TOKEN")
+            .or_else(|_| env::var("AZURE_DEVOPS_EXT_PAT"))
+            .or_else(|_| env::var("SYSTEM_ACCESSTOKEN"))
+            .map_err(|_| {
+                JjstackError::ConfigError(
+                    "missing Azure DevOps token: set AZURE_DEVOPS_TOKEN".to_string(),
+                )
+            })?;
+        Ok(AzureClient {
+            client: http::new_client(),
+            retry,
+            pacer: Pacer::new(retry.write_interval),
+            // Personal access tokens go in the password of basic
+            // authentication, with no user name.
+            auth: format!("Basic {}", STANDARD.encode(format!(":{}", token))),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            project: project.replace(' ', "%20"),
+            repo: repo.replace(' ', "%20"),
+        })
+    }
+
+    fn repo_url(&self) -> String {
+        format!(
+            "{}/{}/_apis/git/repositories/{}",
+            self.api_url, self.project, self.repo
+        )
+    }
+
+    fn pull_url(&self, number: i32) -> String {
+        format!("{}/pullrequests/{}", self.repo_url(), number)
+    }
+
+    fn web_url(&self, number: i32) -> String {
+        format!(
+            "{}/{}/_git/{}/pullrequest/{}",
+            self.api_url, self.project, self.repo, number
+        )
+    }
+
+    /// Appends the API version to url, which every request must carry.
+    fn versioned(url: &str) -> String {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}api-version={}", url, separator, API_VERSION)
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, JjstackError> {
+        let url = Self::versioned(url);
+        http::send(&self.retry, &format!("GET {}", url), || {
+            self.client.get(&url).header("Authorization", &self.auth)
+        })
+        .await
+    }
+
+    async fn send_json(
+        &self,
+        method: &str,
+        url: &str,
+        data: serde_json::Value,
+    ) -> Result<(), JjstackError> {
+        let url = Self::versioned(url);
+        http::send_paced(
+            &self.retry,
+            &self.pacer,
+            &format!("{} {}", method, url),
+            || {
+                let req = match method {
+                    "POST" => self.client.post(&url),
+                    _ => self.client.patch(&url),
+                };
+                req.header("Authorization", &self.auth).json(&data)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, url: &str) -> Result<(), JjstackError> {
+        let url = Self::versioned(url);
+        http::send_paced(&self.retry, &self.pacer, &format!("DELETE {}", url), || {
+            self.client.delete(&url).header("Authorization", &self.auth)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Lists the PRs matching the search criteria in query, 100 at a time.
+    async fn list_prs(&self, query: &str) -> Result<Vec<PullRequest>, JjstackError> {
+        let mut prs = Vec::new();
+        loop {
+            let url = format!(
+                "{}/pullrequests?{}&$top=100&$skip={}",
+                self.repo_url(),
+                query,
+                prs.len()
+            );
+            let page: List<AzurePullRequest> = self.get(&url).await?.json().await?;
+            let last = page.value.len() < 100;
+            prs.extend(page.value.into_iter().map(|pr| {
+                let url = self.web_url(pr.pull_request_id);
+                pr.into_pull_request(url)
+            }));
+            if last {
+                break;
+            }
+        }
+        Ok(prs)
+    }
+
+    async fn get_pr(&self, number: i32) -> Result<AzurePullRequest, JjstackError> {
+        Ok(self.get(&self.pull_url(number)).await?.json().await?)
+    }
+
+    async fn list_threads(&self, number: i32) -> Result<Vec<AzureThread>, JjstackError> {
+        let url = format!("{}/threads", self.pull_url(number));
+        let threads: List<AzureThread> = self.get(&url).await?.json().await?;
+        Ok(threads.value)
+    }
+}
+
+impl AsyncForge for AzureClient {
+    async fn list_open_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        self.list_prs("searchCriteria.status=active").await
+    }
+
+    async fn get_pr_body(&self, number: i32) -> Result<String, JjstackError> {
+        Ok(self.get_pr(number).await?.description.unwrap_or_default())
+    }
+
+    fn max_body_len(&self) -> Option<usize> {
+        Some(MAX_BODY_LEN)
+    }
+
+    async fn update_pr_body(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        self.send_json(
+            "PATCH",
+            &self.pull_url(number),
+            json!({ "description": body }),
+        )
+        .await
+    }
+
+    async fn update_pr_title(
+        &self,
+        number: i32,
+        title: String,
+        body: Option<String>,
+    ) -> Result<(), JjstackError> {
+        let mut data = json!({ "title": title });
+        if let Some(body) = body {
+            data["description"] = json!(body);
+        }
+        self.send_json("PATCH", &self.pull_url(number), data).await
+    }
+
+    async fn find_closed_pr(&self, head: &str) -> Result<Option<PullRequest>, JjstackError> {
+        let query = format!(
+            "searchCriteria.status=all&searchCriteria.sourceRefName={}{}",
+            HEADS, head
+        );
+        let url = format!("{}/pullrequests?{}&$top=10", self.repo_url(), query);
+        let prs: List<AzurePullRequest> = self.get(&url).await?.json().await?;
+        Ok(prs
+            .value
+            .into_iter()
+            .find(|pr| pr.status != "active")
+            .map(|pr| {
+                let url = self.web_url(pr.pull_request_id);
+                pr.into_pull_request(url)
+            }))
+    }
+
+    async fn list_closed_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        // Completed and abandoned PRs come back most recent first.
+        let url = format!(
+            "{}/pullrequests?searchCriteria.status=all&$top=100",
+            self.repo_url()
+        );
+        let prs: List<AzurePullRequest> = self.get(&url).await?.json().await?;
+        Ok(prs
+            .value
+            .into_iter()
+            .filter(|pr| pr.status != "active")
+            .take(50)
+            .map(|pr| {
+                let url = self.web_url(pr.pull_request_id);
+                pr.into_pull_request(url)
+            })
+            .collect())
+    }
+
+    async fn update_pr_base(&self, number: i32, base: String) -> Result<(), JjstackError> {
+        let data = json!({ "targetRefName": format!("{}{}", HEADS, base) });
+        self.send_json("PATCH", &self.pull_url(number), data).await
+    }
+
+    async fn branch_exists(&self, branch: &str) -> Result<bool, JjstackError> {
+        // The filter matches name prefixes, so the exact name is looked for
+        // among the results.
+        let url = format!("{}/refs?filter=heads/{}", self.repo_url(), branch);
+        let refs: List<AzureRef> = self.get(&url).await?.json().await?;
+        let name = format!("{}{}", HEADS, branch);
+        Ok(refs.value.iter().any(|r| r.name == name))
+    }
+
+    async fn default_branch(&self) -> Result<String, JjstackError> {
+        let repo: AzureRepository = self.get(&self.repo_url()).await?.json().await?;
+        let branch = repo
+            .default_branch
+            .ok_or_else(|| format!("{} has no default branch", self.repo))?;
+        Ok(branch_name(&branch).to_string())
+    }
+
+    /// Comments are the threads of the PR, each known by its id and the
+    /// text of its first comment.
+    async fn list_pr_comments(&self, number: i32) -> Result<Vec<Comment>, JjstackError> {
+        Ok(self
+            .list_threads(number)
+            .await?
+            .into_iter()
+            .filter_map(|thread| {
+                let comment = thread.comments.into_iter().next()?;
+                if comment.comment_type.as_deref() == Some("system") {
+                    return None;
+                }
+                Some(Comment {
+                    id: thread.id,
+                    body: comment.content.unwrap_or_default(),
+                })
+            })
+            .collect())
+    }
+
+    async fn create_pr_comment(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        // The thread is created closed, so that it does not hold up branch
+        // policies requiring comments to be resolved.
+        let url = format!("{}/threads", self.pull_url(number));
+        let data = json!({
+            "comments": [{ "parentCommentId": 0, "content": body, "commentType": "text" }],
+            "status": "closed",
+        });
+        self.send_json("POST", &url, data).await
+    }
+
+    async fn update_pr_comment(
+        &self,
+        number: i32,
+        id: u64,
+        body: String,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/threads/{}", self.pull_url(number), id);
+        let thread: AzureThread = self.get(&url).await?.json().await?;
+        let comment = thread
+            .comments
+            .first()
+            .ok_or_else(|| format!("thread {} of PR #{} has no comments", id, number))?;
+        let url = format!("{}/comments/{}", url, comment.id);
+        self.send_json("PATCH", &url, json!({ "content": body }))
+            .await
+    }
+
+    async fn delete_pr_comment(&self, number: i32, id: u64) -> Result<(), JjstackError> {
+        let url = format!("{}/threads/{}", self.pull_url(number), id);
+        let thread: AzureThread = self.get(&url).await?.json().await?;
+        for comment in &thread.comments {
+            self.delete(&format!("{}/comments/{}", url, comment.id))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn set_pr_labels(&self, number: i32, labels: Vec<String>) -> Result<(), JjstackError> {
+        let url = format!("{}/labels", self.pull_url(number));
+        let current: List<AzureLabel> = self.get(&url).await?.json().await?;
+        for label in current.value.iter().filter(|l| !labels.contains(&l.name)) {
+            // Names such as stack:1/4 do not fit in a path, unlike ids.
+            self.delete(&format!("{}/{}", url, label.id)).await?;
+        }
+        for label in labels
+            .iter()
+            .filter(|l| !current.value.iter().any(|c| c.name == **l))
+        {
+            self.send_json("POST", &url, json!({ "name": label }))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn list_requested_reviewers(&self, _number: i32) -> Result<Vec<String>, JjstackError> {
+        Err("reviewer propagation is not supported on Azure DevOps".into())
+    }
+
+    async fn request_reviewers(
+        &self,
+        _number: i32,
+        _reviewers: Vec<String>,
+    ) -> Result<(), JjstackError> {
+        Err("reviewer propagation is not supported on Azure DevOps".into())
+    }
+
+    async fn create_pr(
+        &self,
+        head: &str,
+        base: &str,
+        title: String,
+        body: String,
+        draft: bool,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/pullrequests", self.repo_url());
+        let data = json!({
+            "sourceRefName": format!("{}{}", HEADS, head),
+            "targetRefName": format!("{}{}", HEADS, base),
+            "title": title,
+            "description": body,
+            "isDraft": draft,
+        });
+        self.send_json("POST", &url, data).await
+    }
+
+    async fn merge_pr(&self, number: i32, method: MergeMethod) -> Result<(), JjstackError> {
+        let merge_strategy = match method {
+            MergeMethod::Merge => "noFastForward",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+        };
+        // Completing a PR requires the commit it was last evaluated at.
+        let commit = self
+            .get_pr(number)
+            .await?
+            .last_merge_source_commit
+            .ok_or_else(|| format!("PR #{} has no source commit", number))?;
+        let data = json!({
+            "status": "completed",
+            "lastMergeSourceCommit": { "commitId": commit.commit_id },
+            "completionOptions": { "mergeStrategy": merge_strategy },
+        });
+        self.send_json("PATCH", &self.pull_url(number), data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_repo_path_understands_dev_azure_and_visualstudio_remotes() {
+        let cases = [
+            ("ssh.dev.azure.com", "v3/org/project/repo"),
+            ("dev.azure.com", "org/project/_git/repo"),
+            ("org.visualstudio.com", "project/_git/repo"),
+            (
+                "org.visualstudio.com",
+                "DefaultCollection/project/_git/repo",
+            ),
+            ("vs-ssh.visualstudio.com", "v3/org/project/repo"),
+        ];
+        for (host, path) in cases {
+            assert_eq!(
+                parse_repo_path(host, path),
+                Some(("org".to_string(), "project".to_string(), "repo".to_string())),
+                "{}/{}",
+                host,
+                path
+            );
+        }
+        assert_eq!(parse_repo_path("dev.azure.com", "org/repo"), None);
+    }
+}
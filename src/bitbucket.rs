@@ -0,0 +1,395 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::JjstackError;
+use crate::forge::{AsyncForge, Comment, MergeMethod};
+use crate::http::{self, Pacer, RetryPolicy};
+use crate::PullRequest;
+
+/// Default endpoint of the Bitbucket Cloud REST API.
+pub const DEFAULT_API_URL: &str = "https://api.bitbucket.org/2.0";
+
+/// Page is a page of results of the Bitbucket API, linking to the next one.
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    #[serde(rename = "values")]
+    values: Vec<T>,
+    #[serde(rename = "next")]
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketPullRequest {
+    #[serde(rename = "id")]
+    id: i32,
+    #[serde(rename = "title")]
+    title: String,
+    #[serde(rename = "description", default)]
+    description: Option<String>,
+    #[serde(rename = "links")]
+    links: BitbucketLinks,
+    #[serde(rename = "source")]
+    source: BitbucketEndpoint,
+    #[serde(rename = "destination")]
+    destination: BitbucketEndpoint,
+    #[serde(rename = "draft", default)]
+    draft: bool,
+    #[serde(rename = "created_on", default)]
+    created_on: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketLinks {
+    #[serde(rename = "html")]
+    html: BitbucketLink,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketLink {
+    #[serde(rename = "href")]
+    href: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketEndpoint {
+    #[serde(rename = "branch")]
+    branch: BitbucketBranch,
+    #[serde(rename = "repository", default)]
+    repository: Option<BitbucketRepository>,
+    #[serde(rename = "commit", default)]
+    commit: Option<BitbucketCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketCommit {
+    #[serde(rename = "hash")]
+    hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketBranch {
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketRepository {
+    #[serde(rename = "full_name")]
+    full_name: String,
+    #[serde(rename = "mainbranch", default)]
+    mainbranch: Option<BitbucketBranch>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketComment {
+    #[serde(rename = "id")]
+    id: u64,
+    #[serde(rename = "content")]
+    content: BitbucketContent,
+}
+
+#[derive(Debug, Deserialize)]
+struct BitbucketContent {
+    #[serde(rename = "raw", default)]
+    raw: String,
+}
+
+impl BitbucketPullRequest {
+    fn into_pull_request(self) -> PullRequest {
+        PullRequest {
+            number: self.id,
+            title: self.title,
+            url: self.links.html.href,
+            head_owner: self
+                .source
+                .repository
+                .and_then(|repo| repo.full_name.split_once('/').map(|(o, _)| o.to_string())),
+            head: self.source.branch.name,
+            // Bitbucket abbreviates the commit hash.
+            head_sha: self.source.commit.map(|commit| commit.hash),
+            base: self.destination.branch.name,
+            body: self.description.unwrap_or_default(),
+            mergeable: None,
+            draft: self.draft,
+            labels: Vec::new(),
+            checks: None,
+            review: None,
+            created_at: self.created_on,
+            diff_stat: None,
+        }
+    }
+}
+
+/// BitbucketClient talks to the Bitbucket Cloud REST API (2.0),
+/// authenticating with the access token found in BITBUCKET_TOKEN.
+pub struct BitbucketClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    pacer: Pacer,
+    token: String,
+    api_url: String,
+    repo: String,
+}
+
+impl BitbucketClient {
+    pub fn new(api_url: String, repo: String, retry: RetryPolicy) -> Result<Self, JjstackError> {
+        let token = env::var("BITBUCKET_TOKEN").map_err(|_| {
+            JjstackError::ConfigError("missing Bitbucket token: set BITBUCKET_TOKEN".to_string())
+        })?;
+        Ok(BitbucketClient {
+            client: http::new_client(),
+            retry,
+            pacer: Pacer::new(retry.write_interval),
+            token,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            repo,
+        })
+    }
+
+    fn repo_url(&self) -> String {
+        format!("{}/repositories/{}", self.api_url, self.repo)
+    }
+
+    fn pull_url(&self, number: i32) -> String {
+        format!("{}/pullrequests/{}", self.repo_url(), number)
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, JjstackError> {
+        http::send(&self.retry, &format!("GET {}", url), || {
+            self.client
+                .get(url)
+                .header("Authorization", format!("Bearer {}", self.token))
+        })
+        .await
+    }
+
+    async fn send_json(
+        &self,
+        method: &str,
+        url: &str,
+        data: serde_json::Value,
+    ) -> Result<(), JjstackError> {
+        http::send_paced(
+            &self.retry,
+            &self.pacer,
+            &format!("{} {}", method, url),
+            || {
+                let req = match method {
+                    "POST" => self.client.post(url),
+                    _ => self.client.put(url),
+                };
+                req.header("Authorization", format!("Bearer {}", self.token))
+                    .json(&data)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn list_prs(&self, query: &str) -> Result<Vec<PullRequest>, JjstackError> {
+        let mut url = format!("{}/pullrequests?{}", self.repo_url(), query);
+        let mut prs = Vec::new();
+        loop {
+            let page: Page<BitbucketPullRequest> = self.get(&url).await?.json().await?;
+            prs.extend(
+                page.values
+                    .into_iter()
+                    .map(BitbucketPullRequest::into_pull_request),
+            );
+            match page.next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(prs)
+    }
+
+    async fn get_pr(&self, number: i32) -> Result<BitbucketPullRequest, JjstackError> {
+        Ok(self.get(&self.pull_url(number)).await?.json().await?)
+    }
+}
+
+impl AsyncForge for BitbucketClient {
+    async fn list_open_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        self.list_prs("state=OPEN&pagelen=50").await
+    }
+
+    async fn get_pr_body(&self, number: i32) -> Result<String, JjstackError> {
+        Ok(self.get_pr(number).await?.description.unwrap_or_default())
+    }
+
+    async fn update_pr_body(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        // Updates replace the whole pull request, so the title is sent back
+        // unchanged.
+        let title = self.get_pr(number).await?.title;
+        let data = json!({ "title": title, "description": body });
+        self.send_json("PUT", &self.pull_url(number), data).await
+    }
+
+    async fn update_pr_title(
+        &self,
+        number: i32,
+        title: String,
+        body: Option<String>,
+    ) -> Result<(), JjstackError> {
+        let body = match body {
+            Some(body) => body,
+            None => self.get_pr(number).await?.description.unwrap_or_default(),
+        };
+        let data = json!({ "title": title, "description": body });
+        self.send_json("PUT", &self.pull_url(number), data).await
+    }
+
+    async fn find_closed_pr(&self, head: &str) -> Result<Option<PullRequest>, JjstackError> {
+        let url = format!(
+            "{}/pullrequests?state=MERGED&state=DECLINED&sort=-updated_on&q=source.branch.name=\"{}\"",
+            self.repo_url(),
+            head
+        );
+        let page: Page<BitbucketPullRequest> = self.get(&url).await?.json().await?;
+        Ok(page
+            .values
+            .into_iter()
+            .next()
+            .map(BitbucketPullRequest::into_pull_request))
+    }
+
+    async fn list_closed_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let url = format!(
+            "{}/pullrequests?state=MERGED&state=DECLINED&sort=-updated_on&pagelen=50",
+            self.repo_url()
+        );
+        let page: Page<BitbucketPullRequest> = self.get(&url).await?.json().await?;
+        Ok(page
+            .values
+            .into_iter()
+            .map(BitbucketPullRequest::into_pull_request)
+            .collect())
+    }
+
+    async fn update_pr_base(&self, number: i32, base: String) -> Result<(), JjstackError> {
+        let title = self.get_pr(number).await?.title;
+        let data = json!({ "title": title, "destination": { "branch": { "name": base } } });
+        self.send_json("PUT", &self.pull_url(number), data).await
+    }
+
+    async fn branch_exists(&self, branch: &str) -> Result<bool, JjstackError> {
+        let url = format!("{}/refs/branches/{}", self.repo_url(), branch);
+        let resp = http::send_optional(&self.retry, &format!("GET {}", url), || {
+            self.client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", self.token))
+        })
+        .await?;
+        Ok(resp.is_some())
+    }
+
+    async fn default_branch(&self) -> Result<String, JjstackError> {
+        let repo: BitbucketRepository = self.get(&self.repo_url()).await?.json().await?;
+        let branch = repo
+            .mainbranch
+            .ok_or_else(|| format!("{} has no main branch", self.repo))?;
+        Ok(branch.name)
+    }
+
+    async fn list_pr_comments(&self, number: i32) -> Result<Vec<Comment>, JjstackError> {
+        let mut url = format!("{}/comments?pagelen=100", self.pull_url(number));
+        let mut comments = Vec::new();
+        loop {
+            let page: Page<BitbucketComment> = self.get(&url).await?.json().await?;
+            comments.extend(page.values.into_iter().map(|c| Comment {
+                id: c.id,
+                body: c.content.raw,
+            }));
+            match page.next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(comments)
+    }
+
+    async fn create_pr_comment(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        let url = format!("{}/comments", self.pull_url(number));
+        self.send_json("POST", &url, json!({ "content": { "raw": body } }))
+            .await
+    }
+
+    async fn update_pr_comment(
+        &self,
+        number: i32,
+        id: u64,
+        body: String,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/comments/{}", self.pull_url(number), id);
+        self.send_json("PUT", &url, json!({ "content": { "raw": body } }))
+            .await
+    }
+
+    async fn delete_pr_comment(&self, number: i32, id: u64) -> Result<(), JjstackError> {
+        let url = format!("{}/comments/{}", self.pull_url(number), id);
+        http::send_paced(&self.retry, &self.pacer, &format!("DELETE {}", url), || {
+            self.client
+                .delete(&url)
+                .header("Authorization", format!("Bearer {}", self.token))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn set_pr_labels(&self, _number: i32, _labels: Vec<String>) -> Result<(), JjstackError> {
+        Err("Bitbucket pull requests have no labels".into())
+    }
+
+    async fn list_requested_reviewers(&self, _number: i32) -> Result<Vec<String>, JjstackError> {
+        Err("reviewer propagation is not supported on Bitbucket".into())
+    }
+
+    async fn request_reviewers(
+        &self,
+        _number: i32,
+        _reviewers: Vec<String>,
+    ) -> Result<(), JjstackError> {
+        Err("reviewer propagation is not supported on Bitbucket".into())
+    }
+
+    async fn create_pr(
+        &self,
+        head: &str,
+        base: &str,
+        title: String,
+        body: String,
+        draft: bool,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/pullrequests", self.repo_url());
+        let data = json!({
+            "title": title,
+            "description": body,
+            "draft": draft,
+            "source": { "branch": { "name": head } },
+            "destination": { "branch": { "name": base } },
+        });
+        self.send_json("POST", &url, data).await
+    }
+
+    async fn merge_pr(&self, number: i32, method: MergeMethod) -> Result<(), JjstackError> {
+        let merge_strategy = match method {
+            MergeMethod::Merge => "merge_commit",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase_merge",
+        };
+        let url = format!("{}/merge", self.pull_url(number));
+        self.send_json("POST", &url, json!({ "merge_strategy": merge_strategy }))
+            .await
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashSet;
+
+use log::warn;
+use serde_json::json;
+
+use jjstack_core::config::Config;
+use jjstack_core::jj::{
+    add_stack_ids, create_bookmark, is_stack_bookmark, list_bookmarks, list_commits, Commit,
+};
+
+use crate::cli::BookmarkArgs;
+
+/// DEFAULT_NAME_TEMPLATE names bookmarks after the bookmark prefix and the
+/// slug of the change description.
+const DEFAULT_NAME_TEMPLATE: &str = "{{ prefix }}{{ slug }}";
+
+/// Longest slug taken from a change description.
+const SLUG_WIDTH: usize = 40;
+
+/// Creates a bookmark on each change of the revisions that has none,
+/// bottom-up, so that the stack has branches to push. The working-copy
+/// commit is left alone while it is empty and undescribed.
+pub fn run(args: BookmarkArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let revset = format!(
+        "({}) ~ bookmarks() ~ (empty() & description(exact:\"\"))",
+        args.revisions
+    );
+    let commits = list_commits(&revset)?;
+    if commits.is_empty() {
+        println!("no changes without a bookmark.");
+        return Ok(());
+    }
+    let template = config
+        .bookmark_name_template
+        .as_deref()
+        .unwrap_or(DEFAULT_NAME_TEMPLATE);
+    let mut taken: HashSet<String> = list_bookmarks(false)?.into_iter().map(|b| b.name).collect();
+    let mut created = Vec::new();
+    for commit in commits.iter().rev() {
+        let mut name = bookmark_name(template, commit, config)?;
+        if taken.contains(&name) {
+            name = format!("{}-{}", name, commit.change_id);
+        }
+        if !is_stack_bookmark(config, &name) {
+            warn!("{} does not match the bookmark filters", name);
+        }
+        if args.dry_run {
+            println!("{}: would create {}", commit.change_id, name);
+        } else {
+            create_bookmark(&name, &commit.change_id)?;
+            println!("{}: created {}", commit.change_id, name);
+            created.push(commit.change_id.to_string());
+        }
+        taken.insert(name);
+    }
+    if config.stack_ids.unwrap_or_default() && !created.is_empty() {
+        add_stack_ids(&created.join(" | "))?;
+    }
+    Ok(())
+}
+
+fn bookmark_name(
+    template: &str,
+    commit: &Commit,
+    config: &Config,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let slug = match slugify(&commit.description) {
+        slug if slug.is_empty() => commit.change_id.to_string(),
+        slug => slug,
+    };
+    let env = minijinja::Environment::new();
+    let name = env
+        .render_str(
+            template,
+            json!({
+                "prefix": config.bookmark_prefix.as_deref().unwrap_or_default(),
+                "slug": slug,
+                "change_id": commit.change_id,
+                "description": commit.description,
+            }),
+        )
+        .map_err(|e| format!("cannot render bookmark name template: {}", e))?;
+    Ok(name.trim().to_string())
+}
+
+/// Turns text into lowercase words of ASCII letters and digits joined by
+/// dashes, cut at a word boundary past SLUG_WIDTH.
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            if slug.len() >= SLUG_WIDTH {
+                break;
+            }
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
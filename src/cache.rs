@@ -0,0 +1,134 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Entry is a cached response, revalidated with its ETag.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub url: String,
+    pub etag: String,
+    pub body: String,
+    pub next: Option<String>,
+}
+
+/// Cache keeps API responses on disk between runs, so that unchanged
+/// resources can be revalidated with a conditional request instead of being
+/// downloaded again.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    /// Opens the cache in $XDG_CACHE_HOME/jjstack, or ~/.cache/jjstack.
+    pub fn open() -> Option<Self> {
+        let dir = match env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        Some(Cache::new(dir.join("jjstack")))
+    }
+
+    /// Names the file of url after its SHA-256, which, unlike the hashers of
+    /// the standard library, stays the same across Rust releases.
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:x}.json", Sha256::digest(url.as_bytes())))
+    }
+
+    /// Returns the entry cached for url, if any.
+    pub fn get(&self, url: &str) -> Option<Entry> {
+        let text = fs::read_to_string(self.path(url)).ok()?;
+        let entry: Entry = serde_json::from_str(&text).ok()?;
+        (entry.url == url).then_some(entry)
+    }
+
+    /// Stores entry, silently giving up when the cache is not writable: a
+    /// missing entry only costs a full request on the next run. The entry is
+    /// written aside and renamed into place, so that concurrent runs and
+    /// interrupted writes never leave a truncated entry behind. Responses may
+    /// come from private repositories, so only the user may read them.
+    pub fn put(&self, entry: &Entry) {
+        let Ok(text) = serde_json::to_string(entry) else {
+            return;
+        };
+        let mut dir = DirBuilder::new();
+        dir.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+        if dir.create(&self.dir).is_err() {
+            return;
+        }
+        // A cache made by an earlier version may still be open to others.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o700));
+        }
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let temp = self.dir.join(format!(
+            ".{}.{}.tmp",
+            process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        if write_private(&temp, &text).is_err() || fs::rename(&temp, self.path(&entry.url)).is_err()
+        {
+            let _ = fs::remove_file(&temp);
+        }
+    }
+}
+
+/// Writes text to a new file at path that only the user may read.
+fn write_private(path: &Path, text: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_replaces_entries_in_a_private_directory() {
+        let dir = env::temp_dir().join(format!("jjstack-cache-test-{}", process::id()));
+        let cache = Cache::new(dir.clone());
+        for body in ["old", "new"] {
+            cache.put(&Entry {
+                url: "https://example.com/a".to_string(),
+                etag: "\"1\"".to_string(),
+                body: body.to_string(),
+                next: None,
+            });
+        }
+        assert_eq!(cache.get("https://example.com/a").unwrap().body, "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,740 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use jjstack_core::config::{
+    AnnotateMode, BookmarkRegex, Config, ForeignStacks, NavFormat, NavOrder, NavStyle, Placement,
+    Topology,
+};
+use jjstack_core::forge::{ForgeKind, MergeMethod};
+
+/// Simple helper to generate stacked PR messages.
+#[derive(Parser)]
+#[command(
+    name = "jjstack",
+    version,
+    about,
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    /// Log more details to stderr: -v for each PR decision, -vv for every
+    /// command and API call with its timing.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only log errors.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Forge hosting the repository (github, gitlab, gitea, bitbucket or
+    /// azure);
+    /// detected from the git remote when omitted.
+    #[arg(long, global = true)]
+    pub forge: Option<ForgeKind>,
+
+    /// Git remote whose URL names the repository holding the PRs; defaults
+    /// to upstream, then origin.
+    #[arg(long, global = true)]
+    pub remote: Option<String>,
+
+    /// Repository holding the PRs, as owner/name; overrides the one named by
+    /// the git remote or `gh repo set-default`. Defaults to $JJSTACK_REPO.
+    #[arg(long, global = true)]
+    pub repo: Option<String>,
+
+    /// Trunk branch, never treated as a stack member; defaults to the
+    /// repository's default branch on the forge.
+    #[arg(long, global = true)]
+    pub trunk: Option<String>,
+
+    /// Order the PRs of each stack by their base branches on the forge, or
+    /// by the jj commit graph.
+    #[arg(long, global = true, value_enum)]
+    pub topology: Option<Topology>,
+
+    /// Only consider bookmarks starting with this prefix.
+    #[arg(long, global = true, alias = "prefix")]
+    pub bookmark_prefix: Option<String>,
+
+    /// Only consider bookmarks matching this glob, where * matches any run of
+    /// characters and ? a single one (e.g. "uc/*").
+    #[arg(long, global = true)]
+    pub bookmark_glob: Option<String>,
+
+    /// Only consider bookmarks matching this regular expression anywhere in
+    /// their name.
+    #[arg(long, global = true)]
+    pub bookmark_regex: Option<BookmarkRegex>,
+
+    /// Never consider bookmarks matching this glob, such as release
+    /// branches; repeat for several globs, added to those of the
+    /// configuration.
+    #[arg(long, global = true, value_name = "GLOB")]
+    pub exclude_bookmark: Vec<String>,
+
+    /// How entries of the navigation block are rendered.
+    #[arg(long, global = true, value_enum, alias = "style")]
+    pub nav_style: Option<NavStyle>,
+
+    /// How the entries of the navigation block are listed and the current PR
+    /// marked.
+    #[arg(long = "format", global = true, value_enum)]
+    pub nav_format: Option<NavFormat>,
+
+    /// In which direction the entries of the navigation block are listed.
+    #[arg(long = "order", global = true, value_enum)]
+    pub nav_order: Option<NavOrder>,
+
+    /// Where the navigation block goes in the PR description.
+    #[arg(long, global = true, value_enum)]
+    pub placement: Option<Placement>,
+
+    /// Heading line the navigation block follows with --placement
+    /// after-heading (e.g. "## Stack").
+    #[arg(long, global = true)]
+    pub placement_heading: Option<String>,
+
+    /// What to do with the stack sections Graphite or spr wrote in a PR
+    /// description.
+    #[arg(long, global = true, value_enum)]
+    pub foreign_stacks: Option<ForeignStacks>,
+
+    /// Show the CI status of each PR in the navigation block.
+    #[arg(long, global = true)]
+    pub ci_status: bool,
+
+    /// Show the lines added and removed, and the commits, of each PR in the
+    /// navigation block.
+    #[arg(long, global = true)]
+    pub diff_stats: bool,
+
+    /// Only use ASCII in the terminal and in the navigation block, with no
+    /// emoji or arrows.
+    #[arg(long, global = true)]
+    pub no_emoji: bool,
+
+    /// Add "Depends on #N" and "Required by #M" lines to the navigation
+    /// block, naming the neighbors of each PR.
+    #[arg(long, global = true)]
+    pub neighbors: bool,
+
+    /// Where the navigation block is written: the PR description or a
+    /// dedicated comment.
+    #[arg(long, global = true, value_enum)]
+    pub mode: Option<AnnotateMode>,
+
+    /// Render the navigation block with this minijinja template instead of a
+    /// built-in style.
+    #[arg(long, global = true)]
+    pub template_file: Option<PathBuf>,
+
+    /// How many times an API call is attempted before giving up.
+    #[arg(long, global = true)]
+    pub retry_attempts: Option<u32>,
+
+    /// Delay before the first retry of a failed API call, doubled after each
+    /// attempt.
+    #[arg(long, global = true)]
+    pub retry_backoff_ms: Option<u64>,
+
+    /// Minimum delay between API calls that change data (1000 by default),
+    /// to avoid tripping secondary rate limits.
+    #[arg(long, global = true)]
+    pub write_interval_ms: Option<u64>,
+
+    /// Host of the forge, for GitHub Enterprise Server or self-hosted GitLab
+    /// (e.g. github.example.com).
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+
+    /// Owner of the fork the bookmarks are pushed to; PR heads are matched as
+    /// OWNER:BRANCH instead of against the base repository.
+    #[arg(long, global = true)]
+    pub fork_owner: Option<String>,
+
+    /// Label each PR with its position in the stack (e.g. stack:2/4).
+    #[arg(long, global = true)]
+    pub labels: bool,
+
+    /// After editing a PR, comment on it with a summary of the changes, so
+    /// that each automated edit can be traced.
+    #[arg(long, global = true)]
+    pub audit: bool,
+
+    /// Prefix of the stack position labels; labels starting with it are
+    /// removed when they go stale.
+    #[arg(long, global = true)]
+    pub label_prefix: Option<String>,
+
+    /// Record a JJStack-Id trailer in the description of each pushed
+    /// bookmark's commit, and use it to recognize renamed bookmarks.
+    #[arg(long, global = true)]
+    pub stack_ids: bool,
+
+    /// Also take the bookmarks only found on the remote, which no local
+    /// bookmark tracks, for stack members.
+    #[arg(long, global = true)]
+    pub remote_bookmarks: bool,
+
+    /// Refuse to retarget a PR onto a base whose branch protection requires
+    /// status checks that never reported on the PR, instead of warning.
+    #[arg(long, global = true)]
+    pub strict_protection: bool,
+
+    /// Prefix PR titles with their position in the stack (e.g. [2/4]).
+    #[arg(long, global = true)]
+    pub title_prefix: bool,
+
+    /// Ask the reviewers of the bottom PR of each stack, or the configured
+    /// reviewers when it has none, to review the rest of the stack too.
+    #[arg(long, global = true)]
+    pub propagate_reviewers: bool,
+
+    /// User or ORG/TEAM to request a review from when the bottom PR of a
+    /// stack has no reviewers (repeatable).
+    #[arg(long, global = true, value_name = "USER|ORG/TEAM")]
+    pub reviewer: Vec<String>,
+
+    /// Leave draft PRs out of the stacks entirely.
+    #[arg(long, global = true, conflicts_with = "include_drafts")]
+    pub skip_drafts: bool,
+
+    /// Process draft PRs like any other, overriding skip_drafts from the
+    /// configuration.
+    #[arg(long, global = true)]
+    pub include_drafts: bool,
+
+    /// Do not read or write the on-disk cache of API responses.
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    #[command(flatten)]
+    pub annotate: AnnotateArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Add or refresh the stack navigation block in each PR description
+    /// (default).
+    Annotate(AnnotateArgs),
+    /// List the stacks built from local bookmarks and open PRs, and report
+    /// PRs whose base does not match the jj commit graph.
+    Status,
+    /// Show each stack with the jj commits of its PRs, like 'jj log'
+    /// annotated with the PR numbers and states.
+    Log(LogArgs),
+    /// Report the health of the stacks: their number and depth, the oldest
+    /// PR, and the PRs missing a navigation block or with a stale base.
+    Stats(StatsArgs),
+    /// Create a bookmark, named after its description, on each change of the
+    /// current stack that has none.
+    Bookmark(BookmarkArgs),
+    /// Push every bookmark in the stack containing the working-copy commit.
+    Push(PushArgs),
+    /// Fold the working-copy fixes into the commits of the current stack
+    /// that they touch, then push the stack and refresh its PRs.
+    Absorb(AbsorbArgs),
+    /// Browse the stacks interactively.
+    Tui,
+    /// Land a stack bottom-up: merge the bottom PR once its checks pass,
+    /// retarget the next PRs onto the trunk and refresh the navigation.
+    Merge(MergeArgs),
+    /// Record the navigation changes a dry run would make in a plan file.
+    Plan(PlanArgs),
+    /// Make the changes recorded by 'jjstack plan', unless a PR was edited
+    /// since.
+    Apply(ApplyArgs),
+    /// Strip the navigation blocks jjstack added, to stop using it.
+    Unstack(UnstackArgs),
+    /// Open the PR of the current bookmark in the browser.
+    Open(OpenArgs),
+    /// Open a PR for a bookmark, based on the nearest bookmark among its
+    /// ancestors with an open PR, or on the trunk.
+    Create(CreateArgs),
+    /// Print the completion script of a shell, which also completes bookmark
+    /// names from jj (e.g. 'source <(jjstack completions bash)').
+    Completions(CompletionsArgs),
+    /// Check that jj, the repository, the forge credentials and the
+    /// bookmarks are set up, suggesting fixes for the problems found.
+    Doctor,
+    /// Detect the repository settings, confirm them interactively and write
+    /// .jjstack.toml.
+    Init(InitArgs),
+    /// Fetch, then rebase the stacks onto the updated trunk.
+    Rebase(RebaseArgs),
+    /// Fetch, rebase the stacks onto the updated trunk, push them and
+    /// refresh their PRs; without --apply, report what each step would do.
+    Sync(SyncArgs),
+    /// Keep running, and refresh the PRs whenever the bookmarks are moved,
+    /// created, deleted, fetched or pushed.
+    Watch(WatchArgs),
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    /// File the plan is written to.
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Only process the stack containing the working-copy commit.
+    #[arg(long)]
+    pub current: bool,
+
+    /// Only process the stack containing this bookmark.
+    #[arg(long, value_name = "BOOKMARK")]
+    pub bookmark: Option<String>,
+
+    /// Also plan stripping the navigation block from merged and closed PRs.
+    #[arg(long)]
+    pub prune_merged: bool,
+
+    /// Only plan changes to these PRs, given by number or bookmark name.
+    #[arg(long, value_name = "PR|BOOKMARK")]
+    pub only: Vec<String>,
+
+    /// Show a unified diff of each PR description.
+    #[arg(long)]
+    pub diff: bool,
+
+    /// Number of PRs to read concurrently.
+    #[arg(short, long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+impl PlanArgs {
+    /// Returns the arguments of the dry run the plan is made from.
+    pub fn into_annotate_args(self) -> AnnotateArgs {
+        AnnotateArgs {
+            apply: false,
+            assume_yes: false,
+            check: false,
+            json: false,
+            restack: false,
+            jobs: self.jobs,
+            current: self.current,
+            bookmark: self.bookmark,
+            diff: self.diff,
+            prune_merged: self.prune_merged,
+            only: self.only,
+            fail_fast: false,
+            github_event: None,
+            no_jj: false,
+            fix_bases: false,
+            rollback: None,
+            stack_root: None,
+            between: None,
+            stack_name: None,
+            push: false,
+            message: None,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ApplyArgs {
+    /// Plan file written by 'jjstack plan'.
+    #[arg(long)]
+    pub plan: PathBuf,
+
+    /// Number of PRs to update concurrently.
+    #[arg(short, long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+#[derive(Args)]
+pub struct BookmarkArgs {
+    /// Changes to bookmark.
+    #[arg(short, long, default_value = "trunk()..@")]
+    pub revisions: String,
+
+    /// Print the bookmarks that would be created without creating them.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// Shell to complete commands in.
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Print the report as JSON.
+    #[arg(long)]
+    pub json: bool,
+
+    /// Number of PRs to read concurrently.
+    #[arg(short, long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Only show the stack containing the working-copy commit.
+    #[arg(long)]
+    pub current: bool,
+}
+
+#[derive(Args)]
+pub struct UnstackArgs {
+    /// Scan every open PR of the repository, not only those of local
+    /// bookmarks.
+    #[arg(long)]
+    pub all: bool,
+
+    /// Also scan the recently merged and closed PRs.
+    #[arg(long)]
+    pub include_closed: bool,
+
+    /// Remove the navigation blocks instead of printing which would go.
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Number of PRs to update concurrently.
+    #[arg(short, long, default_value_t = 4)]
+    pub jobs: usize,
+}
+
+#[derive(Args)]
+pub struct CreateArgs {
+    /// Bookmark the PR is opened for; its commit description gives the
+    /// title of the PR and the start of its description.
+    #[arg(value_name = "BOOKMARK")]
+    pub head: String,
+
+    /// Open the PR as a draft.
+    #[arg(long)]
+    pub draft: bool,
+
+    /// PR template of .github/PULL_REQUEST_TEMPLATE/ the description is
+    /// filled with, when there are several.
+    #[arg(long, value_name = "NAME")]
+    pub template: Option<String>,
+
+    /// With --apply, the PR is opened and then the navigation of its stack
+    /// refreshed.
+    #[command(flatten)]
+    pub annotate_args: AnnotateArgs,
+}
+
+#[derive(Args)]
+pub struct OpenArgs {
+    /// Open the PR of this bookmark instead of the one closest to the
+    /// working-copy commit.
+    #[arg(long, value_name = "BOOKMARK")]
+    pub bookmark: Option<String>,
+
+    /// Open every PR in the stack instead of a single one.
+    #[arg(long)]
+    pub all: bool,
+
+    /// Print the URLs instead of opening them.
+    #[arg(long)]
+    pub print: bool,
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    /// Accept the detected settings without asking.
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// Overwrite an existing .jjstack.toml.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct RebaseArgs {
+    /// Only rebase the stack containing the working-copy commit.
+    #[arg(long)]
+    pub current: bool,
+
+    /// Do not run 'jj git fetch' first.
+    #[arg(long)]
+    pub no_fetch: bool,
+
+    /// Push the rebased bookmarks when there are no conflicts.
+    #[arg(long)]
+    pub push: bool,
+}
+
+#[derive(Args)]
+pub struct SyncArgs {
+    /// Do not run 'jj git fetch' first.
+    #[arg(long)]
+    pub no_fetch: bool,
+
+    #[command(flatten)]
+    pub annotate_args: AnnotateArgs,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Seconds between checks of the bookmarks.
+    #[arg(long, default_value_t = 5)]
+    pub interval: u64,
+
+    /// Seconds the bookmarks must stay put before the PRs are refreshed, so
+    /// that a rebase followed by a push is handled once.
+    #[arg(long, default_value_t = 3)]
+    pub debounce: u64,
+
+    #[command(flatten)]
+    pub annotate_args: AnnotateArgs,
+}
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// Land the stack containing this bookmark instead of the one containing
+    /// the working-copy commit.
+    #[arg(long, value_name = "BOOKMARK")]
+    pub bookmark: Option<String>,
+
+    /// Stop after merging this PR.
+    #[arg(long)]
+    pub until: Option<i32>,
+
+    /// How each PR is merged; a merge queue merges the way it is set up to.
+    #[arg(long, value_enum, default_value_t)]
+    pub method: MergeMethod,
+
+    /// Seconds between polls while waiting for checks.
+    #[arg(long, default_value_t = 30)]
+    pub poll_interval: u64,
+
+    /// Seconds to wait for the checks of a PR, or for it to land through
+    /// the merge queue, before giving up.
+    #[arg(long, default_value_t = 3600)]
+    pub timeout: u64,
+}
+
+#[derive(Args)]
+pub struct PushArgs {
+    /// Run the annotate step after pushing.
+    #[arg(long)]
+    pub annotate: bool,
+
+    #[command(flatten)]
+    pub annotate_args: AnnotateArgs,
+}
+
+#[derive(Args)]
+pub struct AbsorbArgs {
+    /// Revision holding the fixes.
+    #[arg(long, default_value = "@")]
+    pub from: String,
+
+    /// Squash all the fixes into this revision instead of letting
+    /// 'jj absorb' spread them over the stack.
+    #[arg(long, value_name = "BOOKMARK")]
+    pub into: Option<String>,
+
+    #[command(flatten)]
+    pub annotate_args: AnnotateArgs,
+}
+
+#[derive(Args, Clone)]
+pub struct AnnotateArgs {
+    /// Update the PRs instead of printing what would change.
+    #[arg(long)]
+    pub apply: bool,
+
+    /// Update the PRs without asking for confirmation on the terminal.
+    #[arg(short = 'y', long)]
+    pub assume_yes: bool,
+
+    /// Exit with an error when any PR would change, for use in CI.
+    #[arg(long, conflicts_with = "apply")]
+    pub check: bool,
+
+    /// Print a JSON document describing the stacks and planned changes.
+    #[arg(long)]
+    pub json: bool,
+
+    /// Retarget PRs whose parent was merged or closed onto the parent's base.
+    #[arg(long)]
+    pub restack: bool,
+
+    /// Number of stacks to update concurrently; the PRs of a stack are
+    /// updated in order.
+    #[arg(short, long, default_value_t = 4)]
+    pub jobs: usize,
+
+    /// Only process the stack containing the working-copy commit.
+    #[arg(long)]
+    pub current: bool,
+
+    /// Only process the stack containing this bookmark.
+    #[arg(long, value_name = "BOOKMARK")]
+    pub bookmark: Option<String>,
+
+    /// In a dry run, show a unified diff of each PR description instead of
+    /// only the navigation block.
+    #[arg(long)]
+    pub diff: bool,
+
+    /// Also strip the navigation block from merged and closed PRs that
+    /// still carry one.
+    #[arg(long)]
+    pub prune_merged: bool,
+
+    /// Only update these PRs, given by number or bookmark name; the rest of
+    /// their stacks is still read to render the navigation.
+    #[arg(long, value_name = "PR|BOOKMARK")]
+    pub only: Vec<String>,
+
+    /// Stop at the first PR that cannot be updated instead of carrying on
+    /// and exiting with code 3.
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Update the stack of the PR in this GitHub `pull_request` event
+    /// payload (such as $GITHUB_EVENT_PATH) using only the forge, without a
+    /// local jj repository.
+    #[arg(long, value_name = "PATH")]
+    pub github_event: Option<PathBuf>,
+
+    /// Build stacks from the open PRs alone, without a local jj repository:
+    /// every PR based on another open PR's head is stacked on it.
+    #[arg(long, conflicts_with = "current")]
+    pub no_jj: bool,
+
+    /// Restore the titles and descriptions an earlier --apply run replaced,
+    /// given the run id it printed.
+    #[arg(long, value_name = "RUN_ID", conflicts_with = "apply")]
+    pub rollback: Option<String>,
+
+    /// Retarget PRs whose base branch was deleted onto their nearest
+    /// remaining ancestor: the base of the closed PR of that branch, or the
+    /// trunk.
+    #[arg(long)]
+    pub fix_bases: bool,
+
+    /// Only process the whole stack of this bookmark, from its root down to
+    /// every leaf, connected through PR bases or the jj commit graph.
+    #[arg(long, value_name = "BOOKMARK", conflicts_with_all = ["bookmark", "current", "github_event"])]
+    pub stack_root: Option<String>,
+
+    /// Only annotate the part of a stack from BASE up to TOP, each a bookmark
+    /// or a PR number, as if it were the whole stack; the navigation notes
+    /// how many PRs were left out under and over it.
+    #[arg(long, value_name = "BASE..TOP", value_parser = parse_range, conflicts_with = "restack")]
+    pub between: Option<(String, String)>,
+
+    /// Name the stack of --bookmark, which then heads its navigation block;
+    /// --apply records the name in a JJStack-Name trailer of the bookmark's
+    /// commit.
+    #[arg(long, value_name = "NAME", requires = "bookmark")]
+    pub stack_name: Option<String>,
+
+    /// With --apply, push the bookmarks whose local commit is ahead of the
+    /// head of their PR before annotating the stacks.
+    #[arg(long)]
+    pub push: bool,
+
+    /// Note added to the comment summarizing the changes made to each PR,
+    /// such as a ticket number; implies --audit.
+    #[arg(short, long)]
+    pub message: Option<String>,
+}
+
+impl Cli {
+    /// Sets up logging to stderr at the level picked by --verbose and
+    /// --quiet, unless RUST_LOG says otherwise.
+    pub fn init_logging(&self) {
+        let level = match (self.quiet, self.verbose) {
+            (true, _) => "error",
+            (false, 0) => "warn",
+            (false, 1) => "info",
+            (false, 2) => "debug",
+            (false, _) => "trace",
+        };
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level))
+            .format_timestamp(None)
+            .init();
+    }
+
+    /// Returns the configuration overridden by the flags given on the
+    /// command line.
+    pub fn override_config(&self, config: Config) -> Config {
+        config.merge(Config {
+            trunk: self.trunk.clone(),
+            nav_style: self.nav_style,
+            nav_format: self.nav_format,
+            nav_order: self.nav_order,
+            mode: self.mode,
+            placement: self.placement,
+            placement_heading: self.placement_heading.clone(),
+            foreign_stacks: self.foreign_stacks,
+            bookmark_prefix: self.bookmark_prefix.clone(),
+            bookmark_glob: self.bookmark_glob.clone(),
+            exclude_bookmarks: (!self.exclude_bookmark.is_empty())
+                .then(|| self.exclude_bookmark.clone()),
+            bookmark_regex: self.bookmark_regex.clone(),
+            push_bookmark_prefix: None,
+            bookmark_name_template: None,
+            forge: self.forge,
+            template_file: self.template_file.clone(),
+            retry_attempts: self.retry_attempts,
+            retry_backoff_ms: self.retry_backoff_ms,
+            write_interval_ms: self.write_interval_ms,
+            host: self.host.clone(),
+            api_url: None,
+            web_url: None,
+            github_app_id: None,
+            github_app_installation_id: None,
+            github_app_key_file: None,
+            fork_owner: self.fork_owner.clone(),
+            cache: self.no_cache.then_some(false),
+            ci_status: self.ci_status.then_some(true),
+            diff_stats: self.diff_stats.then_some(true),
+            neighbors: self.neighbors.then_some(true),
+            emoji: self.no_emoji.then_some(false),
+            labels: self.labels.then_some(true),
+            audit: self.audit.then_some(true),
+            label_prefix: self.label_prefix.clone(),
+            title_prefix: self.title_prefix.then_some(true),
+            propagate_reviewers: self.propagate_reviewers.then_some(true),
+            reviewers: (!self.reviewer.is_empty()).then(|| self.reviewer.clone()),
+            remote: self.remote.clone(),
+            repo: self.repo.clone().or_else(|| env::var("JJSTACK_REPO").ok()),
+            stack_ids: self.stack_ids.then_some(true),
+            remote_bookmarks: self.remote_bookmarks.then_some(true),
+            strict_protection: self.strict_protection.then_some(true),
+            stack_names: None,
+            jj_bin: None,
+            gh_bin: None,
+            topology: self.topology,
+            workspace: None,
+            skip_drafts: match (self.skip_drafts, self.include_drafts) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+        })
+    }
+}
+
+fn parse_range(range: &str) -> Result<(String, String), String> {
+    match range.split_once("..") {
+        Some((base, top)) if !base.is_empty() && !top.is_empty() => {
+            Ok((base.to_string(), top.to_string()))
+        }
+        _ => Err(format!("expected BASE..TOP, got {:?}", range)),
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::rc::Rc;
+use std::string::FromUtf8Error;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::debug;
+
+/// CommandRunner runs the external commands (jj, gh) jjstack depends on.
+/// The system runner is used unless another one is installed with
+/// `with_runner`, which lets tests fake the commands.
+pub trait CommandRunner {
+    /// Runs cmd to completion, capturing its output.
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+
+    /// Runs cmd to completion with inherited stdio.
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus>;
+}
+
+/// SystemRunner spawns the commands for real.
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.output()
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        cmd.status()
+    }
+}
+
+thread_local! {
+    static RUNNER: RefCell<Option<Rc<dyn CommandRunner>>> = const { RefCell::new(None) };
+}
+
+/// Runs f with runner in place of the system runner on the current thread.
+pub fn with_runner<T>(runner: Rc<dyn CommandRunner>, f: impl FnOnce() -> T) -> T {
+    let previous = RUNNER.with(|r| r.replace(Some(runner)));
+    let result = f();
+    RUNNER.with(|r| r.replace(previous));
+    result
+}
+
+fn runner() -> Rc<dyn CommandRunner> {
+    RUNNER
+        .with(|r| r.borrow().clone())
+        .unwrap_or_else(|| Rc::new(SystemRunner))
+}
+
+/// Executables configured in place of the programs of the same name.
+static PROGRAMS: Mutex<Vec<(String, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Runs path whenever the program name is asked for.
+pub fn set_program(name: &str, path: PathBuf) {
+    let mut programs = PROGRAMS.lock().unwrap();
+    programs.retain(|(n, _)| n != name);
+    programs.push((name.to_string(), path));
+}
+
+/// Creates a Command for the program name, which is resolved to an
+/// executable up front: the one configured with `set_program` or else the
+/// first match in PATH, trying every PATHEXT extension on Windows. Fake
+/// runners get the bare name.
+pub fn new(name: &str) -> Command {
+    if RUNNER.with(|r| r.borrow().is_some()) {
+        return Command::new(name);
+    }
+    let configured = PROGRAMS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, path)| path.clone());
+    let program = configured
+        .or_else(|| find_in_path(name, &env::var_os("PATH")?, &path_extensions()))
+        .unwrap_or_else(|| PathBuf::from(name));
+    Command::new(program)
+}
+
+fn path_extensions() -> Vec<String> {
+    if !cfg!(windows) {
+        return Vec::new();
+    }
+    let pathext = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    pathext
+        .split(';')
+        .filter(|ext| !ext.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Looks program up in the directories of path, the way a shell would,
+/// trying each of extensions when program has none.
+fn find_in_path(program: &str, path: &OsStr, extensions: &[String]) -> Option<PathBuf> {
+    let has_extension = Path::new(program).extension().is_some();
+    env::split_paths(path).find_map(|dir| {
+        let candidate = dir.join(program);
+        if (extensions.is_empty() || has_extension) && candidate.is_file() {
+            return Some(candidate);
+        }
+        extensions
+            .iter()
+            .map(|ext| dir.join(format!("{}{}", program, ext)))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// Reads the standard output of a command as text, with Windows line
+/// endings turned into plain newlines.
+pub fn stdout(out: Output) -> Result<String, FromUtf8Error> {
+    let text = String::from_utf8(out.stdout)?;
+    if text.contains('\r') {
+        return Ok(text.replace("\r\n", "\n"));
+    }
+    Ok(text)
+}
+
+/// Runs cmd to completion, capturing its output, and logs how long it took.
+pub fn output(cmd: &mut Command) -> io::Result<Output> {
+    let start = Instant::now();
+    let out = runner().output(cmd);
+    match &out {
+        Ok(out) => debug!("{}: {} in {:?}", describe(cmd), out.status, start.elapsed()),
+        Err(e) => debug!("{}: {} after {:?}", describe(cmd), e, start.elapsed()),
+    }
+    out
+}
+
+/// Runs cmd to completion with inherited stdio, and logs how long it took.
+pub fn status(cmd: &mut Command) -> io::Result<ExitStatus> {
+    let start = Instant::now();
+    let status = runner().status(cmd);
+    match &status {
+        Ok(status) => debug!("{}: {} in {:?}", describe(cmd), status, start.elapsed()),
+        Err(e) => debug!("{}: {} after {:?}", describe(cmd), e, start.elapsed()),
+    }
+    status
+}
+
+/// Renders cmd as the command line it runs.
+pub fn describe(cmd: &Command) -> String {
+    let mut s = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        s.push(' ');
+        s.push_str(&arg.to_string_lossy());
+    }
+    s
+}
+
+/// MockRunner answers commands with canned output instead of running them,
+/// and records the command lines it was asked to run.
+#[derive(Default)]
+pub struct MockRunner {
+    responses: Vec<(String, Result<String, String>)>,
+    calls: RefCell<Vec<String>>,
+}
+
+impl MockRunner {
+    /// Answers commands whose command line starts with prefix with stdout
+    /// and a successful exit status.
+    pub fn ok(mut self, prefix: &str, stdout: &str) -> Self {
+        self.responses
+            .push((prefix.to_string(), Ok(stdout.to_string())));
+        self
+    }
+
+    /// Answers commands whose command line starts with prefix with stderr
+    /// and a failing exit status.
+    pub fn fail(mut self, prefix: &str, stderr: &str) -> Self {
+        self.responses
+            .push((prefix.to_string(), Err(stderr.to_string())));
+        self
+    }
+
+    /// Returns the command lines run so far.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.borrow().clone()
+    }
+
+    fn respond(&self, cmd: &Command) -> io::Result<Output> {
+        let line = describe(cmd);
+        self.calls.borrow_mut().push(line.clone());
+        let response = self
+            .responses
+            .iter()
+            .find(|(prefix, _)| line.starts_with(prefix.as_str()))
+            .map(|(_, response)| response)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, line.clone()))?;
+        Ok(match response {
+            Ok(stdout) => Output {
+                status: exit_status(0),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: Vec::new(),
+            },
+            Err(stderr) => Output {
+                status: exit_status(1),
+                stdout: Vec::new(),
+                stderr: stderr.as_bytes().to_vec(),
+            },
+        })
+    }
+}
+
+impl CommandRunner for MockRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        self.respond(cmd)
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        self.respond(cmd).map(|out| out.status)
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
@@ -0,0 +1,115 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::io::{self, Write};
+
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+
+use crate::cli::{Cli, CompletionsArgs};
+
+/// Lists the local bookmarks, for the shells to complete their names.
+const LIST_BOOKMARKS: &str = r#"jj bookmark list -T 'if(remote, "", name ++ "\n")' 2>/dev/null"#;
+
+/// Prints the completion script of args.shell. Besides the subcommands and
+/// flags, the bash, zsh and fish scripts complete the bookmark names taken
+/// by --bookmark and its siblings by asking jj for them.
+pub fn run(args: &CompletionsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = Cli::command();
+    let mut script = Vec::new();
+    generate(args.shell, &mut command, "jjstack", &mut script);
+    let script = String::from_utf8(script)?;
+    let flags = bookmark_flags(&command);
+    let script = match args.shell {
+        Shell::Bash => format!("{}\n{}", script, bash_bookmarks(&flags)),
+        Shell::Zsh => zsh_bookmarks(&script),
+        Shell::Fish => format!("{}\n{}", script, fish_bookmarks(&flags)),
+        _ => script,
+    };
+    io::stdout().write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// Returns the long flags, of every subcommand, whose value is a bookmark.
+fn bookmark_flags(command: &clap::Command) -> Vec<String> {
+    let mut flags: Vec<String> = command
+        .get_arguments()
+        .filter(|arg| {
+            arg.get_value_names().is_some_and(|names| {
+                names
+                    .iter()
+                    .any(|name| *name == "BOOKMARK" || *name == "PR|BOOKMARK")
+            })
+        })
+        .filter_map(|arg| arg.get_long().map(|long| format!("--{}", long)))
+        .collect();
+    for subcommand in command.get_subcommands() {
+        flags.extend(bookmark_flags(subcommand));
+    }
+    flags.sort();
+    flags.dedup();
+    flags
+}
+
+/// Wraps the generated _jjstack function to complete bookmark names first.
+fn bash_bookmarks(flags: &[String]) -> String {
+    format!(
+        r#"_jjstack_bookmarks() {{
+    case "${{COMP_WORDS[COMP_CWORD-1]}}" in
+        {})
+            COMPREPLY=($(compgen -W "$({})" -- "${{COMP_WORDS[COMP_CWORD]}}"))
+            ;;
+        *)
+            _jjstack "$@"
+            ;;
+    esac
+}}
+
+complete -F _jjstack_bookmarks -o nosort -o bashdefault -o default jjstack
+"#,
+        flags.join("|"),
+        LIST_BOOKMARKS
+    )
+}
+
+/// Completes the values named BOOKMARK with the bookmarks of the repository
+/// instead of file names.
+fn zsh_bookmarks(script: &str) -> String {
+    let function = format!(
+        r#"_jjstack_bookmarks() {{
+    local -a bookmarks
+    bookmarks=(${{(f)"$({})"}})
+    _describe 'bookmark' bookmarks
+}}
+"#,
+        LIST_BOOKMARKS
+    );
+    let script = script
+        .replace(":BOOKMARK:_default", ":BOOKMARK:_jjstack_bookmarks")
+        .replace(":PR|BOOKMARK:_default", ":PR|BOOKMARK:_jjstack_bookmarks");
+    // The function must exist before the script runs _jjstack, right after
+    // the #compdef line.
+    match script.split_once('\n') {
+        Some((compdef, rest)) => format!("{}\n\n{}\n{}", compdef, function, rest),
+        None => script,
+    }
+}
+
+fn fish_bookmarks(flags: &[String]) -> String {
+    let mut s = format!(
+        "function __jjstack_bookmarks\n    {}\nend\n",
+        LIST_BOOKMARKS
+    );
+    for flag in flags {
+        s.push_str(&format!(
+            "complete -c jjstack -l {} -f -a '(__jjstack_bookmarks)'\n",
+            flag.trim_start_matches("--")
+        ));
+    }
+    s
+}
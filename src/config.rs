@@ -0,0 +1,493 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::command;
+use crate::error::JjstackError;
+use crate::forge::ForgeKind;
+use crate::http::RetryPolicy;
+
+const REPO_CONFIG_FILE: &str = ".jjstack.toml";
+
+/// NavStyle selects how each entry of the navigation block is rendered.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NavStyle {
+    /// Markdown links with the PR title.
+    #[default]
+    Links,
+    /// PR number and branch name, without links.
+    Plain,
+    /// A Mermaid diagram of the stack, which shows branching stacks best.
+    Mermaid,
+}
+
+/// NavFormat selects how the entries of a links or plain navigation block
+/// are listed and how the current PR is marked.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NavFormat {
+    /// A numbered list from the bottom of the stack, marked with ◁.
+    #[default]
+    Numbered,
+    /// A bulleted list from the bottom of the stack, marked with ◁.
+    Bullets,
+    /// A numbered list from the bottom of the stack, marked with
+    /// "⬅ you are here".
+    Arrow,
+    /// Deprecated alias of bullets listed in top-down order, ending with the
+    /// trunk; resolved by Config::nav_layout.
+    #[value(hide = true)]
+    TopDown,
+}
+
+/// NavOrder selects in which direction the entries of a links or plain
+/// navigation block are listed. Entries are numbered from the bottom of the
+/// stack either way; a bulleted list in top-down order ends with the trunk.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NavOrder {
+    /// From the PR closest to the trunk up to the newest.
+    #[default]
+    BottomUp,
+    /// From the newest PR down to the trunk.
+    TopDown,
+}
+
+/// AnnotateMode selects where the navigation block is written.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotateMode {
+    /// Append the navigation block to the PR description.
+    #[default]
+    Body,
+    /// Keep the navigation block in a single comment on each PR, leaving the
+    /// description untouched.
+    Comment,
+}
+
+/// Placement selects where the navigation block goes in a PR description.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// Before the description.
+    Top,
+    /// After the description.
+    #[default]
+    Bottom,
+    /// Right below the line matching placement_heading.
+    AfterHeading,
+    /// Right below the <!-- jjstack --> placeholder.
+    Placeholder,
+}
+
+/// ForeignStacks selects what happens to the stack sections other tools,
+/// such as Graphite or spr, wrote in a PR description.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ForeignStacks {
+    /// Leave them alone, placing the navigation block as usual.
+    #[default]
+    Ignore,
+    /// Keep them, with the navigation block right below.
+    Adjacent,
+    /// Replace them with the navigation block.
+    Replace,
+}
+
+/// Topology selects where the order of the PRs in a stack comes from.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Topology {
+    /// Chain the PRs by their base and head branches on the forge.
+    #[default]
+    Forge,
+    /// Follow the jj commit graph, falling back to the forge for PRs whose
+    /// bookmark it does not place.
+    Jj,
+}
+
+/// BookmarkRegex is a regular expression bookmarks must match, anywhere in
+/// their name, to be considered part of a stack.
+#[derive(Clone, Debug)]
+pub struct BookmarkRegex(pub Regex);
+
+impl FromStr for BookmarkRegex {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s).map(BookmarkRegex)
+    }
+}
+
+impl<'de> Deserialize<'de> for BookmarkRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// WorkspaceRepo is another repository of the workspace, holding stacks
+/// that mirror the local ones: its PRs whose branch matches a local bookmark,
+/// once the bookmark prefix of each repository is dropped, are linked from
+/// the navigation.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceRepo {
+    pub repo: String,
+    pub forge: Option<ForgeKind>,
+    pub host: Option<String>,
+    pub bookmark_prefix: Option<String>,
+}
+
+/// Config holds the settings read from the user and repository configuration
+/// files. Every field is optional so that files can be layered: the
+/// repository file overrides the user file, and command line flags override
+/// both.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub trunk: Option<String>,
+    pub nav_style: Option<NavStyle>,
+    pub nav_format: Option<NavFormat>,
+    pub nav_order: Option<NavOrder>,
+    pub mode: Option<AnnotateMode>,
+    pub placement: Option<Placement>,
+    pub placement_heading: Option<String>,
+    pub foreign_stacks: Option<ForeignStacks>,
+    pub bookmark_prefix: Option<String>,
+    pub bookmark_glob: Option<String>,
+    pub exclude_bookmarks: Option<Vec<String>>,
+    pub bookmark_regex: Option<BookmarkRegex>,
+    pub push_bookmark_prefix: Option<String>,
+    pub bookmark_name_template: Option<String>,
+    pub forge: Option<ForgeKind>,
+    pub template_file: Option<PathBuf>,
+    pub retry_attempts: Option<u32>,
+    pub retry_backoff_ms: Option<u64>,
+    pub write_interval_ms: Option<u64>,
+    pub host: Option<String>,
+    pub api_url: Option<String>,
+    pub web_url: Option<String>,
+    pub github_app_id: Option<u64>,
+    pub github_app_installation_id: Option<u64>,
+    pub github_app_key_file: Option<PathBuf>,
+    pub fork_owner: Option<String>,
+    pub cache: Option<bool>,
+    pub ci_status: Option<bool>,
+    pub diff_stats: Option<bool>,
+    pub neighbors: Option<bool>,
+    pub emoji: Option<bool>,
+    pub skip_drafts: Option<bool>,
+    pub labels: Option<bool>,
+    pub audit: Option<bool>,
+    pub label_prefix: Option<String>,
+    pub title_prefix: Option<bool>,
+    pub propagate_reviewers: Option<bool>,
+    pub reviewers: Option<Vec<String>>,
+    pub remote: Option<String>,
+    pub repo: Option<String>,
+    pub stack_ids: Option<bool>,
+    pub remote_bookmarks: Option<bool>,
+    pub strict_protection: Option<bool>,
+    pub stack_names: Option<HashMap<String, String>>,
+    pub jj_bin: Option<PathBuf>,
+    pub gh_bin: Option<PathBuf>,
+    pub topology: Option<Topology>,
+    pub workspace: Option<Vec<WorkspaceRepo>>,
+}
+
+impl Config {
+    /// Loads ~/.config/jjstack/config.toml, then the jjstack.* settings of
+    /// the jj configuration and .jjstack.toml from the root of the jj
+    /// repository, each taking precedence over the previous ones.
+    pub fn load() -> Result<Config, JjstackError> {
+        let mut config = Config::default();
+        if let Some(path) = user_config_path() {
+            config = config.merge(read_config(&path)?);
+        }
+        // The repository is found with jj, which the user configuration may
+        // point elsewhere.
+        config.set_programs();
+        if let Ok(root) = repo_root() {
+            let mut jj_config = read_jj_config()?;
+            jj_config.template_file = jj_config.template_file.map(|path| root.join(path));
+            config = config.merge(jj_config);
+            let mut repo_config = read_config(&root.join(REPO_CONFIG_FILE))?;
+            repo_config.template_file = repo_config.template_file.map(|path| root.join(path));
+            config = config.merge(repo_config);
+        }
+        config.set_programs();
+        Ok(config)
+    }
+
+    /// Makes jjstack run the jj and gh executables set in jj_bin and gh_bin.
+    pub fn set_programs(&self) {
+        if let Some(path) = &self.jj_bin {
+            command::set_program("jj", path.clone());
+        }
+        if let Some(path) = &self.gh_bin {
+            command::set_program("gh", path.clone());
+        }
+    }
+
+    /// Returns a copy of self with the fields set in other taking precedence.
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            trunk: other.trunk.or(self.trunk),
+            nav_style: other.nav_style.or(self.nav_style),
+            nav_format: other.nav_format.or(self.nav_format),
+            nav_order: other.nav_order.or(self.nav_order),
+            mode: other.mode.or(self.mode),
+            placement: other.placement.or(self.placement),
+            placement_heading: other.placement_heading.or(self.placement_heading),
+            foreign_stacks: other.foreign_stacks.or(self.foreign_stacks),
+            bookmark_prefix: other.bookmark_prefix.or(self.bookmark_prefix),
+            bookmark_glob: other.bookmark_glob.or(self.bookmark_glob),
+            // Exclusions accumulate: the command line and the repository
+            // add to the globs of the user configuration.
+            exclude_bookmarks: match (self.exclude_bookmarks, other.exclude_bookmarks) {
+                (Some(mut globs), Some(other)) => {
+                    globs.extend(other);
+                    Some(globs)
+                }
+                (globs, other) => other.or(globs),
+            },
+            bookmark_regex: other.bookmark_regex.or(self.bookmark_regex),
+            push_bookmark_prefix: other.push_bookmark_prefix.or(self.push_bookmark_prefix),
+            bookmark_name_template: other.bookmark_name_template.or(self.bookmark_name_template),
+            forge: other.forge.or(self.forge),
+            template_file: other.template_file.or(self.template_file),
+            retry_attempts: other.retry_attempts.or(self.retry_attempts),
+            retry_backoff_ms: other.retry_backoff_ms.or(self.retry_backoff_ms),
+            write_interval_ms: other.write_interval_ms.or(self.write_interval_ms),
+            host: other.host.or(self.host),
+            api_url: other.api_url.or(self.api_url),
+            web_url: other.web_url.or(self.web_url),
+            github_app_id: other.github_app_id.or(self.github_app_id),
+            github_app_installation_id: other
+                .github_app_installation_id
+                .or(self.github_app_installation_id),
+            github_app_key_file: other.github_app_key_file.or(self.github_app_key_file),
+            fork_owner: other.fork_owner.or(self.fork_owner),
+            cache: other.cache.or(self.cache),
+            ci_status: other.ci_status.or(self.ci_status),
+            diff_stats: other.diff_stats.or(self.diff_stats),
+            neighbors: other.neighbors.or(self.neighbors),
+            emoji: other.emoji.or(self.emoji),
+            skip_drafts: other.skip_drafts.or(self.skip_drafts),
+            labels: other.labels.or(self.labels),
+            audit: other.audit.or(self.audit),
+            label_prefix: other.label_prefix.or(self.label_prefix),
+            title_prefix: other.title_prefix.or(self.title_prefix),
+            propagate_reviewers: other.propagate_reviewers.or(self.propagate_reviewers),
+            reviewers: other.reviewers.or(self.reviewers),
+            remote: other.remote.or(self.remote),
+            repo: other.repo.or(self.repo),
+            stack_ids: other.stack_ids.or(self.stack_ids),
+            remote_bookmarks: other.remote_bookmarks.or(self.remote_bookmarks),
+            strict_protection: other.strict_protection.or(self.strict_protection),
+            stack_names: other.stack_names.or(self.stack_names),
+            jj_bin: other.jj_bin.or(self.jj_bin),
+            gh_bin: other.gh_bin.or(self.gh_bin),
+            topology: other.topology.or(self.topology),
+            workspace: other.workspace.or(self.workspace),
+        }
+    }
+
+    /// Builds the retry policy for API calls, filling unset fields with
+    /// the defaults.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            attempts: self.retry_attempts.unwrap_or(default.attempts),
+            backoff: self
+                .retry_backoff_ms
+                .map_or(default.backoff, Duration::from_millis),
+            write_interval: self
+                .write_interval_ms
+                .map_or(default.write_interval, Duration::from_millis),
+        }
+    }
+
+    /// Resolves how the navigation block is listed. The deprecated top-down
+    /// format stands for bullets in top-down order, unless the order is set.
+    pub fn nav_layout(&self) -> (NavFormat, NavOrder) {
+        match self.nav_format.unwrap_or_default() {
+            NavFormat::TopDown => (
+                NavFormat::Bullets,
+                self.nav_order.unwrap_or(NavOrder::TopDown),
+            ),
+            format => (format, self.nav_order.unwrap_or_default()),
+        }
+    }
+}
+
+fn read_config(path: &Path) -> Result<Config, JjstackError> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let config = parse_config(&text).map_err(|e| {
+        JjstackError::ConfigError(format!("cannot parse {}: {}", path.display(), e))
+    })?;
+    Ok(config)
+}
+
+/// Parses a configuration file, reporting where an error lies and
+/// suggesting the closest known key for a misspelled one.
+fn parse_config<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    toml::from_str(text).map_err(|e| {
+        let message = suggest_key(e.message()).unwrap_or_else(|| e.message().to_string());
+        let Some(span) = e.span() else {
+            return message;
+        };
+        let before = &text[..span.start.min(text.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        format!("line {}, column {}: {}", line, column, message)
+    })
+}
+
+/// Rewrites the error serde gives for an unknown key, which lists every
+/// expected key, to only suggest the closest one.
+fn suggest_key(message: &str) -> Option<String> {
+    let rest = message.strip_prefix("unknown field `")?;
+    let (key, expected) = rest.split_once('`')?;
+    let closest = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .min()
+        .filter(|(distance, _)| *distance <= 2.max(key.len() / 3));
+    Some(match closest {
+        Some((_, candidate)) => format!("unknown key `{}`, did you mean `{}`?", key, candidate),
+        None => format!("unknown key `{}`", key),
+    })
+}
+
+/// Counts the characters to insert, delete or replace to turn a into b.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// JjConfig is the output of `jj config list jjstack`, where the settings
+/// are listed as TOML keys under the jjstack table.
+#[derive(Default, Deserialize)]
+struct JjConfig {
+    #[serde(default)]
+    jjstack: Config,
+}
+
+/// Reads the jjstack.* settings of the jj configuration, such as those set
+/// with `jj config set --repo jjstack.trunk main`.
+fn read_jj_config() -> Result<Config, JjstackError> {
+    let out = command::output(command::new("jj").args(["config", "list", "jjstack"]))?;
+    if !out.status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj config list jjstack".to_string(),
+            message: String::from_utf8_lossy(&out.stderr).into_owned(),
+        });
+    }
+    let text = command::stdout(out)?;
+    let config: JjConfig = parse_config(&text).map_err(|e| {
+        JjstackError::ConfigError(format!("cannot parse the jjstack settings of jj: {}", e))
+    })?;
+    Ok(config.jjstack)
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("jjstack").join("config.toml"))
+}
+
+/// Returns the path of the configuration file of the current repository.
+pub fn repo_config_path() -> Result<PathBuf, JjstackError> {
+    Ok(repo_root()?.join(REPO_CONFIG_FILE))
+}
+
+/// Returns the root directory of the current jj repository.
+pub fn repo_root() -> Result<PathBuf, JjstackError> {
+    let out = command::output(command::new("jj").arg("root"))?;
+    if !out.status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj root".to_string(),
+            message: String::from_utf8_lossy(&out.stderr).into_owned(),
+        });
+    }
+    Ok(PathBuf::from(command::stdout(out)?.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config_suggests_the_closest_key() {
+        let text = "trunk = \"main\"\nnavstyle = \"plain\"\n";
+        let err = parse_config::<Config>(text).unwrap_err();
+        assert_eq!(
+            err,
+            "line 2, column 1: unknown key `navstyle`, did you mean `nav_style`?"
+        );
+        let err = parse_config::<Config>("emoji = \"no\"\n").unwrap_err();
+        assert!(err.starts_with("line 1, column 9: invalid type"), "{}", err);
+    }
+
+    #[test]
+    fn nav_layout_folds_the_top_down_format_into_the_order() {
+        let config = Config {
+            nav_format: Some(NavFormat::TopDown),
+            ..Default::default()
+        };
+        assert_eq!(config.nav_layout(), (NavFormat::Bullets, NavOrder::TopDown));
+        let config = Config {
+            nav_format: Some(NavFormat::TopDown),
+            nav_order: Some(NavOrder::BottomUp),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.nav_layout(),
+            (NavFormat::Bullets, NavOrder::BottomUp)
+        );
+        assert_eq!(
+            Config::default().nav_layout(),
+            (NavFormat::Numbered, NavOrder::BottomUp)
+        );
+    }
+}
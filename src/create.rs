@@ -0,0 +1,164 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use log::warn;
+
+use jjstack_core::config::{repo_root, Config};
+use jjstack_core::forge::Forge;
+use jjstack_core::jj::{get_bookmark_parents, get_bookmarks, get_description, is_stack_bookmark};
+
+use crate::cli::CreateArgs;
+use crate::get_open_prs;
+
+/// Opens a PR for a bookmark, based on the closest bookmark among the
+/// ancestors of its commit that has an open PR, falling back to the trunk.
+/// Without --apply, only explains the base it would pick.
+pub fn run(
+    repo: String,
+    forge: &dyn Forge,
+    args: &CreateArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bookmark = &args.head;
+    if !is_stack_bookmark(config, bookmark) {
+        warn!("{} does not match the bookmark filters", bookmark);
+    }
+    let mut bookmarks: HashSet<String> = get_bookmarks(config)?.into_iter().collect();
+    bookmarks.insert(bookmark.to_string());
+    let prs = get_open_prs(forge, bookmarks, &repo, config)?;
+    if let Some(pr) = prs.iter().find(|pr| pr.head == *bookmark) {
+        return Err(format!("{} already has PR #{}: {}", bookmark, pr.number, pr.url).into());
+    }
+
+    let mut candidates: Vec<String> = prs.iter().map(|pr| pr.head.to_string()).collect();
+    candidates.push(bookmark.to_string());
+    let parents = get_bookmark_parents(config, &candidates)?;
+    let parent = parents
+        .get(bookmark)
+        .cloned()
+        .flatten()
+        .and_then(|parent| prs.iter().find(|pr| pr.head == parent));
+    let (base, reason) = match parent {
+        Some(pr) => (
+            pr.head.to_string(),
+            format!(
+                "the closest ancestor with an open PR, #{} {:?}",
+                pr.number, pr.title
+            ),
+        ),
+        None => (
+            config.trunk.clone().unwrap_or_default(),
+            "the trunk, as no ancestor bookmark has an open PR".to_string(),
+        ),
+    };
+
+    let description = get_description(bookmark)?;
+    let (title, body) = description
+        .trim()
+        .split_once('\n')
+        .unwrap_or((description.trim(), ""));
+    if title.is_empty() {
+        return Err(format!("{} has no description to title its PR", bookmark).into());
+    }
+    // The template follows the commit description, and the navigation block
+    // the template once the stack is annotated.
+    let template = pr_template(&repo_root()?, args.template.as_deref())?;
+    let body: Vec<&str> = [body.trim(), template.as_deref().unwrap_or_default().trim()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect();
+    let body = body.join("\n\n");
+    println!("PR for {}: {:?}", bookmark, title);
+    println!("base: {} ({})", base, reason);
+    if !args.annotate_args.apply {
+        println!("dry run: use --apply to open the PR.");
+        return Ok(());
+    }
+
+    if !forge.branch_exists(bookmark)? {
+        return Err(format!(
+            "{} is not pushed; push it first with 'jj git push -b {}'",
+            bookmark, bookmark
+        )
+        .into());
+    }
+    forge.create_pr(bookmark, &base, title.to_string(), body, args.draft)?;
+    match forge
+        .list_open_prs()?
+        .into_iter()
+        .find(|pr| pr.head == *bookmark)
+    {
+        Some(pr) => println!("opened PR #{}: {}", pr.number, pr.url),
+        None => println!("opened a PR for {}", bookmark),
+    }
+    Ok(())
+}
+
+/// PR_TEMPLATES are the places GitHub looks for the PR template of a
+/// repository, in order.
+const PR_TEMPLATES: &[&str] = &[
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "pull_request_template.md",
+    "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+    "docs/PULL_REQUEST_TEMPLATE.md",
+];
+
+/// PR_TEMPLATE_DIR holds the templates of repositories with several, one of
+/// which is picked by name.
+const PR_TEMPLATE_DIR: &str = ".github/PULL_REQUEST_TEMPLATE";
+
+/// Reads the PR template of the repository at root: the one named name in
+/// PR_TEMPLATE_DIR, or else the single template of the repository.
+fn pr_template(
+    root: &Path,
+    name: Option<&str>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let dir = root.join(PR_TEMPLATE_DIR);
+    if let Some(name) = name {
+        let file = if name.ends_with(".md") {
+            name.to_string()
+        } else {
+            format!("{}.md", name)
+        };
+        let path = dir.join(file);
+        let template = fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        return Ok(Some(template));
+    }
+    for path in PR_TEMPLATES {
+        if let Ok(template) = fs::read_to_string(root.join(path)) {
+            return Ok(Some(template));
+        }
+    }
+    let mut names: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".md"))
+            .collect(),
+        Err(_) => return Ok(None),
+    };
+    names.sort();
+    match &names[..] {
+        [name] => Ok(Some(fs::read_to_string(dir.join(name))?)),
+        [] => Ok(None),
+        _ => {
+            warn!(
+                "several PR templates, pick one with --template: {}",
+                names.join(", ")
+            );
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+
+use jjstack_core::command;
+use jjstack_core::config::Config;
+use jjstack_core::forge::{parse_remote_url, ForgeKind};
+use jjstack_core::github;
+use jjstack_core::jj::{get_remote_url, is_stack_bookmark, list_bookmarks, BookmarkState};
+
+use crate::{connect, table};
+
+/// Doctor prints the outcome of each check and counts the failures.
+#[derive(Default)]
+struct Doctor {
+    failures: usize,
+}
+
+impl Doctor {
+    fn ok(&self, what: &str) {
+        println!("{} {}", if table::ascii() { "ok" } else { "✓" }, what);
+    }
+
+    fn fail(&mut self, what: &str, fix: &str) {
+        self.failures += 1;
+        println!("{} {}", if table::ascii() { "FAIL" } else { "✗" }, what);
+        println!("    fix: {}", fix);
+    }
+
+    fn warn(&self, what: &str, fix: &str) {
+        println!("! {}", what);
+        println!("    fix: {}", fix);
+    }
+}
+
+/// Checks that jj, the repository, the forge credentials and the bookmarks
+/// are set up, and suggests a fix for each problem found.
+pub fn run(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut doctor = Doctor::default();
+
+    match command::output(command::new("jj").arg("--version")) {
+        Ok(out) if out.status.success() => {
+            doctor.ok(String::from_utf8_lossy(&out.stdout).trim());
+        }
+        _ => {
+            doctor.fail(
+                "jj is not installed",
+                "install jj from https://jj-vcs.github.io/jj/latest/install-and-setup/",
+            );
+            return finish(doctor);
+        }
+    }
+
+    match command::output(command::new("jj").arg("root")) {
+        Ok(out) if out.status.success() => {
+            doctor.ok(&format!(
+                "jj repository at {}",
+                String::from_utf8_lossy(&out.stdout).trim()
+            ));
+        }
+        _ => {
+            doctor.fail(
+                "not inside a jj repository",
+                "run 'jj git init --colocate' in your git checkout",
+            );
+            return finish(doctor);
+        }
+    }
+
+    let remote = match get_remote_url(config.remote.as_deref()) {
+        Ok(url) => match parse_remote_url(&url) {
+            Some(remote) => {
+                doctor.ok(&format!("git remote {}", url));
+                Some(remote)
+            }
+            None => {
+                doctor.fail(
+                    &format!("cannot parse git remote URL {:?}", url),
+                    "point the remote at an ssh:// or https:// URL",
+                );
+                None
+            }
+        },
+        Err(e) => {
+            doctor.fail(
+                &format!("no usable git remote: {}", e),
+                "run 'jj git remote add origin URL', or pick one with --remote",
+            );
+            None
+        }
+    };
+
+    let forge_kind = config.forge.unwrap_or_else(|| {
+        remote
+            .as_ref()
+            .map_or(ForgeKind::Github, |(host, _)| ForgeKind::detect(host))
+    });
+    let tokens = forge_kind.token_vars();
+    let app_id = config
+        .github_app_id
+        .filter(|_| forge_kind == ForgeKind::Github);
+    match (
+        app_id,
+        tokens.iter().find(|name| env::var_os(name).is_some()),
+    ) {
+        (Some(app_id), _) => doctor.ok(&format!("authenticating as GitHub App {}", app_id)),
+        (None, Some(name)) => doctor.ok(&format!("{:?} token in {}", forge_kind, name)),
+        (None, None) => doctor.fail(
+            &format!("no {:?} token found", forge_kind),
+            &match forge_kind {
+                ForgeKind::Github => "export GITHUB_TOKEN=$(gh auth token)".to_string(),
+                _ => format!("export {}=<personal access token>", tokens[0]),
+            },
+        ),
+    }
+
+    // Without --repo or a usable remote, the GitHub repository comes from
+    // `gh repo set-default`.
+    if forge_kind == ForgeKind::Github && remote.is_none() && config.repo.is_none() {
+        match github::default_repo() {
+            Ok(repo) => doctor.ok(&format!("default repository {}", repo)),
+            Err(e) => doctor.fail(
+                &format!("no default repository: {}", e.to_string().trim()),
+                "run 'gh repo set-default'",
+            ),
+        }
+    }
+
+    if doctor.failures > 0 {
+        return finish(doctor);
+    }
+    match connect(config) {
+        Ok((repo, _)) => doctor.ok(&format!(
+            "connected to {} (trunk: {})",
+            repo,
+            config.trunk.as_deref().unwrap_or_default()
+        )),
+        Err(e) => {
+            doctor.fail(
+                &format!("cannot reach the forge: {}", e),
+                "check the token scopes and the host/api_url settings",
+            );
+            return finish(doctor);
+        }
+    }
+
+    match list_bookmarks(false) {
+        Ok(bookmarks) => {
+            let mut checked = 0;
+            for bookmark in bookmarks.iter().filter(|b| {
+                matches!(b.state, BookmarkState::Present | BookmarkState::Conflicted)
+                    && is_stack_bookmark(config, &b.name)
+            }) {
+                checked += 1;
+                if bookmark.state == BookmarkState::Conflicted {
+                    doctor.fail(
+                        &format!("bookmark {} is conflicted", bookmark.name),
+                        &format!("run 'jj bookmark set {} -r REV'", bookmark.name),
+                    );
+                } else if !bookmark.remotes.iter().any(|r| r.tracked) {
+                    doctor.warn(
+                        &format!("bookmark {} is not pushed", bookmark.name),
+                        &format!("run 'jj git push -b {}'", bookmark.name),
+                    );
+                } else if bookmark.remotes.iter().any(|r| r.tracked && !r.synced) {
+                    doctor.warn(
+                        &format!("bookmark {} differs from its remote", bookmark.name),
+                        "run 'jjstack push'",
+                    );
+                }
+            }
+            doctor.ok(&format!("{} stack bookmarks checked", checked));
+        }
+        Err(e) => doctor.fail(
+            &format!("cannot list bookmarks: {}", e),
+            "run 'jj bookmark list' to see the error",
+        ),
+    }
+
+    finish(doctor)
+}
+
+fn finish(doctor: Doctor) -> Result<(), Box<dyn std::error::Error>> {
+    match doctor.failures {
+        0 => Ok(()),
+        1 => Err("1 check failed".into()),
+        n => Err(format!("{} checks failed", n).into()),
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::io;
+use std::string::FromUtf8Error;
+
+/// JjstackError tells why an operation of the library failed, so that
+/// callers can match on the kind of failure rather than on its message.
+#[derive(Debug, thiserror::Error)]
+pub enum JjstackError {
+    /// jj failed; message is what it printed on stderr, or its exit status.
+    #[error("cannot run '{command}': {message}")]
+    JjCommandFailed { command: String, message: String },
+    /// gh failed; message is what it printed on stderr, or its exit status.
+    #[error("cannot run '{command}': {message}")]
+    GhCommandFailed { command: String, message: String },
+    /// The forge answered request, such as "GET url", with an error status.
+    #[error("cannot {request}: {status}: {body}")]
+    ApiError {
+        request: String,
+        status: u16,
+        body: String,
+    },
+    /// The request never got an answer from the forge.
+    #[error("cannot {request}: {message}")]
+    RequestFailed { request: String, message: String },
+    /// A response, a file or the output of a command is malformed.
+    #[error("cannot parse {what}: {message}")]
+    ParseError { what: String, message: String },
+    /// The configuration is invalid or misses a setting.
+    #[error("{0}")]
+    ConfigError(String),
+    /// Ctrl-C stopped the run.
+    #[error("interrupted")]
+    Interrupted,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<String> for JjstackError {
+    fn from(message: String) -> Self {
+        JjstackError::Other(message)
+    }
+}
+
+impl From<&str> for JjstackError {
+    fn from(message: &str) -> Self {
+        JjstackError::Other(message.to_string())
+    }
+}
+
+impl From<serde_json::Error> for JjstackError {
+    fn from(e: serde_json::Error) -> Self {
+        JjstackError::ParseError {
+            what: "JSON".to_string(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for JjstackError {
+    fn from(e: FromUtf8Error) -> Self {
+        JjstackError::ParseError {
+            what: "UTF-8 text".to_string(),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// reqwest fails this way while reading a response, once it was received.
+impl From<reqwest::Error> for JjstackError {
+    fn from(e: reqwest::Error) -> Self {
+        JjstackError::ParseError {
+            what: "response".to_string(),
+            message: e.to_string(),
+        }
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::JjstackError;
+use crate::forge::{next_page_url, AsyncForge, Comment, MergeMethod};
+use crate::http::{self, Pacer, RetryPolicy};
+use crate::stack::DiffStat;
+use crate::PullRequest;
+
+#[derive(Debug, Deserialize)]
+struct GiteaPullRequest {
+    #[serde(rename = "number")]
+    number: i32,
+    #[serde(rename = "title")]
+    title: String,
+    #[serde(rename = "html_url")]
+    html_url: String,
+    #[serde(rename = "body")]
+    body: Option<String>,
+    #[serde(rename = "head")]
+    head: GiteaReference,
+    #[serde(rename = "base")]
+    base: GiteaReference,
+    #[serde(rename = "mergeable", default)]
+    mergeable: Option<bool>,
+    #[serde(rename = "labels", default)]
+    labels: Vec<GiteaLabel>,
+    #[serde(rename = "requested_reviewers", default)]
+    requested_reviewers: Option<Vec<GiteaUser>>,
+    #[serde(rename = "requested_reviewers_teams", default)]
+    requested_reviewers_teams: Option<Vec<GiteaTeam>>,
+    #[serde(rename = "created_at", default)]
+    created_at: Option<String>,
+    #[serde(rename = "additions", default)]
+    additions: Option<u64>,
+    #[serde(rename = "deletions", default)]
+    deletions: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaTeam {
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaLabel {
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaReference {
+    #[serde(rename = "ref")]
+    r#ref: String,
+    #[serde(rename = "repo")]
+    repo: Option<GiteaRepository>,
+    #[serde(rename = "sha", default)]
+    sha: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaRepository {
+    #[serde(rename = "owner")]
+    owner: Option<GiteaUser>,
+    #[serde(rename = "default_branch", default)]
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaUser {
+    #[serde(rename = "login")]
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GiteaComment {
+    #[serde(rename = "id")]
+    id: u64,
+    #[serde(rename = "body")]
+    body: String,
+}
+
+impl GiteaPullRequest {
+    fn into_pull_request(self) -> PullRequest {
+        // Gitea marks drafts with a work-in-progress title prefix.
+        let draft = ["WIP:", "[WIP]"]
+            .iter()
+            .any(|prefix| self.title.to_uppercase().starts_with(prefix));
+        PullRequest {
+            number: self.number,
+            title: self.title,
+            url: self.html_url,
+            head_owner: self
+                .head
+                .repo
+                .and_then(|repo| repo.owner)
+                .map(|owner| owner.login),
+            head: self.head.r#ref,
+            head_sha: self.head.sha,
+            base: self.base.r#ref,
+            body: self.body.unwrap_or_default(),
+            mergeable: self.mergeable,
+            draft,
+            labels: self.labels.into_iter().map(|label| label.name).collect(),
+            checks: None,
+            review: None,
+            created_at: self.created_at,
+            // Gitea reports the size of the changes, but not the commits.
+            diff_stat: match (self.additions, self.deletions) {
+                (Some(additions), Some(deletions)) => Some(DiffStat {
+                    additions,
+                    deletions,
+                    commits: None,
+                }),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// GiteaClient talks to the REST API (v1) of Gitea and Forgejo instances,
+/// such as Codeberg, authenticating with the token found in GITEA_TOKEN or
+/// FORGEJO_TOKEN.
+pub struct GiteaClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    pacer: Pacer,
+    token: String,
+    api_url: String,
+    repo: String,
+}
+
+impl GiteaClient {
+    pub fn new(api_url: String, repo: String, retry: RetryPolicy) -> Result<Self, JjstackError> {
+        let token = env::var("GITEA_TOKEN")
+            .or_else(|_| env::var("FORGEJO_TOKEN"))
+            .map_err(|_| {
+                JjstackError::ConfigError(
+                    "missing Gitea token: set GITEA_TOKEN or FORGEJO_TOKEN".to_string(),
+                )
+            })?;
+        Ok(GiteaClient {
+            client: http::new_client(),
+            retry,
+            pacer: Pacer::new(retry.write_interval),
+            token,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            repo,
+        })
+    }
+
+    fn repo_url(&self) -> String {
+        format!("{}/repos/{}", self.api_url, self.repo)
+    }
+
+    fn pull_url(&self, number: i32) -> String {
+        format!("{}/pulls/{}", self.repo_url(), number)
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, JjstackError> {
+        http::send(&self.retry, &format!("GET {}", url), || {
+            self.client
+                .get(url)
+                .header("Authorization", format!("token {}", self.token))
+        })
+        .await
+    }
+
+    async fn send_json(
+        &self,
+        method: &str,
+        url: &str,
+        data: serde_json::Value,
+    ) -> Result<(), JjstackError> {
+        http::send_paced(
+            &self.retry,
+            &self.pacer,
+            &format!("{} {}", method, url),
+            || {
+                let req = match method {
+                    "POST" => self.client.post(url),
+                    "PUT" => self.client.put(url),
+                    _ => self.client.patch(url),
+                };
+                req.header("Authorization", format!("token {}", self.token))
+                    .json(&data)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+impl AsyncForge for GiteaClient {
+    async fn list_open_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let mut url = format!("{}/pulls?state=open&limit=50", self.repo_url());
+        let mut prs = Vec::new();
+        loop {
+            let resp = self.get(&url).await?;
+            let next = resp
+                .headers()
+                .get("link")
+                .and_then(|v| v.to_str().ok())
+                .and_then(next_page_url);
+            let gitea_prs: Vec<GiteaPullRequest> = resp.json().await?;
+            prs.extend(
+                gitea_prs
+                    .into_iter()
+                    .map(GiteaPullRequest::into_pull_request),
+            );
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(prs)
+    }
+
+    async fn get_pr_body(&self, number: i32) -> Result<String, JjstackError> {
+        let pr: GiteaPullRequest = self.get(&self.pull_url(number)).await?.json().await?;
+        Ok(pr.body.unwrap_or_default())
+    }
+
+    async fn update_pr_body(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        self.send_json("PATCH", &self.pull_url(number), json!({ "body": body }))
+            .await
+    }
+
+    async fn update_pr_title(
+        &self,
+        number: i32,
+        title: String,
+        body: Option<String>,
+    ) -> Result<(), JjstackError> {
+        let mut data = json!({ "title": title });
+        if let Some(body) = body {
+            data["body"] = json!(body);
+        }
+        self.send_json("PATCH", &self.pull_url(number), data).await
+    }
+
+    async fn find_closed_pr(&self, head: &str) -> Result<Option<PullRequest>, JjstackError> {
+        // The API cannot filter by head branch, so only the most recently
+        // updated closed PRs are scanned: a parent that was just merged is
+        // among them.
+        let url = format!(
+            "{}/pulls?state=closed&sort=recentupdate&limit=50",
+            self.repo_url()
+        );
+        let gitea_prs: Vec<GiteaPullRequest> = self.get(&url).await?.json().await?;
+        let owner = self.repo.split('/').next().unwrap_or_default();
+        Ok(gitea_prs
+            .into_iter()
+            .map(GiteaPullRequest::into_pull_request)
+            .find(|pr| pr.head == head && pr.head_owner.as_deref().is_none_or(|o| o == owner)))
+    }
+
+    async fn list_closed_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let url = format!(
+            "{}/pulls?state=closed&sort=recentupdate&limit=50",
+            self.repo_url()
+        );
+        let gitea_prs: Vec<GiteaPullRequest> = self.get(&url).await?.json().await?;
+        Ok(gitea_prs
+            .into_iter()
+            .map(GiteaPullRequest::into_pull_request)
+            .collect())
+    }
+
+    async fn update_pr_base(&self, number: i32, base: String) -> Result<(), JjstackError> {
+        self.send_json("PATCH", &self.pull_url(number), json!({ "base": base }))
+            .await
+    }
+
+    async fn branch_exists(&self, branch: &str) -> Result<bool, JjstackError> {
+        let url = format!("{}/branches/{}", self.repo_url(), branch);
+        let resp = http::send_optional(&self.retry, &format!("GET {}", url), || {
+            self.client
+                .get(&url)
+                .header("Authorization", format!("token {}", self.token))
+        })
+        .await?;
+        Ok(resp.is_some())
+    }
+
+    async fn default_branch(&self) -> Result<String, JjstackError> {
+        let repo: GiteaRepository = self.get(&self.repo_url()).await?.json().await?;
+        Ok(repo.default_branch)
+    }
+
+    async fn list_pr_comments(&self, number: i32) -> Result<Vec<Comment>, JjstackError> {
+        let mut url = format!("{}/issues/{}/comments?limit=50", self.repo_url(), number);
+        let mut comments = Vec::new();
+        loop {
+            let resp = self.get(&url).await?;
+            let next = resp
+                .headers()
+                .get("link")
+                .and_then(|v| v.to_str().ok())
+                .and_then(next_page_url);
+            let gitea_comments: Vec<GiteaComment> = resp.json().await?;
+            comments.extend(gitea_comments.into_iter().map(|c| Comment {
+                id: c.id,
+                body: c.body,
+            }));
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(comments)
+    }
+
+    async fn create_pr_comment(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        let url = format!("{}/issues/{}/comments", self.repo_url(), number);
+        self.send_json("POST", &url, json!({ "body": body })).await
+    }
+
+    async fn update_pr_comment(
+        &self,
+        _number: i32,
+        id: u64,
+        body: String,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/issues/comments/{}", self.repo_url(), id);
+        self.send_json("PATCH", &url, json!({ "body": body })).await
+    }
+
+    async fn delete_pr_comment(&self, _number: i32, id: u64) -> Result<(), JjstackError> {
+        let url = format!("{}/issues/comments/{}", self.repo_url(), id);
+        http::send_paced(&self.retry, &self.pacer, &format!("DELETE {}", url), || {
+            self.client
+                .delete(&url)
+                .header("Authorization", format!("token {}", self.token))
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn set_pr_labels(&self, number: i32, labels: Vec<String>) -> Result<(), JjstackError> {
+        let url = format!("{}/issues/{}/labels", self.repo_url(), number);
+        self.send_json("PUT", &url, json!({ "labels": labels }))
+            .await
+    }
+
+    async fn list_requested_reviewers(&self, number: i32) -> Result<Vec<String>, JjstackError> {
+        let pr: GiteaPullRequest = self.get(&self.pull_url(number)).await?.json().await?;
+        let org = self.repo.split('/').next().unwrap_or_default();
+        let users = pr.requested_reviewers.unwrap_or_default();
+        let teams = pr.requested_reviewers_teams.unwrap_or_default();
+        Ok(users
+            .into_iter()
+            .map(|user| user.login)
+            .chain(
+                teams
+                    .into_iter()
+                    .map(|team| format!("{}/{}", org, team.name)),
+            )
+            .collect())
+    }
+
+    async fn request_reviewers(
+        &self,
+        number: i32,
+        reviewers: Vec<String>,
+    ) -> Result<(), JjstackError> {
+        let (teams, users): (Vec<String>, Vec<String>) =
+            reviewers.into_iter().partition(|r| r.contains('/'));
+        let teams: Vec<&str> = teams
+            .iter()
+            .filter_map(|team| team.split_once('/').map(|(_, name)| name))
+            .collect();
+        let url = format!("{}/requested_reviewers", self.pull_url(number));
+        self.send_json(
+            "POST",
+            &url,
+            json!({ "reviewers": users, "team_reviewers": teams }),
+        )
+        .await
+    }
+
+    async fn create_pr(
+        &self,
+        head: &str,
+        base: &str,
+        title: String,
+        body: String,
+        draft: bool,
+    ) -> Result<(), JjstackError> {
+        // Gitea marks work in progress by the title alone.
+        let title = if draft {
+            format!("WIP: {}", title)
+        } else {
+            title
+        };
+        let url = format!("{}/pulls", self.repo_url());
+        let data = json!({ "head": head, "base": base, "title": title, "body": body });
+        self.send_json("POST", &url, data).await
+    }
+
+    async fn merge_pr(&self, number: i32, method: MergeMethod) -> Result<(), JjstackError> {
+        let merge_method = match method {
+            MergeMethod::Merge => "merge",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+        };
+        let url = format!("{}/merge", self.pull_url(number));
+        self.send_json("POST", &url, json!({ "Do": merge_method }))
+            .await
+    }
+}
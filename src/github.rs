@@ -0,0 +1,1195 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::cache::{Cache, Entry};
+use crate::command;
+use crate::error::JjstackError;
+use crate::forge::{next_page_url, AsyncForge, Comment, MergeMethod, QueueState};
+use crate::http::{self, Pacer, RetryPolicy};
+use crate::stack::{CheckState, DiffStat, ReviewState};
+use crate::PullRequest;
+
+const API_URL: &str = "https://api.github.com";
+const WEB_URL: &str = "https://github.com";
+
+/// Longest PR description GitHub accepts, in characters.
+const MAX_BODY_LEN: usize = 65_536;
+
+const OPEN_PRS_QUERY: &str = r#"
+query($owner: String!, $name: String!, $cursor: String) {
+  repository(owner: $owner, name: $name) {
+    pullRequests(states: OPEN, first: 100, after: $cursor) {
+      nodes {
+        number
+        title
+        body
+        headRefName
+        headRefOid
+        headRepositoryOwner {
+          login
+        }
+        baseRefName
+        mergeable
+        isDraft
+        createdAt
+        additions
+        deletions
+        labels(first: 100) {
+          nodes {
+            name
+          }
+        }
+        reviewDecision
+        commits(last: 1) {
+          totalCount
+          nodes {
+            commit {
+              statusCheckRollup {
+                state
+              }
+            }
+          }
+        }
+      }
+      pageInfo {
+        hasNextPage
+        endCursor
+      }
+    }
+  }
+}
+"#;
+
+const MERGE_QUEUE_QUERY: &str = r#"
+query($owner: String!, $name: String!, $branch: String!) {
+  repository(owner: $owner, name: $name) {
+    mergeQueue(branch: $branch) {
+      id
+    }
+  }
+}
+"#;
+
+const QUEUE_STATE_QUERY: &str = r#"
+query($owner: String!, $name: String!, $number: Int!) {
+  repository(owner: $owner, name: $name) {
+    pullRequest(number: $number) {
+      id
+      state
+      mergeQueueEntry {
+        position
+      }
+    }
+  }
+}
+"#;
+
+const ENQUEUE_MUTATION: &str = r#"
+mutation($id: ID!) {
+  enqueuePullRequest(input: { pullRequestId: $id }) {
+    mergeQueueEntry {
+      position
+    }
+  }
+}
+"#;
+
+#[derive(Debug, Deserialize)]
+struct GithubPullRequest {
+    #[serde(rename = "number")]
+    number: i32,
+    #[serde(rename = "title")]
+    title: String,
+    #[serde(rename = "body")]
+    body: Option<String>,
+    #[serde(rename = "head")]
+    head: GithubReference,
+    #[serde(rename = "base")]
+    base: GithubReference,
+    #[serde(rename = "mergeable", default)]
+    mergeable: Option<bool>,
+    #[serde(rename = "draft", default)]
+    draft: bool,
+    #[serde(rename = "labels", default)]
+    labels: Vec<GithubLabel>,
+    #[serde(rename = "created_at", default)]
+    created_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubLabel {
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubReference {
+    #[serde(rename = "ref")]
+    r#ref: String,
+    #[serde(rename = "label")]
+    label: String,
+    #[serde(rename = "sha", default)]
+    sha: Option<String>,
+}
+
+impl GithubPullRequest {
+    fn into_pull_request(self, url: String) -> PullRequest {
+        PullRequest {
+            number: self.number,
+            title: self.title,
+            url,
+            head_owner: self
+                .head
+                .label
+                .split_once(':')
+                .map(|(owner, _)| owner.to_string()),
+            head: self.head.r#ref,
+            head_sha: self.head.sha,
+            base: self.base.r#ref,
+            body: self.body.unwrap_or_default(),
+            mergeable: self.mergeable,
+            draft: self.draft,
+            labels: self.labels.into_iter().map(|label| label.name).collect(),
+            checks: None,
+            review: None,
+            created_at: self.created_at,
+            diff_stat: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEvent {
+    #[serde(rename = "action")]
+    action: String,
+    #[serde(rename = "pull_request")]
+    pull_request: GithubEventPullRequest,
+    #[serde(rename = "repository")]
+    repository: GithubEventRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEventPullRequest {
+    #[serde(rename = "html_url")]
+    html_url: String,
+    #[serde(flatten)]
+    pull_request: GithubPullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubEventRepository {
+    #[serde(rename = "full_name")]
+    full_name: String,
+}
+
+/// PullRequestEvent is the part of a `pull_request` webhook payload, as
+/// handed to GitHub Actions workflows in GITHUB_EVENT_PATH, that jjstack
+/// acts on.
+#[derive(Debug)]
+pub struct PullRequestEvent {
+    pub action: String,
+    /// Base repository as OWNER/REPO.
+    pub repo: String,
+    pub pull_request: PullRequest,
+}
+
+/// Reads a `pull_request` event payload.
+pub fn read_event(path: &Path) -> Result<PullRequestEvent, JjstackError> {
+    let payload = fs::read_to_string(path)
+        .map_err(|e| format!("cannot read event payload {}: {}", path.display(), e))?;
+    let event: GithubEvent = serde_json::from_str(&payload).map_err(|e| {
+        format!(
+            "cannot parse {} as a pull_request event: {}",
+            path.display(),
+            e
+        )
+    })?;
+    let pull_request = event.pull_request;
+    Ok(PullRequestEvent {
+        action: event.action,
+        repo: event.repository.full_name,
+        pull_request: pull_request
+            .pull_request
+            .into_pull_request(pull_request.html_url),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubComment {
+    #[serde(rename = "id")]
+    id: u64,
+    #[serde(rename = "body")]
+    body: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubRequestedReviewers {
+    #[serde(rename = "users", default)]
+    users: Vec<GithubUser>,
+    #[serde(rename = "teams", default)]
+    teams: Vec<GithubTeam>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubUser {
+    #[serde(rename = "login")]
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubTeam {
+    #[serde(rename = "slug")]
+    slug: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubRepository {
+    #[serde(rename = "default_branch")]
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlResponse<T> {
+    #[serde(rename = "data")]
+    data: Option<T>,
+    #[serde(rename = "errors", default)]
+    errors: Vec<GraphqlError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlError {
+    #[serde(rename = "message")]
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenPrsData {
+    #[serde(rename = "repository")]
+    repository: GraphqlRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlRepository {
+    #[serde(rename = "pullRequests")]
+    pull_requests: GraphqlConnection,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlConnection {
+    #[serde(rename = "nodes")]
+    nodes: Vec<GraphqlPullRequest>,
+    #[serde(rename = "pageInfo")]
+    page_info: GraphqlPageInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlPageInfo {
+    #[serde(rename = "hasNextPage")]
+    has_next_page: bool,
+    #[serde(rename = "endCursor")]
+    end_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlPullRequest {
+    #[serde(rename = "number")]
+    number: i32,
+    #[serde(rename = "title")]
+    title: String,
+    #[serde(rename = "body")]
+    body: String,
+    #[serde(rename = "headRefName")]
+    head_ref_name: String,
+    #[serde(rename = "headRefOid", default)]
+    head_ref_oid: Option<String>,
+    #[serde(rename = "headRepositoryOwner")]
+    head_repository_owner: Option<GraphqlActor>,
+    #[serde(rename = "baseRefName")]
+    base_ref_name: String,
+    #[serde(rename = "mergeable")]
+    mergeable: String,
+    #[serde(rename = "isDraft")]
+    is_draft: bool,
+    #[serde(rename = "labels")]
+    labels: GraphqlLabels,
+    #[serde(rename = "reviewDecision")]
+    review_decision: Option<String>,
+    #[serde(rename = "commits")]
+    commits: GraphqlCommits,
+    #[serde(rename = "createdAt", default)]
+    created_at: Option<String>,
+    #[serde(rename = "additions", default)]
+    additions: Option<u64>,
+    #[serde(rename = "deletions", default)]
+    deletions: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequiredStatusChecks {
+    #[serde(rename = "contexts", default)]
+    contexts: Vec<String>,
+    #[serde(rename = "checks", default)]
+    checks: Vec<RequiredCheck>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequiredCheck {
+    #[serde(rename = "context")]
+    context: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRuns {
+    #[serde(rename = "check_runs", default)]
+    check_runs: Vec<CheckRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRun {
+    #[serde(rename = "name")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CombinedStatus {
+    #[serde(rename = "statuses", default)]
+    statuses: Vec<RequiredCheck>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeQueueData {
+    #[serde(rename = "repository")]
+    repository: MergeQueueRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct MergeQueueRepository {
+    #[serde(rename = "mergeQueue")]
+    merge_queue: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueueStateData {
+    #[serde(rename = "repository")]
+    repository: QueueStateRepository,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueueStateRepository {
+    #[serde(rename = "pullRequest")]
+    pull_request: QueueStatePullRequest,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueueStatePullRequest {
+    #[serde(rename = "id")]
+    id: String,
+    #[serde(rename = "state")]
+    state: String,
+    #[serde(rename = "mergeQueueEntry")]
+    merge_queue_entry: Option<GraphqlQueueEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlQueueEntry {
+    #[serde(rename = "position")]
+    position: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlActor {
+    #[serde(rename = "login")]
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlLabels {
+    #[serde(rename = "nodes")]
+    nodes: Vec<GithubLabel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlCommits {
+    #[serde(rename = "totalCount", default)]
+    total_count: Option<u64>,
+    #[serde(rename = "nodes")]
+    nodes: Vec<GraphqlCommitNode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlCommitNode {
+    #[serde(rename = "commit")]
+    commit: GraphqlCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlCommit {
+    #[serde(rename = "statusCheckRollup")]
+    status_check_rollup: Option<GraphqlStatusCheckRollup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlStatusCheckRollup {
+    #[serde(rename = "state")]
+    state: String,
+}
+
+impl GraphqlPullRequest {
+    fn into_pull_request(self, url: String) -> PullRequest {
+        let diff_stat = match (self.additions, self.deletions) {
+            (Some(additions), Some(deletions)) => Some(DiffStat {
+                additions,
+                deletions,
+                commits: self.commits.total_count,
+            }),
+            _ => None,
+        };
+        PullRequest {
+            number: self.number,
+            title: self.title,
+            url,
+            head: self.head_ref_name,
+            head_owner: self.head_repository_owner.map(|owner| owner.login),
+            head_sha: self.head_ref_oid,
+            base: self.base_ref_name,
+            body: self.body,
+            draft: self.is_draft,
+            labels: self.labels.nodes.into_iter().map(|l| l.name).collect(),
+            mergeable: match self.mergeable.as_str() {
+                "MERGEABLE" => Some(true),
+                "CONFLICTING" => Some(false),
+                _ => None,
+            },
+            checks: self
+                .commits
+                .nodes
+                .into_iter()
+                .next()
+                .and_then(|node| node.commit.status_check_rollup)
+                .map(|rollup| match rollup.state.as_str() {
+                    "SUCCESS" => CheckState::Success,
+                    "FAILURE" | "ERROR" => CheckState::Failure,
+                    _ => CheckState::Pending,
+                }),
+            review: match self.review_decision.as_deref() {
+                Some("APPROVED") => Some(ReviewState::Approved),
+                Some("CHANGES_REQUESTED") => Some(ReviewState::ChangesRequested),
+                Some("REVIEW_REQUIRED") => Some(ReviewState::ReviewRequired),
+                _ => None,
+            },
+            created_at: self.created_at,
+            diff_stat,
+        }
+    }
+}
+
+/// GithubEndpoints holds the base URLs of github.com or of a GitHub
+/// Enterprise Server instance.
+#[derive(Clone, Debug)]
+pub struct GithubEndpoints {
+    pub api_url: String,
+    pub graphql_url: String,
+    pub web_url: String,
+}
+
+impl GithubEndpoints {
+    /// Derives the endpoints from the web host (e.g. github.example.com),
+    /// letting explicit API and web URLs take precedence.
+    pub fn new(host: Option<&str>, api_url: Option<&str>, web_url: Option<&str>) -> Self {
+        let host = host.filter(|h| *h != "github.com");
+        let api_url = match (api_url, host) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Some(host)) => format!("https://{}/api/v3", host),
+            (None, None) => API_URL.to_string(),
+        };
+        let graphql_url = match api_url.strip_suffix("/api/v3") {
+            Some(base) => format!("{}/api/graphql", base),
+            None => format!("{}/graphql", api_url),
+        };
+        let web_url = match (web_url, host) {
+            (Some(url), _) => url.trim_end_matches('/').to_string(),
+            (None, Some(host)) => format!("https://{}", host),
+            (None, None) => WEB_URL.to_string(),
+        };
+        GithubEndpoints {
+            api_url,
+            graphql_url,
+            web_url,
+        }
+    }
+}
+
+/// GithubApp authenticates as an installation of a GitHub App: a JWT signed
+/// with the app's private key is traded for an installation token, renewed
+/// before it expires.
+pub struct GithubApp {
+    app_id: u64,
+    installation_id: Option<u64>,
+    key: RsaKeyPair,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+/// Installation tokens are valid for an hour; they are renewed earlier so
+/// that no request is made with one about to expire.
+const APP_TOKEN_LIFETIME: Duration = Duration::from_secs(50 * 60);
+
+#[derive(Deserialize)]
+struct GithubInstallation {
+    #[serde(rename = "id")]
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct GithubInstallationToken {
+    #[serde(rename = "token")]
+    token: String,
+}
+
+impl GithubApp {
+    /// Reads the app's private key from pem, in PKCS#1 or PKCS#8 form. The
+    /// installation is looked up from the repository when not given.
+    pub fn new(app_id: u64, installation_id: Option<u64>, pem: &str) -> Result<Self, JjstackError> {
+        let begin = pem
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("-----BEGIN "))
+            .ok_or("cannot read GitHub App private key: not a PEM file")?;
+        let der: String = pem
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+            .collect();
+        let der = STANDARD
+            .decode(der)
+            .map_err(|e| format!("cannot read GitHub App private key: {}", e))?;
+        let key = if begin.contains("RSA PRIVATE KEY") {
+            RsaKeyPair::from_der(&der)
+        } else {
+            RsaKeyPair::from_pkcs8(&der)
+        }
+        .map_err(|e| format!("cannot read GitHub App private key: {}", e))?;
+        Ok(GithubApp {
+            app_id,
+            installation_id,
+            key,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Returns a JWT identifying the app for the next few minutes, backdated
+    /// a minute to allow for clock drift.
+    fn jwt(&self) -> Result<String, JjstackError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_secs();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+        let claims = json!({ "iat": now - 60, "exp": now + 540, "iss": self.app_id.to_string() });
+        let message = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(claims.to_string()));
+        let mut signature = vec![0; self.key.public().modulus_len()];
+        self.key
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|_| "cannot sign GitHub App JWT")?;
+        Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+    }
+}
+
+enum GithubAuth {
+    Token(String),
+    App(Box<GithubApp>),
+}
+
+/// GithubClient talks to the GitHub REST and GraphQL APIs directly,
+/// authenticating as app, when given, or else with the token found in
+/// GITHUB_TOKEN or GH_TOKEN. When fork_owner is set, head branches are looked
+/// up in that owner's fork. REST responses are kept in cache, when given, and
+/// revalidated by ETag.
+pub struct GithubClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    pacer: Pacer,
+    endpoints: GithubEndpoints,
+    auth: GithubAuth,
+    repo: String,
+    fork_owner: Option<String>,
+    cache: Option<Cache>,
+}
+
+impl GithubClient {
+    pub fn new(
+        repo: String,
+        endpoints: GithubEndpoints,
+        retry: RetryPolicy,
+        fork_owner: Option<String>,
+        cache: Option<Cache>,
+        app: Option<GithubApp>,
+    ) -> Result<Self, JjstackError> {
+        let auth = match app {
+            Some(app) => GithubAuth::App(Box::new(app)),
+            None => GithubAuth::Token(
+                env::var("GITHUB_TOKEN")
+                    .or_else(|_| env::var("GH_TOKEN"))
+                    .map_err(|_| {
+                        JjstackError::ConfigError(
+                            "missing GitHub token: set GITHUB_TOKEN or GH_TOKEN".to_string(),
+                        )
+                    })?,
+            ),
+        };
+        Ok(GithubClient {
+            client: http::new_client(),
+            retry,
+            pacer: Pacer::new(retry.write_interval),
+            endpoints,
+            auth,
+            repo,
+            fork_owner,
+            cache,
+        })
+    }
+
+    /// Returns the token requests are authenticated with, fetching a new
+    /// installation token when authenticating as a GitHub App.
+    async fn token(&self) -> Result<String, JjstackError> {
+        let app = match &self.auth {
+            GithubAuth::Token(token) => return Ok(token.to_string()),
+            GithubAuth::App(app) => app,
+        };
+        let mut cached = app.token.lock().await;
+        if let Some((token, expires)) = &*cached {
+            if Instant::now() < *expires {
+                return Ok(token.to_string());
+            }
+        }
+        let jwt = app.jwt()?;
+        let installation_id = match app.installation_id {
+            Some(id) => id,
+            None => {
+                let url = format!(
+                    "{}/repos/{}/installation",
+                    self.endpoints.api_url, self.repo
+                );
+                let installation: GithubInstallation =
+                    http::send(&self.retry, &format!("GET {}", url), || {
+                        self.client
+                            .get(&url)
+                            .header("Accept", "application/vnd.github+json")
+                            .header("Authorization", format!("Bearer {}", jwt))
+                    })
+                    .await
+                    .map_err(|e| format!("cannot find the GitHub App installation: {}", e))?
+                    .json()
+                    .await?;
+                installation.id
+            }
+        };
+        let url = format!(
+            "{}/app/installations/{}/access_tokens",
+            self.endpoints.api_url, installation_id
+        );
+        // A token nobody receives merely expires, so asking again is safe.
+        let token: GithubInstallationToken =
+            http::send_repeatable(&self.retry, &format!("POST {}", url), || {
+                self.client
+                    .post(&url)
+                    .header("Accept", "application/vnd.github+json")
+                    .header("Authorization", format!("Bearer {}", jwt))
+            })
+            .await
+            .map_err(|e| format!("cannot get a GitHub App installation token: {}", e))?
+            .json()
+            .await?;
+        *cached = Some((token.token.clone(), Instant::now() + APP_TOKEN_LIFETIME));
+        Ok(token.token)
+    }
+
+    /// Fetches url, revalidating the cached copy with If-None-Match when
+    /// there is one. Returns the body and the URL of the next page.
+    async fn get(&self, url: &str) -> Result<(String, Option<String>), JjstackError> {
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(url));
+        let token = self.token().await?;
+        let resp = http::send(&self.retry, &format!("GET {}", url), || {
+            let mut req = self
+                .client
+                .get(url)
+                .header("Accept", "application/vnd.github+json")
+                .header("Authorization", format!("Bearer {}", token));
+            if let Some(entry) = &cached {
+                req = req.header("If-None-Match", &entry.etag);
+            }
+            req
+        })
+        .await?;
+        if let (304, Some(entry)) = (resp.status().as_u16(), cached) {
+            return Ok((entry.body, entry.next));
+        }
+        let next = resp
+            .headers()
+            .get("link")
+            .and_then(|v| v.to_str().ok())
+            .and_then(next_page_url);
+        let etag = resp
+            .headers()
+            .get("etag")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let body = resp.text().await?;
+        if let (Some(cache), Some(etag)) = (&self.cache, etag) {
+            cache.put(&Entry {
+                url: url.to_string(),
+                etag,
+                body: body.clone(),
+                next: next.clone(),
+            });
+        }
+        Ok((body, next))
+    }
+
+    fn pr_url(&self, number: i32) -> String {
+        format!("{}/{}/pull/{}", self.endpoints.web_url, self.repo, number)
+    }
+
+    async fn graphql<T: serde::de::DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<T, JjstackError> {
+        let url = &self.endpoints.graphql_url;
+        let data = json!({ "query": query, "variables": variables });
+        let token = self.token().await?;
+        let what = format!("POST {}", url);
+        let call = || {
+            self.client
+                .post(url)
+                .header("Authorization", format!("Bearer {}", token))
+                .json(&data)
+        };
+        // Queries only read, so they are retried, unlike mutations.
+        let resp = if query.trim_start().starts_with("mutation") {
+            http::send(&self.retry, &what, call).await?
+        } else {
+            http::send_repeatable(&self.retry, &what, call).await?
+        };
+        let resp: GraphqlResponse<T> = resp.json().await?;
+        if let Some(err) = resp.errors.first() {
+            return Err(format!("GraphQL query failed: {}", err.message).into());
+        }
+        resp.data
+            .ok_or_else(|| "GraphQL response without data".into())
+    }
+
+    fn owner_and_name(&self) -> Result<(&str, &str), JjstackError> {
+        self.repo
+            .split_once('/')
+            .ok_or_else(|| format!("invalid repository name: {:?}", self.repo).into())
+    }
+
+    async fn queue_state(&self, number: i32) -> Result<QueueStatePullRequest, JjstackError> {
+        let (owner, name) = self.owner_and_name()?;
+        let variables = json!({ "owner": owner, "name": name, "number": number });
+        let data: QueueStateData = self.graphql(QUEUE_STATE_QUERY, variables).await?;
+        Ok(data.repository.pull_request)
+    }
+
+    fn pr_api_url(&self, number: i32) -> String {
+        format!(
+            "{}/repos/{}/pulls/{}",
+            self.endpoints.api_url, self.repo, number
+        )
+    }
+
+    fn comments_url(&self) -> String {
+        format!(
+            "{}/repos/{}/issues/comments",
+            self.endpoints.api_url, self.repo
+        )
+    }
+
+    async fn send_json(
+        &self,
+        method: &str,
+        url: &str,
+        data: serde_json::Value,
+    ) -> Result<(), JjstackError> {
+        let token = self.token().await?;
+        http::send_paced(
+            &self.retry,
+            &self.pacer,
+            &format!("{} {}", method, url),
+            || {
+                let req = match method {
+                    "POST" => self.client.post(url),
+                    "PUT" => self.client.put(url),
+                    _ => self.client.patch(url),
+                };
+                req.header("Accept", "application/vnd.github+json")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .json(&data)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, url: &str) -> Result<(), JjstackError> {
+        let token = self.token().await?;
+        http::send_paced(&self.retry, &self.pacer, &format!("DELETE {}", url), || {
+            self.client
+                .delete(url)
+                .header("Accept", "application/vnd.github+json")
+                .header("Authorization", format!("Bearer {}", token))
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+impl AsyncForge for GithubClient {
+    fn max_body_len(&self) -> Option<usize> {
+        Some(MAX_BODY_LEN)
+    }
+
+    async fn list_open_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let (owner, name) = self.owner_and_name()?;
+        let mut cursor: Option<String> = None;
+        let mut prs = Vec::new();
+        loop {
+            let variables = json!({ "owner": owner, "name": name, "cursor": cursor });
+            let data: OpenPrsData = self.graphql(OPEN_PRS_QUERY, variables).await?;
+            let conn = data.repository.pull_requests;
+            for node in conn.nodes {
+                let url = self.pr_url(node.number);
+                prs.push(node.into_pull_request(url));
+            }
+            if !conn.page_info.has_next_page {
+                break;
+            }
+            cursor = conn.page_info.end_cursor;
+        }
+        Ok(prs)
+    }
+
+    async fn get_pr_body(&self, number: i32) -> Result<String, JjstackError> {
+        let url = self.pr_api_url(number);
+        let gh_pr: GithubPullRequest = serde_json::from_str(&self.get(&url).await?.0)?;
+        Ok(gh_pr.body.unwrap_or_default())
+    }
+
+    async fn update_pr_body(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        self.send_json("PATCH", &self.pr_api_url(number), json!({ "body": body }))
+            .await
+    }
+
+    async fn update_pr_title(
+        &self,
+        number: i32,
+        title: String,
+        body: Option<String>,
+    ) -> Result<(), JjstackError> {
+        let mut data = json!({ "title": title });
+        if let Some(body) = body {
+            data["body"] = json!(body);
+        }
+        self.send_json("PATCH", &self.pr_api_url(number), data)
+            .await
+    }
+
+    async fn find_closed_pr(&self, head: &str) -> Result<Option<PullRequest>, JjstackError> {
+        let owner = match &self.fork_owner {
+            Some(owner) => owner.as_str(),
+            None => self.repo.split('/').next().unwrap_or_default(),
+        };
+        let url = format!(
+            "{}/repos/{}/pulls?state=closed&sort=updated&direction=desc&head={}:{}",
+            self.endpoints.api_url,
+            self.repo,
+            http::encode_path_segment(owner),
+            http::encode_path_segment(head)
+        );
+        let gh_prs: Vec<GithubPullRequest> = serde_json::from_str(&self.get(&url).await?.0)?;
+        Ok(gh_prs.into_iter().next().map(|gh| {
+            let url = self.pr_url(gh.number);
+            gh.into_pull_request(url)
+        }))
+    }
+
+    async fn list_closed_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let url = format!(
+            "{}/repos/{}/pulls?state=closed&sort=updated&direction=desc&per_page=100",
+            self.endpoints.api_url, self.repo
+        );
+        let gh_prs: Vec<GithubPullRequest> = serde_json::from_str(&self.get(&url).await?.0)?;
+        Ok(gh_prs
+            .into_iter()
+            .map(|gh| {
+                let url = self.pr_url(gh.number);
+                gh.into_pull_request(url)
+            })
+            .collect())
+    }
+
+    async fn update_pr_base(&self, number: i32, base: String) -> Result<(), JjstackError> {
+        self.send_json("PATCH", &self.pr_api_url(number), json!({ "base": base }))
+            .await
+    }
+
+    async fn branch_exists(&self, branch: &str) -> Result<bool, JjstackError> {
+        // The ref is a path of its own, whose slashes stay as they are.
+        let branch: Vec<String> = branch.split('/').map(http::encode_path_segment).collect();
+        let url = format!(
+            "{}/repos/{}/git/ref/heads/{}",
+            self.endpoints.api_url,
+            self.repo,
+            branch.join("/")
+        );
+        let token = self.token().await?;
+        let resp = http::send_optional(&self.retry, &format!("GET {}", url), || {
+            self.client
+                .get(&url)
+                .header("Accept", "application/vnd.github+json")
+                .header("Authorization", format!("Bearer {}", token))
+        })
+        .await?;
+        Ok(resp.is_some())
+    }
+
+    async fn default_branch(&self) -> Result<String, JjstackError> {
+        let url = format!("{}/repos/{}", self.endpoints.api_url, self.repo);
+        let repo: GithubRepository = serde_json::from_str(&self.get(&url).await?.0)?;
+        Ok(repo.default_branch)
+    }
+
+    async fn list_pr_comments(&self, number: i32) -> Result<Vec<Comment>, JjstackError> {
+        let mut url = format!(
+            "{}/repos/{}/issues/{}/comments?per_page=100",
+            self.endpoints.api_url, self.repo, number
+        );
+        let mut comments = Vec::new();
+        loop {
+            let (body, next) = self.get(&url).await?;
+            let gh_comments: Vec<GithubComment> = serde_json::from_str(&body)?;
+            comments.extend(gh_comments.into_iter().map(|c| Comment {
+                id: c.id,
+                body: c.body.unwrap_or_default(),
+            }));
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(comments)
+    }
+
+    async fn create_pr_comment(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        let url = format!(
+            "{}/repos/{}/issues/{}/comments",
+            self.endpoints.api_url, self.repo, number
+        );
+        self.send_json("POST", &url, json!({ "body": body })).await
+    }
+
+    async fn update_pr_comment(
+        &self,
+        _number: i32,
+        id: u64,
+        body: String,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/{}", self.comments_url(), id);
+        self.send_json("PATCH", &url, json!({ "body": body })).await
+    }
+
+    async fn delete_pr_comment(&self, _number: i32, id: u64) -> Result<(), JjstackError> {
+        self.delete(&format!("{}/{}", self.comments_url(), id))
+            .await
+    }
+
+    async fn set_pr_labels(&self, number: i32, labels: Vec<String>) -> Result<(), JjstackError> {
+        let url = format!(
+            "{}/repos/{}/issues/{}/labels",
+            self.endpoints.api_url, self.repo, number
+        );
+        self.send_json("PUT", &url, json!({ "labels": labels }))
+            .await
+    }
+
+    async fn list_requested_reviewers(&self, number: i32) -> Result<Vec<String>, JjstackError> {
+        let url = format!("{}/requested_reviewers", self.pr_api_url(number));
+        let requested: GithubRequestedReviewers = serde_json::from_str(&self.get(&url).await?.0)?;
+        // Teams always belong to the organization owning the repository.
+        let org = self.repo.split('/').next().unwrap_or_default();
+        let users = requested.users.into_iter().map(|user| user.login);
+        let teams = requested
+            .teams
+            .into_iter()
+            .map(|team| format!("{}/{}", org, team.slug));
+        Ok(users.chain(teams).collect())
+    }
+
+    async fn request_reviewers(
+        &self,
+        number: i32,
+        reviewers: Vec<String>,
+    ) -> Result<(), JjstackError> {
+        let (teams, users): (Vec<String>, Vec<String>) =
+            reviewers.into_iter().partition(|r| r.contains('/'));
+        let teams: Vec<&str> = teams
+            .iter()
+            .filter_map(|team| team.split_once('/').map(|(_, slug)| slug))
+            .collect();
+        let url = format!("{}/requested_reviewers", self.pr_api_url(number));
+        self.send_json(
+            "POST",
+            &url,
+            json!({ "reviewers": users, "team_reviewers": teams }),
+        )
+        .await
+    }
+
+    async fn create_pr(
+        &self,
+        head: &str,
+        base: &str,
+        title: String,
+        body: String,
+        draft: bool,
+    ) -> Result<(), JjstackError> {
+        let head = match &self.fork_owner {
+            Some(owner) => format!("{}:{}", owner, head),
+            None => head.to_string(),
+        };
+        let url = format!("{}/repos/{}/pulls", self.endpoints.api_url, self.repo);
+        let data = json!({
+            "head": head,
+            "base": base,
+            "title": title,
+            "body": body,
+            "draft": draft,
+        });
+        self.send_json("POST", &url, data).await
+    }
+
+    async fn merge_pr(&self, number: i32, method: MergeMethod) -> Result<(), JjstackError> {
+        let merge_method = match method {
+            MergeMethod::Merge => "merge",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+        };
+        let url = format!("{}/merge", self.pr_api_url(number));
+        self.send_json("PUT", &url, json!({ "merge_method": merge_method }))
+            .await
+    }
+
+    async fn has_merge_queue(&self, branch: &str) -> Result<bool, JjstackError> {
+        let (owner, name) = self.owner_and_name()?;
+        let variables = json!({ "owner": owner, "name": name, "branch": branch });
+        let data: MergeQueueData = self.graphql(MERGE_QUEUE_QUERY, variables).await?;
+        Ok(data.repository.merge_queue.is_some())
+    }
+
+    async fn enqueue_pr(&self, number: i32) -> Result<(), JjstackError> {
+        let pr = self.queue_state(number).await?;
+        let _: serde_json::Value = self
+            .graphql(ENQUEUE_MUTATION, json!({ "id": pr.id }))
+            .await?;
+        Ok(())
+    }
+
+    async fn merge_queue_state(&self, number: i32) -> Result<QueueState, JjstackError> {
+        let pr = self.queue_state(number).await?;
+        Ok(match (pr.state.as_str(), pr.merge_queue_entry) {
+            ("MERGED", _) => QueueState::Merged,
+            (_, Some(entry)) => QueueState::Queued(entry.position),
+            (_, None) => QueueState::NotQueued,
+        })
+    }
+
+    async fn required_checks(&self, branch: &str) -> Result<Option<Vec<String>>, JjstackError> {
+        let url = format!(
+            "{}/repos/{}/branches/{}/protection/required_status_checks",
+            self.endpoints.api_url,
+            self.repo,
+            http::encode_path_segment(branch)
+        );
+        let required: RequiredStatusChecks = match self.get(&url).await {
+            Ok((body, _)) => serde_json::from_str(&body)?,
+            // The branch is not protected, or requires no status checks.
+            Err(JjstackError::ApiError { status: 404, .. }) => return Ok(Some(Vec::new())),
+            // Reading protection rules takes admin rights on the repository.
+            Err(JjstackError::ApiError { status: 403, .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut contexts = required.contexts;
+        for check in required.checks {
+            if !contexts.contains(&check.context) {
+                contexts.push(check.context);
+            }
+        }
+        Ok(Some(contexts))
+    }
+
+    async fn reported_checks(&self, branch: &str) -> Result<Vec<String>, JjstackError> {
+        let url = format!(
+            "{}/repos/{}/commits/{}/check-runs?per_page=100",
+            self.endpoints.api_url,
+            self.repo,
+            http::encode_path_segment(branch)
+        );
+        let runs: CheckRuns = serde_json::from_str(&self.get(&url).await?.0)?;
+        let url = format!(
+            "{}/repos/{}/commits/{}/status?per_page=100",
+            self.endpoints.api_url,
+            self.repo,
+            http::encode_path_segment(branch)
+        );
+        let status: CombinedStatus = serde_json::from_str(&self.get(&url).await?.0)?;
+        Ok(runs
+            .check_runs
+            .into_iter()
+            .map(|run| run.name)
+            .chain(status.statuses.into_iter().map(|status| status.context))
+            .collect())
+    }
+}
+
+/// Reads the default repository (OWNER/REPO) configured with
+/// `gh repo set-default`.
+pub fn default_repo() -> Result<String, JjstackError> {
+    let out = command::output(command::new("gh").args(["repo", "set-default", "--view"]))?;
+    if !out.status.success() {
+        return Err(JjstackError::GhCommandFailed {
+            command: "gh repo set-default --view".to_string(),
+            message: String::from_utf8_lossy(&out.stderr).into_owned(),
+        });
+    }
+    let repo = command::stdout(out)?.trim().to_string();
+    // On GitHub Enterprise Server the default repository is reported as
+    // HOST/OWNER/REPO.
+    match repo.splitn(3, '/').collect::<Vec<_>>()[..] {
+        [_, owner, name] => Ok(format!("{}/{}", owner, name)),
+        _ => Ok(repo),
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::JjstackError;
+use crate::forge::{next_page_url, AsyncForge, Comment, MergeMethod};
+use crate::http::{self, Pacer, RetryPolicy};
+use crate::PullRequest;
+
+/// Longest merge request description GitLab accepts, in characters.
+const MAX_BODY_LEN: usize = 1_048_576;
+
+#[derive(Debug, Deserialize)]
+struct GitlabMergeRequest {
+    #[serde(rename = "iid")]
+    iid: i32,
+    #[serde(rename = "title")]
+    title: String,
+    #[serde(rename = "web_url")]
+    web_url: String,
+    #[serde(rename = "description")]
+    description: Option<String>,
+    #[serde(rename = "source_branch")]
+    source_branch: String,
+    #[serde(rename = "sha", default)]
+    sha: Option<String>,
+    #[serde(rename = "target_branch")]
+    target_branch: String,
+    #[serde(rename = "has_conflicts", default)]
+    has_conflicts: bool,
+    #[serde(rename = "draft", default)]
+    draft: bool,
+    #[serde(rename = "labels", default)]
+    labels: Vec<String>,
+    #[serde(rename = "created_at", default)]
+    created_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabNote {
+    #[serde(rename = "id")]
+    id: u64,
+    #[serde(rename = "body")]
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabProject {
+    #[serde(rename = "default_branch")]
+    default_branch: String,
+}
+
+impl GitlabMergeRequest {
+    fn into_pull_request(self) -> PullRequest {
+        PullRequest {
+            number: self.iid,
+            title: self.title,
+            url: self.web_url,
+            head: self.source_branch,
+            head_owner: None,
+            head_sha: self.sha,
+            base: self.target_branch,
+            body: self.description.unwrap_or_default(),
+            mergeable: Some(!self.has_conflicts),
+            draft: self.draft,
+            labels: self.labels,
+            checks: None,
+            review: None,
+            created_at: self.created_at,
+            diff_stat: None,
+        }
+    }
+}
+
+/// GitlabClient talks to the GitLab REST API (v4), authenticating with the
+/// token found in GITLAB_TOKEN.
+pub struct GitlabClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    pacer: Pacer,
+    token: String,
+    api_url: String,
+    project: String,
+}
+
+impl GitlabClient {
+    pub fn new(host: String, project: String, retry: RetryPolicy) -> Result<Self, JjstackError> {
+        let token = env::var("GITLAB_TOKEN").map_err(|_| {
+            JjstackError::ConfigError("missing GitLab token: set GITLAB_TOKEN".to_string())
+        })?;
+        Ok(GitlabClient {
+            client: http::new_client(),
+            retry,
+            pacer: Pacer::new(retry.write_interval),
+            token,
+            api_url: format!("https://{}/api/v4", host),
+            project: project.replace('/', "%2F"),
+        })
+    }
+
+    fn merge_request_url(&self, iid: i32) -> String {
+        format!(
+            "{}/projects/{}/merge_requests/{}",
+            self.api_url, self.project, iid
+        )
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, JjstackError> {
+        http::send(&self.retry, &format!("GET {}", url), || {
+            self.client.get(url).header("PRIVATE-TOKEN", &self.token)
+        })
+        .await
+    }
+
+    async fn send_json(
+        &self,
+        method: &str,
+        url: &str,
+        data: serde_json::Value,
+    ) -> Result<(), JjstackError> {
+        http::send_paced(
+            &self.retry,
+            &self.pacer,
+            &format!("{} {}", method, url),
+            || {
+                let req = match method {
+                    "POST" => self.client.post(url),
+                    _ => self.client.put(url),
+                };
+                req.header("PRIVATE-TOKEN", &self.token).json(&data)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, url: &str) -> Result<(), JjstackError> {
+        http::send_paced(&self.retry, &self.pacer, &format!("DELETE {}", url), || {
+            self.client.delete(url).header("PRIVATE-TOKEN", &self.token)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+impl AsyncForge for GitlabClient {
+    fn max_body_len(&self) -> Option<usize> {
+        Some(MAX_BODY_LEN)
+    }
+
+    async fn list_open_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let mut url = format!(
+            "{}/projects/{}/merge_requests?state=opened&per_page=100",
+            self.api_url, self.project
+        );
+        let mut prs = Vec::new();
+        loop {
+            let resp = self.get(&url).await?;
+            let next = resp
+                .headers()
+                .get("link")
+                .and_then(|v| v.to_str().ok())
+                .and_then(next_page_url);
+            let mrs: Vec<GitlabMergeRequest> = resp.json().await?;
+            prs.extend(mrs.into_iter().map(GitlabMergeRequest::into_pull_request));
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(prs)
+    }
+
+    async fn get_pr_body(&self, number: i32) -> Result<String, JjstackError> {
+        let url = self.merge_request_url(number);
+        let mr: GitlabMergeRequest = self.get(&url).await?.json().await?;
+        Ok(mr.description.unwrap_or_default())
+    }
+
+    async fn update_pr_body(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        let url = self.merge_request_url(number);
+        self.send_json("PUT", &url, json!({ "description": body }))
+            .await
+    }
+
+    async fn update_pr_title(
+        &self,
+        number: i32,
+        title: String,
+        body: Option<String>,
+    ) -> Result<(), JjstackError> {
+        let mut data = json!({ "title": title });
+        if let Some(body) = body {
+            data["description"] = json!(body);
+        }
+        self.send_json("PUT", &self.merge_request_url(number), data)
+            .await
+    }
+
+    async fn find_closed_pr(&self, head: &str) -> Result<Option<PullRequest>, JjstackError> {
+        for state in ["merged", "closed"] {
+            let url = format!(
+                "{}/projects/{}/merge_requests?state={}&source_branch={}&order_by=updated_at",
+                self.api_url, self.project, state, head
+            );
+            let mrs: Vec<GitlabMergeRequest> = self.get(&url).await?.json().await?;
+            if let Some(mr) = mrs.into_iter().next() {
+                return Ok(Some(mr.into_pull_request()));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn list_closed_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+        let mut prs = Vec::new();
+        for state in ["merged", "closed"] {
+            let url = format!(
+                "{}/projects/{}/merge_requests?state={}&order_by=updated_at&per_page=100",
+                self.api_url, self.project, state
+            );
+            let mrs: Vec<GitlabMergeRequest> = self.get(&url).await?.json().await?;
+            prs.extend(mrs.into_iter().map(GitlabMergeRequest::into_pull_request));
+        }
+        Ok(prs)
+    }
+
+    async fn update_pr_base(&self, number: i32, base: String) -> Result<(), JjstackError> {
+        let url = self.merge_request_url(number);
+        self.send_json("PUT", &url, json!({ "target_branch": base }))
+            .await
+    }
+
+    async fn branch_exists(&self, branch: &str) -> Result<bool, JjstackError> {
+        let url = format!(
+            "{}/projects/{}/repository/branches/{}",
+            self.api_url,
+            self.project,
+            branch.replace('/', "%2F")
+        );
+        let resp = http::send_optional(&self.retry, &format!("GET {}", url), || {
+            self.client.get(&url).header("PRIVATE-TOKEN", &self.token)
+        })
+        .await?;
+        Ok(resp.is_some())
+    }
+
+    async fn default_branch(&self) -> Result<String, JjstackError> {
+        let url = format!("{}/projects/{}", self.api_url, self.project);
+        let project: GitlabProject = self.get(&url).await?.json().await?;
+        Ok(project.default_branch)
+    }
+
+    async fn list_pr_comments(&self, number: i32) -> Result<Vec<Comment>, JjstackError> {
+        let mut url = format!(
+            "{}/notes?sort=asc&per_page=100",
+            self.merge_request_url(number)
+        );
+        let mut comments = Vec::new();
+        loop {
+            let resp = self.get(&url).await?;
+            let next = resp
+                .headers()
+                .get("link")
+                .and_then(|v| v.to_str().ok())
+                .and_then(next_page_url);
+            let notes: Vec<GitlabNote> = resp.json().await?;
+            comments.extend(notes.into_iter().map(|n| Comment {
+                id: n.id,
+                body: n.body,
+            }));
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(comments)
+    }
+
+    async fn create_pr_comment(&self, number: i32, body: String) -> Result<(), JjstackError> {
+        let url = format!("{}/notes", self.merge_request_url(number));
+        self.send_json("POST", &url, json!({ "body": body })).await
+    }
+
+    async fn update_pr_comment(
+        &self,
+        number: i32,
+        id: u64,
+        body: String,
+    ) -> Result<(), JjstackError> {
+        let url = format!("{}/notes/{}", self.merge_request_url(number), id);
+        self.send_json("PUT", &url, json!({ "body": body })).await
+    }
+
+    async fn delete_pr_comment(&self, number: i32, id: u64) -> Result<(), JjstackError> {
+        self.delete(&format!("{}/notes/{}", self.merge_request_url(number), id))
+            .await
+    }
+
+    async fn set_pr_labels(&self, number: i32, labels: Vec<String>) -> Result<(), JjstackError> {
+        let url = self.merge_request_url(number);
+        self.send_json("PUT", &url, json!({ "labels": labels.join(",") }))
+            .await
+    }
+
+    async fn list_requested_reviewers(&self, _number: i32) -> Result<Vec<String>, JjstackError> {
+        Err("reviewer propagation is not supported on GitLab".into())
+    }
+
+    async fn request_reviewers(
+        &self,
+        _number: i32,
+        _reviewers: Vec<String>,
+    ) -> Result<(), JjstackError> {
+        Err("reviewer propagation is not supported on GitLab".into())
+    }
+
+    async fn create_pr(
+        &self,
+        head: &str,
+        base: &str,
+        title: String,
+        body: String,
+        draft: bool,
+    ) -> Result<(), JjstackError> {
+        let title = if draft {
+            format!("Draft: {}", title)
+        } else {
+            title
+        };
+        let url = format!("{}/projects/{}/merge_requests", self.api_url, self.project);
+        let data = json!({
+            "source_branch": head,
+            "target_branch": base,
+            "title": title,
+            "description": body,
+        });
+        self.send_json("POST", &url, data).await
+    }
+
+    async fn merge_pr(&self, number: i32, method: MergeMethod) -> Result<(), JjstackError> {
+        // Whether merges rebase is a project setting on GitLab, so only
+        // squashing can be asked for.
+        let squash =
+            match method {
+                MergeMethod::Merge => false,
+                MergeMethod::Squash => true,
+                MergeMethod::Rebase => return Err(
+                    "GitLab merges with the project's merge method; use --method merge or squash"
+                        .into(),
+                ),
+            };
+        let url = format!("{}/merge", self.merge_request_url(number));
+        self.send_json("PUT", &url, json!({ "squash": squash }))
+            .await
+    }
+}
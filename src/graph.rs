@@ -0,0 +1,139 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashSet;
+
+use jjstack_core::config::Config;
+use jjstack_core::forge::Forge;
+use jjstack_core::jj::{
+    branch_change_id, get_current_stack_bookmarks, is_stack_bookmark, list_bookmarks, list_commits,
+    BookmarkState,
+};
+use jjstack_core::stack::{build_pr_stacks, select_stacks, CheckState, ReviewState};
+use jjstack_core::PullRequest;
+
+use crate::cli::LogArgs;
+use crate::{get_open_prs, table};
+
+/// Prints each stack top-down, like `jj log`, with the jj commits of every
+/// PR listed under it, along with the state of the PR and whether its
+/// bookmark matches the remotes.
+pub fn run(
+    repo: String,
+    forge: &dyn Forge,
+    args: LogArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("repo: {:?}", repo);
+    let bookmarks: Vec<_> = list_bookmarks(false)?
+        .into_iter()
+        .filter(|b| b.state == BookmarkState::Present && is_stack_bookmark(config, &b.name))
+        .collect();
+    let local: HashSet<String> = bookmarks.iter().map(|b| b.name.clone()).collect();
+    let mut prs = get_open_prs(forge, local.clone(), &repo, config)?;
+    if args.current {
+        prs = select_stacks(prs, &get_current_stack_bookmarks(config)?);
+    }
+    if prs.is_empty() {
+        println!("no matching PRs found for bookmarks.");
+        return Ok(());
+    }
+    let color = table::use_color();
+    let heads: HashSet<String> = prs.iter().map(|pr| pr.head.clone()).collect();
+    for (i, stack) in build_pr_stacks(prs).iter().enumerate() {
+        println!("stack {}:", i + 1);
+        for pr in stack.iter().rev() {
+            let mut notes = pr_states(pr);
+            match bookmarks.iter().find(|b| b.name == pr.head) {
+                Some(b) if b.remotes.is_empty() => notes.push("not pushed".to_string()),
+                Some(b) => notes.extend(
+                    b.remotes
+                        .iter()
+                        .filter(|r| !r.synced)
+                        .map(|r| format!("differs from {}", r.remote)),
+                ),
+                None => {}
+            }
+            let (node, arrow) = if table::ascii() {
+                ("o", "->")
+            } else {
+                ("○", "→")
+            };
+            let line = format!(
+                "{} #{} {:?} {} {} {} [{}]",
+                node,
+                pr.number,
+                pr.title,
+                pr.head,
+                arrow,
+                pr.base,
+                notes.join(", ")
+            );
+            if color {
+                println!("  \x1b[1m{}\x1b[0m", line);
+            } else {
+                println!("  {}", line);
+            }
+            let since = if heads.contains(&pr.base) {
+                revision(config, &local, &pr.base)
+            } else {
+                "trunk()".to_string()
+            };
+            let revset = format!("{}..{}", since, revision(config, &local, &pr.head));
+            for commit in list_commits(&revset)? {
+                let description = match commit.description.as_str() {
+                    "" => "(no description set)",
+                    description => description,
+                };
+                let conflict = if commit.conflict { " (conflict)" } else { "" };
+                let edge = if table::ascii() { "|" } else { "│" };
+                if color {
+                    println!(
+                        "  {}   \x1b[35m{}\x1b[0m {}\x1b[31m{}\x1b[0m",
+                        edge, commit.change_id, description, conflict
+                    );
+                } else {
+                    println!(
+                        "  {}   {} {}{}",
+                        edge, commit.change_id, description, conflict
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Names the commit a PR branch points at in a revset: its bookmark or, for
+/// a branch pushed with `jj git push --change`, its change.
+fn revision(config: &Config, local: &HashSet<String>, branch: &str) -> String {
+    match branch_change_id(config, branch) {
+        Some(change_id) if !local.contains(branch) => change_id.to_string(),
+        _ => format!("{:?}", branch),
+    }
+}
+
+fn pr_states(pr: &PullRequest) -> Vec<String> {
+    let mut states = vec![if pr.draft { "draft" } else { "open" }.to_string()];
+    if pr.mergeable == Some(false) {
+        states.push("conflicts".to_string());
+    }
+    match pr.checks {
+        Some(CheckState::Success) => states.push("CI passing".to_string()),
+        Some(CheckState::Failure) => states.push("CI failing".to_string()),
+        Some(CheckState::Pending) => states.push("CI pending".to_string()),
+        None => {}
+    }
+    match pr.review {
+        Some(ReviewState::Approved) => states.push("approved".to_string()),
+        Some(ReviewState::ChangesRequested) => states.push("changes requested".to_string()),
+        Some(ReviewState::ReviewRequired) => states.push("review required".to_string()),
+        None => {}
+    }
+    states
+}
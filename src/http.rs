@@ -0,0 +1,334 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use reqwest::{Method, RequestBuilder, Response};
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+use crate::error::JjstackError;
+
+/// The runtime driving the forge clients; the CLI blocks on it at the
+/// boundary, from as many threads as it updates stacks on.
+static RUNTIME: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("cannot start the async runtime")
+});
+
+/// Runs a future to completion on the shared runtime, blocking the calling
+/// thread. It must not be called from inside the runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    RUNTIME.block_on(future)
+}
+
+/// How many times a rate-limited request is resumed before giving up.
+const RATE_LIMIT_RETRIES: u32 = 5;
+
+/// RetryPolicy controls how failed API calls are retried: transport errors
+/// and 5xx responses are attempted up to `attempts` times, doubling the
+/// `backoff` delay after each failure. POST requests may have gone through
+/// despite the failure, so they are only retried when sent with
+/// `send_repeatable`. Rate-limited requests are resumed once the limit
+/// resets. Requests changing data are spaced at least
+/// `write_interval` apart, to stay clear of secondary rate limits.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub write_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(500),
+            write_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Pacer spaces requests at least `interval` apart, across threads.
+#[derive(Debug)]
+pub struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub fn new(interval: Duration) -> Self {
+        Pacer {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the next request may be sent.
+    pub async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().await;
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            slot
+        };
+        sleep(slot.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+/// Creates the client the forges send their requests with. Error statuses
+/// come back as regular responses, so that `send` can inspect their headers.
+pub fn new_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent("jjstack")
+        .build()
+        .expect("cannot set up the HTTP client")
+}
+
+/// Sends the request built by `call`, retrying according to `policy`. The
+/// `what` string describes the request in error messages (e.g. "GET url").
+pub async fn send<F>(policy: &RetryPolicy, what: &str, call: F) -> Result<Response, JjstackError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_accepting(policy, None, what, &[], false, call).await
+}
+
+/// Like `send`, but retries POST requests too. Only requests the caller
+/// knows to be safe to repeat, such as GraphQL queries, go through here.
+pub async fn send_repeatable<F>(
+    policy: &RetryPolicy,
+    what: &str,
+    call: F,
+) -> Result<Response, JjstackError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_accepting(policy, None, what, &[], true, call).await
+}
+
+/// Like `send`, but waits for `pacer` before every attempt. Requests changing
+/// data go through here.
+pub async fn send_paced<F>(
+    policy: &RetryPolicy,
+    pacer: &Pacer,
+    what: &str,
+    call: F,
+) -> Result<Response, JjstackError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_accepting(policy, Some(pacer), what, &[], false, call).await
+}
+
+/// Like `send`, but answers None when the resource does not exist.
+pub async fn send_optional<F>(
+    policy: &RetryPolicy,
+    what: &str,
+    call: F,
+) -> Result<Option<Response>, JjstackError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let resp = send_accepting(policy, None, what, &[404], false, call).await?;
+    Ok((resp.status().as_u16() != 404).then_some(resp))
+}
+
+async fn send_accepting<F>(
+    policy: &RetryPolicy,
+    pacer: Option<&Pacer>,
+    what: &str,
+    accepted: &[u16],
+    repeatable: bool,
+    call: F,
+) -> Result<Response, JjstackError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut delay = policy.backoff;
+    let mut attempt = 1;
+    let mut rate_limited = 0;
+    loop {
+        let (client, req) = call().build_split();
+        let req = req.map_err(|e| JjstackError::RequestFailed {
+            request: what.to_string(),
+            message: e.to_string(),
+        })?;
+        let last =
+            attempt >= policy.attempts.max(1) || (*req.method() == Method::POST && !repeatable);
+        if let Some(pacer) = pacer {
+            pacer.wait().await;
+        }
+        let start = Instant::now();
+        let resp = match client.execute(req).await {
+            Ok(resp) => {
+                debug!("{}: {} in {:?}", what, resp.status(), start.elapsed());
+                resp
+            }
+            Err(e) if !last => {
+                warn!("cannot {}: {} (retrying in {:?})", what, e, delay);
+                sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+                continue;
+            }
+            Err(e) => {
+                return Err(JjstackError::RequestFailed {
+                    request: what.to_string(),
+                    message: e.to_string(),
+                })
+            }
+        };
+        let status = resp.status();
+        // 304 Not Modified answers a conditional request whose cached copy
+        // the caller still holds.
+        if status.is_success() || status.as_u16() == 304 || accepted.contains(&status.as_u16()) {
+            // The request went through but exhausted the quota: wait for
+            // the reset here rather than failing the next request.
+            if header(&resp, "x-ratelimit-remaining").as_deref() == Some("0") {
+                if let Some(wait) = retry_after(&resp) {
+                    warn!("rate limited, resuming in {}s", wait.as_secs());
+                    sleep(wait).await;
+                }
+            }
+            return Ok(resp);
+        }
+        if is_rate_limited(&resp) && rate_limited < RATE_LIMIT_RETRIES {
+            let wait = retry_after(&resp).unwrap_or(delay);
+            warn!("rate limited, resuming in {}s", wait.as_secs().max(1));
+            sleep(wait).await;
+            delay *= 2;
+            rate_limited += 1;
+            continue;
+        }
+        if status.is_server_error() && !last {
+            let wait = retry_after(&resp).unwrap_or(delay);
+            warn!("cannot {}: {} (retrying in {:?})", what, status, wait);
+            sleep(wait).await;
+            delay *= 2;
+            attempt += 1;
+            continue;
+        }
+        let body = resp.text().await.unwrap_or_default();
+        return Err(JjstackError::ApiError {
+            request: what.to_string(),
+            status: status.as_u16(),
+            body: body.trim().to_string(),
+        });
+    }
+}
+
+fn is_rate_limited(resp: &Response) -> bool {
+    let status = resp.status().as_u16();
+    if status == 429 {
+        return true;
+    }
+    status == 403
+        && (resp.headers().contains_key("retry-after")
+            || header(resp, "x-ratelimit-remaining").as_deref() == Some("0"))
+}
+
+fn retry_after(resp: &Response) -> Option<Duration> {
+    if let Some(secs) = header(resp, "retry-after").and_then(|v| v.parse().ok()) {
+        return Some(Duration::from_secs(secs));
+    }
+    let reset: u64 = header(resp, "x-ratelimit-reset")?.parse().ok()?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some(Duration::from_secs(reset.saturating_sub(now).max(1)))
+}
+
+fn header(resp: &Response, name: &str) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Percent-encodes text as one segment of a URL path, so that branch names
+/// holding slashes, `#` or `?` do not change the request.
+pub fn encode_path_segment(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Serves 500 to every request, counting them.
+    fn failing_server() -> (String, Arc<AtomicU32>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicU32::new(0));
+        let served = Arc::clone(&count);
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 4096]);
+                served.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                );
+            }
+        });
+        (url, count)
+    }
+
+    #[test]
+    fn send_retries_post_only_when_repeatable() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+            write_interval: Duration::ZERO,
+        };
+        let client = new_client();
+        for (method, repeatable, attempts) in [
+            (Method::GET, false, 3),
+            (Method::POST, false, 1),
+            (Method::POST, true, 3),
+        ] {
+            let (url, count) = failing_server();
+            let call = || client.request(method.clone(), &url);
+            let result = if repeatable {
+                block_on(send_repeatable(&policy, "test", call))
+            } else {
+                block_on(send(&policy, "test", call))
+            };
+            assert!(result.is_err());
+            assert_eq!(count.load(Ordering::SeqCst), attempts, "{}", method);
+        }
+    }
+
+    #[test]
+    fn encode_path_segment_escapes_reserved_characters() {
+        assert_eq!(encode_path_segment("main"), "main");
+        assert_eq!(encode_path_segment("feat/x#1"), "feat%2Fx%231");
+        assert_eq!(encode_path_segment("a b?c%"), "a%20b%3Fc%25");
+        assert_eq!(encode_path_segment("é"), "%C3%A9");
+    }
+}
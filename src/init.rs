@@ -0,0 +1,138 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use jjstack_core::config::{repo_config_path, Config};
+use jjstack_core::forge::{parse_remote_url, ForgeKind};
+use jjstack_core::jj::{get_remote_url, list_remotes};
+
+use crate::cli::InitArgs;
+use crate::connect_forge;
+
+/// Prompt asks questions on the terminal, falling back to the detected
+/// answer when the user just presses enter or when there is no terminal.
+struct Prompt {
+    accept_defaults: bool,
+}
+
+impl Prompt {
+    fn ask(&self, question: &str, default: &str) -> Result<String, Box<dyn std::error::Error>> {
+        if self.accept_defaults {
+            println!("{}: {}", question, default);
+            return Ok(default.to_string());
+        }
+        print!("{} [{}]: ", question, default);
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        let answer = answer.trim();
+        Ok(if answer.is_empty() { default } else { answer }.to_string())
+    }
+
+    fn choose(
+        &self,
+        question: &str,
+        choices: &[&str],
+        default: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let question = format!("{} ({})", question, choices.join(", "));
+        loop {
+            let answer = self.ask(&question, default)?;
+            if choices.contains(&answer.as_str()) {
+                return Ok(answer);
+            }
+            println!("{:?} is not one of {}", answer, choices.join(", "));
+        }
+    }
+}
+
+/// Detects the repository, forge, token and trunk, asks the user to confirm
+/// them along with the navigation style, and writes .jjstack.toml at the
+/// root of the repository.
+pub fn run(args: InitArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let path = repo_config_path()?;
+    if path.exists() && !args.force {
+        return Err(format!(
+            "{} already exists (use --force to overwrite)",
+            path.display()
+        )
+        .into());
+    }
+    let prompt = Prompt {
+        accept_defaults: args.yes || !io::stdin().is_terminal(),
+    };
+
+    let remotes = list_remotes()?;
+    let detected_remote = match &config.remote {
+        Some(remote) => remote.to_string(),
+        None => ["upstream", "origin"]
+            .into_iter()
+            .find(|name| remotes.iter().any(|(remote, _)| remote == name))
+            .or(remotes.first().map(|(name, _)| name.as_str()))
+            .ok_or("no git remotes found: run 'jj git remote add origin URL' first")?
+            .to_string(),
+    };
+    let remote = prompt.ask("git remote holding the PRs", &detected_remote)?;
+    let url = get_remote_url(Some(&remote))?;
+    let (host, repo) = parse_remote_url(&url)
+        .ok_or_else(|| format!("cannot parse URL of remote {:?}: {}", remote, url))?;
+    println!("repository {} on {}", repo, host);
+
+    let detected_forge = config.forge.unwrap_or_else(|| ForgeKind::detect(&host));
+    let forge: ForgeKind = prompt
+        .choose(
+            "forge",
+            &["github", "gitlab", "gitea", "bitbucket", "azure"],
+            detected_forge.name(),
+        )?
+        .parse()?;
+    let tokens = forge.token_vars();
+    match tokens.iter().find(|name| env::var_os(name).is_some()) {
+        Some(name) => println!("token found in {}", name),
+        None => println!(
+            "no token found: export {} before running jjstack",
+            tokens.join(" or ")
+        ),
+    }
+
+    let probe = Config {
+        remote: Some(remote.clone()),
+        forge: Some(forge),
+        host: config.host.clone(),
+        api_url: config.api_url.clone(),
+        web_url: config.web_url.clone(),
+        cache: Some(false),
+        ..Default::default()
+    };
+    let detected_trunk = connect_forge(&probe)
+        .ok()
+        .and_then(|(_, forge)| forge.default_branch().ok())
+        .unwrap_or_else(|| "main".to_string());
+    let trunk = prompt.ask("trunk branch", &detected_trunk)?;
+    let nav_style = prompt.choose("navigation style", &["links", "plain", "mermaid"], "links")?;
+    let prefix = prompt.ask("bookmark prefix, empty for every bookmark", "")?;
+
+    let mut text = String::new();
+    for (key, value) in [
+        ("remote", remote.as_str()),
+        ("forge", forge.name()),
+        ("trunk", trunk.as_str()),
+        ("nav_style", nav_style.as_str()),
+        ("bookmark_prefix", prefix.as_str()),
+    ] {
+        if !value.is_empty() {
+            text.push_str(&format!("{} = {}\n", key, toml::Value::from(value)));
+        }
+    }
+    fs::write(&path, text).map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    println!("wrote {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,1114 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{HashMap, HashSet};
+
+use log::warn;
+
+use crate::command;
+use crate::config::Config;
+use crate::error::JjstackError;
+
+/// Prefix of the branches `jj git push --change` creates, unless
+/// push_bookmark_prefix says otherwise.
+pub const DEFAULT_PUSH_BOOKMARK_PREFIX: &str = "push-";
+
+/// Describes a jj command that failed from what it printed on stderr.
+fn jj_failed(command: String, stderr: &[u8]) -> JjstackError {
+    JjstackError::JjCommandFailed {
+        command,
+        message: String::from_utf8_lossy(stderr).into_owned(),
+    }
+}
+
+/// Lists the git remotes of the repository as (name, URL) pairs.
+pub fn list_remotes() -> Result<Vec<(String, String)>, JjstackError> {
+    let out = command::output(command::new("jj").args(["git", "remote", "list"]))?;
+    if !out.status.success() {
+        return Err(jj_failed("jj git remote list".to_string(), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    let mut remotes = Vec::new();
+    for line in text.lines() {
+        if let Some((name, url)) = line.trim().split_once(' ') {
+            remotes.push((name.to_string(), url.trim().to_string()));
+        }
+    }
+    Ok(remotes)
+}
+
+/// Returns the URL of the named remote or, when no name is given, of the
+/// remote PRs are most likely opened against: upstream when the repository
+/// is a fork, then origin, then the first one.
+pub fn get_remote_url(name: Option<&str>) -> Result<String, JjstackError> {
+    let remotes = list_remotes()?;
+    let find = |wanted: &str| remotes.iter().find(|(name, _)| name == wanted);
+    let remote = match name {
+        Some(name) => find(name).ok_or_else(|| format!("no git remote named {:?}", name))?,
+        None => find("upstream")
+            .or_else(|| find("origin"))
+            .or(remotes.first())
+            .ok_or("no git remotes found")?,
+    };
+    Ok(remote.1.to_string())
+}
+
+pub fn get_parent_bookmark(
+    bookmark: &str,
+    candidates: &[String],
+) -> Result<Option<String>, JjstackError> {
+    let others: Vec<&String> = candidates.iter().filter(|c| *c != bookmark).collect();
+    if others.is_empty() {
+        return Ok(None);
+    }
+    let symbols: Vec<String> = others.iter().map(|c| format!("{:?}", c)).collect();
+    let revset = format!("heads(::{:?}- & ({}))", bookmark, symbols.join(" | "));
+    let out = command::output(
+        command::new("jj")
+            .args(["log", "--no-graph", "-r", &revset, "-T"])
+            .arg(r#"local_bookmarks.map(|b| b.name()).join("\n") ++ "\n""#),
+    )?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .find(|name| others.iter().any(|c| c == name))
+        .map(str::to_string))
+}
+
+/// Returns, for each candidate bookmark, the closest candidate among the
+/// ancestors of its commit, following first parents, or None when there is
+/// none between it and the trunk. Candidates missing from the repository are
+/// left out. Branches pushed with `jj git push --change` are found by the
+/// change ID in their name, whether or not a local bookmark tracks them.
+pub fn get_bookmark_parents(
+    config: &Config,
+    candidates: &[String],
+) -> Result<HashMap<String, Option<String>>, JjstackError> {
+    if candidates.is_empty() {
+        return Ok(HashMap::new());
+    }
+    // Bookmarks only found on the remote have no local commit to resolve.
+    let symbols: Vec<String> = candidates
+        .iter()
+        .map(|c| format!("present({:?})", branch_change_id(config, c).unwrap_or(c)))
+        .collect();
+    let revset = format!("::({}) ~ ::trunk()", symbols.join(" | "));
+    let out = command::output(
+        command::new("jj")
+            .args(["log", "--no-graph", "-r", &revset, "-T"])
+            .arg(GRAPH_TEMPLATE),
+    )?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    let mut parent_of: HashMap<&str, &str> = HashMap::new();
+    let mut bookmarks_of: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut commit_of: HashMap<&str, &str> = HashMap::new();
+    for line in text.lines() {
+        let mut fields = line.split('\t');
+        let (Some(change_id), Some(parents), Some(bookmarks)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if let Some(parent) = parents.split_whitespace().next() {
+            parent_of.insert(change_id, parent);
+        }
+        let bookmarks: Vec<&str> = candidates
+            .iter()
+            .filter(|c| match branch_change_id(config, c) {
+                Some(id) => change_id.starts_with(id),
+                None => bookmarks.split_whitespace().any(|b| b == c.as_str()),
+            })
+            .map(String::as_str)
+            .collect();
+        for bookmark in &bookmarks {
+            commit_of.insert(bookmark, change_id);
+        }
+        bookmarks_of.insert(change_id, bookmarks);
+    }
+    let mut parents = HashMap::new();
+    for (bookmark, change_id) in &commit_of {
+        let mut parent = None;
+        let mut current = parent_of.get(change_id);
+        while let Some(change_id) = current {
+            let Some(bookmarks) = bookmarks_of.get(change_id) else {
+                break;
+            };
+            if let Some(bookmark) = bookmarks.first() {
+                parent = Some(bookmark.to_string());
+                break;
+            }
+            current = parent_of.get(change_id);
+        }
+        parents.insert(bookmark.to_string(), parent);
+    }
+    Ok(parents)
+}
+
+/// Returns the change ID in the name of a branch created by `jj git push
+/// --change`, such as push-kmoqnxvlrwzs.
+pub fn branch_change_id<'a>(config: &Config, branch: &'a str) -> Option<&'a str> {
+    let prefix = config
+        .push_bookmark_prefix
+        .as_deref()
+        .unwrap_or(DEFAULT_PUSH_BOOKMARK_PREFIX);
+    let id = branch.strip_prefix(prefix)?;
+    // Change IDs are written with the letters k to z.
+    let valid = !id.is_empty() && id.chars().all(|c| ('k'..='z').contains(&c));
+    valid.then_some(id)
+}
+
+/// Lists the full change IDs of the mutable commits of the repository.
+pub fn list_mutable_change_ids() -> Result<Vec<String>, JjstackError> {
+    let out = command::output(
+        command::new("jj")
+            .args(["log", "--no-graph", "-r", "mutable()", "-T"])
+            .arg(r#"change_id ++ "\n""#),
+    )?;
+    if !out.status.success() {
+        return Err(jj_failed("jj log -r mutable()".to_string(), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    Ok(text.lines().map(str::trim).map(str::to_string).collect())
+}
+
+/// Template for `jj log` printing the change ID of each commit, those of its
+/// parents and its local bookmarks.
+const GRAPH_TEMPLATE: &str = r#"change_id ++ "\t" ++ parents.map(|c| c.change_id()).join(" ") ++ "\t" ++ local_bookmarks.map(|b| b.name()).join(" ") ++ "\n""#;
+
+/// Template for `jj bookmark list`, printing one tab-separated line per
+/// local bookmark and per remote bookmark tracking it.
+const BOOKMARK_TEMPLATE: &str = r#"name ++ "\t" ++ remote ++ "\t" ++ if(present, "present") ++ "\t" ++ if(conflict, "conflict") ++ "\t" ++ if(tracked, "tracked") ++ "\t" ++ if(synced, "synced") ++ "\n""#;
+
+/// Template for `jj bookmark list --all-remotes`, printing each local and
+/// remote bookmark with the commits it points at.
+const TARGET_TEMPLATE: &str =
+    r#"name ++ "@" ++ remote ++ "\t" ++ added_targets.map(|c| c.commit_id()).join(" ") ++ "\n""#;
+
+/// BookmarkState is what a bookmark stands for in the local repository.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BookmarkState {
+    /// Points at a single commit.
+    #[default]
+    Present,
+    /// Points at more than one commit after concurrent updates, and is
+    /// written name?? in jj's output.
+    Conflicted,
+    /// Deleted locally but still tracked, until the deletion is pushed.
+    Deleted,
+    /// Only exists on remotes, without a local bookmark tracking it.
+    RemoteOnly,
+}
+
+/// Bookmark is a local bookmark together with the remote bookmarks tracking
+/// it, or a bookmark that only exists on remotes.
+#[derive(Clone, Debug, Default)]
+pub struct Bookmark {
+    pub name: String,
+    pub state: BookmarkState,
+    pub remotes: Vec<RemoteBookmark>,
+}
+
+/// RemoteBookmark is the state of a bookmark on a remote.
+#[derive(Clone, Debug)]
+pub struct RemoteBookmark {
+    pub remote: String,
+    pub tracked: bool,
+    /// True when the remote bookmark points at more than one commit.
+    pub conflict: bool,
+    /// True when the remote bookmark points at the same commit as the local
+    /// one.
+    pub synced: bool,
+}
+
+/// Lists the bookmarks of the repository using a machine-readable template,
+/// along with the remote bookmarks nothing tracks when all_remotes is set.
+/// The git pseudo-remote of colocated repositories is left out.
+pub fn list_bookmarks(all_remotes: bool) -> Result<Vec<Bookmark>, JjstackError> {
+    let mut cmd = command::new("jj");
+    cmd.args(["bookmark", "list", "-T", BOOKMARK_TEMPLATE]);
+    if all_remotes {
+        cmd.arg("--all-remotes");
+    }
+    let out = command::output(&mut cmd)?;
+    if !out.status.success() {
+        return Err(jj_failed("jj bookmark list".to_string(), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    let mut bookmarks: Vec<Bookmark> = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [name, remote, present, conflict, tracked, synced] = fields[..] else {
+            warn!("skipping malformed bookmark line: {:?}", line);
+            continue;
+        };
+        if remote.is_empty() {
+            let state = match (present, conflict) {
+                (_, "conflict") => BookmarkState::Conflicted,
+                ("present", _) => BookmarkState::Present,
+                _ => BookmarkState::Deleted,
+            };
+            bookmarks.push(Bookmark {
+                name: name.to_string(),
+                state,
+                remotes: Vec::new(),
+            });
+            continue;
+        }
+        if remote == "git" {
+            continue;
+        }
+        let remote = RemoteBookmark {
+            remote: remote.to_string(),
+            tracked: tracked == "tracked",
+            conflict: conflict == "conflict",
+            synced: synced == "synced",
+        };
+        // Remote bookmarks are listed right after their local counterpart,
+        // which is missing when the bookmark only exists on the remote.
+        match bookmarks.last_mut() {
+            Some(bookmark) if bookmark.name == name => bookmark.remotes.push(remote),
+            _ => bookmarks.push(Bookmark {
+                name: name.to_string(),
+                state: BookmarkState::RemoteOnly,
+                remotes: vec![remote],
+            }),
+        }
+    }
+    Ok(bookmarks)
+}
+
+/// Returns where every local and remote bookmark points, which changes
+/// whenever a bookmark is moved, created, deleted, fetched or pushed. The
+/// working copy is not snapshotted, so that polling creates no operations.
+pub fn bookmark_targets() -> Result<String, JjstackError> {
+    let out = command::output(command::new("jj").args([
+        "bookmark",
+        "list",
+        "--all-remotes",
+        "--ignore-working-copy",
+        "-T",
+        TARGET_TEMPLATE,
+    ]))?;
+    if !out.status.success() {
+        return Err(jj_failed("jj bookmark list".to_string(), &out.stderr));
+    }
+    Ok(command::stdout(out)?)
+}
+
+/// Returns the names of the local bookmarks that may belong to a stack, and
+/// with remote_bookmarks those only found on the remote. Conflicted
+/// bookmarks are skipped, as they do not name a single commit, and so are
+/// deleted ones.
+pub fn get_bookmarks(config: &Config) -> Result<Vec<String>, JjstackError> {
+    let remote_bookmarks = config.remote_bookmarks.unwrap_or_default();
+    let on_remote = |remote: &RemoteBookmark| {
+        !remote.conflict
+            && config
+                .remote
+                .as_ref()
+                .is_none_or(|name| remote.remote == *name)
+    };
+    let mut bookmarks = Vec::new();
+    for bookmark in list_bookmarks(remote_bookmarks)? {
+        if !is_stack_bookmark(config, &bookmark.name) {
+            continue;
+        }
+        match bookmark.state {
+            BookmarkState::Present => bookmarks.push(bookmark.name),
+            BookmarkState::Conflicted => {
+                warn!("skipping conflicted bookmark: {}", bookmark.name);
+            }
+            BookmarkState::Deleted => {}
+            BookmarkState::RemoteOnly => {
+                if remote_bookmarks && bookmark.remotes.iter().any(on_remote) {
+                    bookmarks.push(bookmark.name);
+                }
+            }
+        }
+    }
+    Ok(bookmarks)
+}
+
+/// Reports whether bookmark may belong to a stack: it is not the trunk and
+/// it passes every bookmark filter of the configuration.
+pub fn is_stack_bookmark(config: &Config, bookmark: &str) -> bool {
+    if config.trunk.as_deref() == Some(bookmark) {
+        return false;
+    }
+    if let Some(prefix) = &config.bookmark_prefix {
+        if !bookmark.starts_with(prefix.as_str()) {
+            return false;
+        }
+    }
+    if let Some(glob) = &config.bookmark_glob {
+        if !glob_match(glob, bookmark) {
+            return false;
+        }
+    }
+    let mut excluded = config.exclude_bookmarks.iter().flatten();
+    if excluded.any(|glob| glob_match(glob, bookmark)) {
+        return false;
+    }
+    match &config.bookmark_regex {
+        Some(regex) => regex.0.is_match(bookmark),
+        None => true,
+    }
+}
+
+/// Matches name against a glob where * stands for any run of characters and
+/// ? for a single one.
+fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut g, mut n) = (0, 0);
+    // Position of the last * and of the name character it was matched up
+    // to, to backtrack when the rest of the glob does not match.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    g = star_g + 1;
+                    n = star_n + 1;
+                    star = Some((star_g, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+pub fn get_current_stack_bookmarks(config: &Config) -> Result<Vec<String>, JjstackError> {
+    get_revset_bookmarks(config, "(::@ | @::) & bookmarks() ~ ::trunk()")
+}
+
+/// Returns the bookmarks closest to the working-copy commit among its
+/// ancestors, usually the one being worked on.
+pub fn get_current_bookmarks(config: &Config) -> Result<Vec<String>, JjstackError> {
+    get_revset_bookmarks(config, "heads(::@ & bookmarks() ~ ::trunk())")
+}
+
+/// Returns the stack bookmarks that are not yet part of the trunk.
+pub fn get_unmerged_bookmarks(config: &Config) -> Result<Vec<String>, JjstackError> {
+    get_revset_bookmarks(config, "bookmarks() ~ ::trunk()")
+}
+
+fn get_revset_bookmarks(config: &Config, revset: &str) -> Result<Vec<String>, JjstackError> {
+    let out = command::output(
+        command::new("jj")
+            .args(["log", "--no-graph", "-r", revset, "-T"])
+            .arg(r#"local_bookmarks.map(|b| b.name()).join("\n") ++ "\n""#),
+    )?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    let mut bookmarks = Vec::new();
+    for bookmark in text.lines().map(str::trim) {
+        if !bookmark.is_empty() && is_stack_bookmark(config, bookmark) {
+            bookmarks.push(bookmark.to_string());
+        }
+    }
+    Ok(bookmarks)
+}
+
+/// Fetches from all git remotes.
+pub fn git_fetch() -> Result<(), JjstackError> {
+    let status = command::status(command::new("jj").args(["git", "fetch", "--all-remotes"]))?;
+    if !status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj git fetch".to_string(),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Pushes the bookmarks to their remote.
+pub fn git_push(bookmarks: &[String]) -> Result<(), JjstackError> {
+    let mut cmd = command::new("jj");
+    cmd.args(["git", "push"]);
+    for bookmark in bookmarks {
+        cmd.args(["-b", bookmark]);
+    }
+    let status = command::status(&mut cmd)?;
+    if !status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj git push".to_string(),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Rebases the branches of the bookmarks onto destination, a revset.
+pub fn rebase_branches(bookmarks: &[String], destination: &str) -> Result<(), JjstackError> {
+    let mut cmd = command::new("jj");
+    cmd.arg("rebase");
+    for bookmark in bookmarks {
+        cmd.args(["-b", bookmark]);
+    }
+    cmd.args(["-d", destination]);
+    let status = command::status(&mut cmd)?;
+    if !status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj rebase".to_string(),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Moves the changes of revision from into the commits of into, a revset,
+/// that last modified the same lines.
+pub fn absorb(from: &str, into: &str) -> Result<(), JjstackError> {
+    let status =
+        command::status(command::new("jj").args(["absorb", "--from", from, "--into", into]))?;
+    if !status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj absorb".to_string(),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Moves all the changes of revision from into revision into.
+pub fn squash(from: &str, into: &str) -> Result<(), JjstackError> {
+    let status =
+        command::status(command::new("jj").args(["squash", "--from", from, "--into", into]))?;
+    if !status.success() {
+        return Err(JjstackError::JjCommandFailed {
+            command: "jj squash".to_string(),
+            message: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// STACK_ID_TRAILER names the commit description trailer identifying a
+/// change of a stack across bookmark renames and rebases.
+pub const STACK_ID_TRAILER: &str = "JJStack-Id";
+
+/// Separates the records of templates that print whole descriptions.
+const RECORD_SEPARATOR: char = '\x1e';
+
+/// STACK_NAME_TRAILER names the commit description trailer holding the name
+/// given to the stack of the commit's bookmark.
+pub const STACK_NAME_TRAILER: &str = "JJStack-Name";
+
+/// Reads the JJStack-Id trailer of a commit description.
+pub fn stack_id(description: &str) -> Option<&str> {
+    trailer(description, STACK_ID_TRAILER)
+}
+
+/// Reads the trailer key of a commit description.
+fn trailer<'a>(description: &'a str, key: &str) -> Option<&'a str> {
+    let prefix = format!("{}:", key);
+    description
+        .lines()
+        .rev()
+        .take_while(|line| !line.trim().is_empty())
+        .find_map(|line| line.strip_prefix(prefix.as_str()))
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+}
+
+/// Lists the commits of revset as the header printed by the template
+/// followed by the full description.
+fn list_descriptions(revset: &str, header: &str) -> Result<Vec<(String, String)>, JjstackError> {
+    let template = format!(
+        "\"{}\" ++ {} ++ \"\\n\" ++ description",
+        RECORD_SEPARATOR, header
+    );
+    let out = command::output(command::new("jj").args([
+        "log",
+        "--no-graph",
+        "-r",
+        revset,
+        "-T",
+        &template,
+    ]))?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    Ok(text
+        .split(RECORD_SEPARATOR)
+        .filter_map(|record| record.split_once('\n'))
+        .map(|(header, description)| (header.to_string(), description.to_string()))
+        .collect())
+}
+
+/// Adds a JJStack-Id trailer, set to the change id, to the description of
+/// each commit of revset that has none, and returns how many were changed.
+pub fn add_stack_ids(revset: &str) -> Result<usize, JjstackError> {
+    let mut added = 0;
+    for (change_id, description) in list_descriptions(revset, "change_id")? {
+        if stack_id(&description).is_some() {
+            continue;
+        }
+        let description = description.trim_end();
+        let separator = match description.lines().last() {
+            Some(line) if is_trailer(line) => "\n",
+            _ => "\n\n",
+        };
+        let message = format!(
+            "{}{}{}: {}\n",
+            description, separator, STACK_ID_TRAILER, change_id
+        );
+        let out = command::output(
+            command::new("jj").args(["describe", "-r", &change_id, "-m", &message]),
+        )?;
+        if !out.status.success() {
+            return Err(jj_failed(
+                format!("jj describe -r {}", change_id),
+                &out.stderr,
+            ));
+        }
+        added += 1;
+    }
+    Ok(added)
+}
+
+/// Reads the JJStack-Name trailers of the commits of bookmarks, mapping each
+/// bookmark of a named commit to the name.
+pub fn get_stack_names(bookmarks: &[String]) -> Result<HashMap<String, String>, JjstackError> {
+    if bookmarks.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let revset = bookmarks
+        .iter()
+        .map(|bookmark| format!("present({:?})", bookmark))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let header = r#"local_bookmarks.map(|b| b.name()).join(" ")"#;
+    let mut names = HashMap::new();
+    for (header, description) in list_descriptions(&revset, header)? {
+        let Some(name) = trailer(&description, STACK_NAME_TRAILER) else {
+            continue;
+        };
+        for bookmark in header.split_whitespace() {
+            names.insert(bookmark.to_string(), name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Records name in the JJStack-Name trailer of the commit of bookmark,
+/// replacing the previous name.
+pub fn set_stack_name(bookmark: &str, name: &str) -> Result<(), JjstackError> {
+    let prefix = format!("{}:", STACK_NAME_TRAILER);
+    let description = get_description(bookmark)?;
+    let description = description
+        .trim_end()
+        .lines()
+        .filter(|line| !line.starts_with(&prefix))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let separator = match description.lines().last() {
+        Some(line) if is_trailer(line) => "\n",
+        _ => "\n\n",
+    };
+    let message = format!(
+        "{}{}{}: {}\n",
+        description, separator, STACK_NAME_TRAILER, name
+    );
+    let revset = format!("{:?}", bookmark);
+    let out =
+        command::output(command::new("jj").args(["describe", "-r", &revset, "-m", &message]))?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj describe -r {}", revset), &out.stderr));
+    }
+    Ok(())
+}
+
+fn is_trailer(line: &str) -> bool {
+    line.split_once(": ").is_some_and(|(key, _)| {
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// Finds the stack bookmarks renamed since they were pushed: maps the name
+/// of each remote bookmark without a local counterpart to the local
+/// bookmark whose commit carries the same JJStack-Id.
+pub fn get_renamed_bookmarks(config: &Config) -> Result<HashMap<String, String>, JjstackError> {
+    let header = r#"local_bookmarks.map(|b| b.name()).join(" ") ++ "\t" ++ remote_bookmarks.filter(|b| b.remote() != "git").map(|b| b.name()).join(" ")"#;
+    let revset = "(bookmarks() | remote_bookmarks()) ~ ::trunk()";
+    let mut local_of = HashMap::new();
+    let mut remote_of: Vec<(String, String)> = Vec::new();
+    for (header, description) in list_descriptions(revset, header)? {
+        let Some(id) = stack_id(&description) else {
+            continue;
+        };
+        let (local, remote) = header.split_once('\t').unwrap_or((&header, ""));
+        if let Some(name) = local
+            .split_whitespace()
+            .find(|name| is_stack_bookmark(config, name))
+        {
+            local_of.insert(id.to_string(), name.to_string());
+        }
+        for name in remote.split_whitespace() {
+            remote_of.push((name.to_string(), id.to_string()));
+        }
+    }
+    let locals: HashSet<&String> = local_of.values().collect();
+    Ok(remote_of
+        .iter()
+        .filter(|(name, _)| !locals.contains(name))
+        .filter_map(|(name, id)| Some((name.to_string(), local_of.get(id)?.to_string())))
+        .collect())
+}
+
+/// Finds the stack bookmarks renamed with `jj bookmark rename` since they
+/// were pushed, going by jj's remote tracking: maps the name of each tracked
+/// remote bookmark whose local bookmark was deleted to the local bookmark,
+/// never pushed, that now holds the same change.
+pub fn get_renamed_remote_bookmarks(
+    config: &Config,
+) -> Result<HashMap<String, String>, JjstackError> {
+    let bookmarks = list_bookmarks(false)?;
+    let deleted: HashSet<&str> = bookmarks
+        .iter()
+        .filter(|b| b.state == BookmarkState::Deleted && b.remotes.iter().any(|r| r.tracked))
+        .map(|b| b.name.as_str())
+        .collect();
+    let unpushed: HashSet<&str> = bookmarks
+        .iter()
+        .filter(|b| {
+            b.state == BookmarkState::Present
+                && b.remotes.is_empty()
+                && is_stack_bookmark(config, &b.name)
+        })
+        .map(|b| b.name.as_str())
+        .collect();
+    if deleted.is_empty() || unpushed.is_empty() {
+        return Ok(HashMap::new());
+    }
+    // The change, unlike the commit, survives the rewrites made after the
+    // rename.
+    let template = r#"change_id ++ "\t" ++ local_bookmarks.map(|b| b.name()).join(" ") ++ "\t" ++ remote_bookmarks.filter(|b| b.remote() != "git").map(|b| b.name()).join(" ") ++ "\n""#;
+    let revset = "(bookmarks() | remote_bookmarks()) ~ ::trunk()";
+    let out = command::output(command::new("jj").args([
+        "log",
+        "--no-graph",
+        "-r",
+        revset,
+        "-T",
+        template,
+    ]))?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    let mut local_of: HashMap<&str, &str> = HashMap::new();
+    let mut remote_of: Vec<(&str, &str)> = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split('\t');
+        let (Some(change_id), Some(local), Some(remote)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if let Some(name) = local.split_whitespace().find(|n| unpushed.contains(n)) {
+            local_of.insert(change_id, name);
+        }
+        for name in remote.split_whitespace().filter(|n| deleted.contains(n)) {
+            remote_of.push((name, change_id));
+        }
+    }
+    Ok(remote_of
+        .into_iter()
+        .filter_map(|(name, change_id)| {
+            Some((name.to_string(), local_of.get(change_id)?.to_string()))
+        })
+        .collect())
+}
+
+/// Creates the bookmark name on revision.
+pub fn create_bookmark(name: &str, revision: &str) -> Result<(), JjstackError> {
+    let out =
+        command::output(command::new("jj").args(["bookmark", "create", name, "-r", revision]))?;
+    if !out.status.success() {
+        return Err(jj_failed(
+            format!("jj bookmark create {}", name),
+            &out.stderr,
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the description of the commit of revision.
+pub fn get_description(revision: &str) -> Result<String, JjstackError> {
+    let revset = format!("{:?}", revision);
+    Ok(list_descriptions(&revset, "change_id")?
+        .into_iter()
+        .next()
+        .map(|(_, description)| description)
+        .unwrap_or_default())
+}
+
+/// Returns the commit id each of bookmarks points to, leaving out those that
+/// do not exist.
+pub fn bookmark_commit_ids(bookmarks: &[String]) -> Result<HashMap<String, String>, JjstackError> {
+    if bookmarks.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let revset = bookmarks
+        .iter()
+        .map(|bookmark| format!("present({:?})", bookmark))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let out = command::output(command::new("jj").args([
+        "log",
+        "--no-graph",
+        "-r",
+        &revset,
+        "-T",
+        r#"local_bookmarks.map(|b| b.name()).join(" ") ++ "\t" ++ commit_id ++ "\n""#,
+    ]))?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let mut commit_ids = HashMap::new();
+    for line in command::stdout(out)?.lines() {
+        let Some((names, commit_id)) = line.split_once('\t') else {
+            continue;
+        };
+        for name in names.split_whitespace() {
+            commit_ids.insert(name.to_string(), commit_id.to_string());
+        }
+    }
+    Ok(commit_ids)
+}
+
+/// Commit is a commit of the jj log.
+#[derive(Clone, Debug)]
+pub struct Commit {
+    pub change_id: String,
+    pub description: String,
+    pub conflict: bool,
+}
+
+/// Lists the commits of revset, newest first.
+pub fn list_commits(revset: &str) -> Result<Vec<Commit>, JjstackError> {
+    let out = command::output(
+        command::new("jj")
+            .args(["log", "--no-graph", "-r", revset, "-T"])
+            .arg(r#"change_id.short() ++ "\t" ++ if(conflict, "conflict") ++ "\t" ++ description.first_line() ++ "\n""#),
+    )?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    Ok(text
+        .lines()
+        .filter_map(|line| {
+            let (change_id, rest) = line.split_once('\t')?;
+            let (conflict, description) = rest.split_once('\t')?;
+            Some(Commit {
+                change_id: change_id.to_string(),
+                description: description.to_string(),
+                conflict: conflict == "conflict",
+            })
+        })
+        .collect())
+}
+
+/// Describes the conflicted commits in the branches of the bookmarks, one
+/// line per commit.
+pub fn get_conflicts(bookmarks: &[String]) -> Result<Vec<String>, JjstackError> {
+    let symbols: Vec<String> = bookmarks.iter().map(|b| format!("{:?}", b)).collect();
+    let revset = format!("conflicts() & (trunk()..({}))", symbols.join(" | "));
+    let out = command::output(
+        command::new("jj")
+            .args(["log", "--no-graph", "-r", &revset, "-T"])
+            .arg(r#"change_id.short() ++ " " ++ local_bookmarks.join(" ") ++ " " ++ description.first_line() ++ "\n""#),
+    )?;
+    if !out.status.success() {
+        return Err(jj_failed(format!("jj log -r {}", revset), &out.stderr));
+    }
+    let text = command::stdout(out)?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::command::{with_runner, MockRunner};
+
+    #[test]
+    fn bookmark_commit_ids_maps_every_bookmark_of_a_commit() {
+        let runner = MockRunner::default().ok(
+            "jj log",
+            "feat-a feat-b\t0123abcd\n\tdeadbeef\nfeat-c\t4567cdef\n",
+        );
+        let bookmarks = ["feat-a", "feat-b", "feat-c", "gone"].map(String::from);
+        let commit_ids = with_runner(Rc::new(runner), || bookmark_commit_ids(&bookmarks)).unwrap();
+        let expected: HashMap<String, String> = [
+            ("feat-a", "0123abcd"),
+            ("feat-b", "0123abcd"),
+            ("feat-c", "4567cdef"),
+        ]
+        .map(|(name, id)| (name.to_string(), id.to_string()))
+        .into();
+        assert_eq!(commit_ids, expected);
+    }
+
+    #[test]
+    fn get_bookmarks_skips_trunk_conflicts_and_deleted() {
+        let runner = MockRunner::default().ok(
+            "jj bookmark list",
+            "feat-a\t\tpresent\t\t\t\n\
+             feat-a\torigin\t\t\ttracked\tsynced\n\
+             feat-b\t\tpresent\tconflict\t\t\n\
+             feat-c\t\t\t\t\t\n\
+             feat-c\torigin\t\t\ttracked\t\n\
+             main\t\tpresent\t\t\t\n\
+             wip\t\tpresent\t\t\t\n",
+        );
+        let config = Config {
+            trunk: Some("main".to_string()),
+            bookmark_prefix: Some("feat-".to_string()),
+            ..Default::default()
+        };
+        let bookmarks = with_runner(Rc::new(runner), || get_bookmarks(&config)).unwrap();
+        assert_eq!(bookmarks, vec!["feat-a".to_string()]);
+    }
+
+    #[test]
+    fn get_bookmarks_includes_remote_only_bookmarks_when_asked() {
+        let runner = MockRunner::default().ok(
+            "jj bookmark list",
+            "feat-a\t\tpresent\t\t\t\n\
+             feat-a\tgit\t\t\ttracked\tsynced\n\
+             feat-d\torigin\t\t\t\t\n\
+             feat-e\torigin\t\tconflict\t\t\n\
+             feat-f\tupstream\t\t\t\t\n",
+        );
+        let config = Config {
+            remote: Some("origin".to_string()),
+            remote_bookmarks: Some(true),
+            ..Default::default()
+        };
+        let bookmarks = with_runner(Rc::new(runner), || get_bookmarks(&config)).unwrap();
+        assert_eq!(bookmarks, vec!["feat-a".to_string(), "feat-d".to_string()]);
+    }
+
+    #[test]
+    fn is_stack_bookmark_applies_every_filter() {
+        let config = Config {
+            bookmark_glob: Some("uc/*-?".to_string()),
+            exclude_bookmarks: Some(vec!["uc/wip-*".to_string()]),
+            bookmark_regex: Some("[0-9]$".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(is_stack_bookmark(&config, "uc/fix-1"));
+        assert!(is_stack_bookmark(&config, "uc/a-b-2"));
+        assert!(!is_stack_bookmark(&config, "uc/fix-a"));
+        assert!(!is_stack_bookmark(&config, "uc/fix-12"));
+        assert!(!is_stack_bookmark(&config, "me/fix-1"));
+        assert!(!is_stack_bookmark(&config, "uc/wip-1"));
+    }
+
+    #[test]
+    fn list_commits_reads_descriptions_and_conflicts() {
+        let runner = MockRunner::default().ok(
+            "jj log",
+            "qpvuntsm\t\tAdd a\twith a tab\nkkmpptxz\tconflict\t\n",
+        );
+        let commits = with_runner(Rc::new(runner), || list_commits("a..b")).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].change_id, "qpvuntsm");
+        assert_eq!(commits[0].description, "Add a\twith a tab");
+        assert!(!commits[0].conflict);
+        assert!(commits[1].conflict);
+        assert_eq!(commits[1].description, "");
+    }
+
+    #[test]
+    fn get_renamed_bookmarks_matches_stack_ids() {
+        let runner = MockRunner::default().ok(
+            "jj log",
+            "\x1enew\told\nAdd a\n\nJJStack-Id: kkk\n\
+             \x1eb\tb\nAdd b\n\nJJStack-Id: lll\n\
+             \x1ec\told-c\nAdd c\n",
+        );
+        let renamed = with_runner(
+            Rc::new(runner),
+            || get_renamed_bookmarks(&Config::default()),
+        )
+        .unwrap();
+        assert_eq!(
+            renamed,
+            HashMap::from([("old".to_string(), "new".to_string())])
+        );
+    }
+
+    #[test]
+    fn get_renamed_remote_bookmarks_follows_the_change() {
+        // old was renamed to new and amended; b is pushed under its own name.
+        let runner = MockRunner::default()
+            .ok(
+                "jj bookmark list",
+                "b\t\tpresent\t\t\t\n\
+                 b\torigin\t\t\ttracked\tsynced\n\
+                 new\t\tpresent\t\t\t\n\
+                 old\t\t\t\t\t\n\
+                 old\torigin\t\t\ttracked\t\n",
+            )
+            .ok(
+                "jj log",
+                "kkk\tnew\t\n\
+                 kkk\t\told\n\
+                 lll\tb\tb\n",
+            );
+        let renamed = with_runner(Rc::new(runner), || {
+            get_renamed_remote_bookmarks(&Config::default())
+        })
+        .unwrap();
+        assert_eq!(
+            renamed,
+            HashMap::from([("old".to_string(), "new".to_string())])
+        );
+    }
+
+    #[test]
+    fn stack_id_reads_the_last_paragraph() {
+        assert_eq!(stack_id("Add a\n\nJJStack-Id: kkk\n"), Some("kkk"));
+        assert_eq!(
+            stack_id("Add a\n\nSigned-off-by: me\nJJStack-Id: kkk"),
+            Some("kkk")
+        );
+        assert_eq!(stack_id("JJStack-Id: kkk\n\nAdd a\n"), None);
+        assert_eq!(stack_id("Add a\n"), None);
+    }
+
+    #[test]
+    fn list_bookmarks_attaches_remotes() {
+        let runner = MockRunner::default().ok(
+            "jj bookmark list",
+            "a\t\tpresent\t\t\t\n\
+             a\torigin\t\t\ttracked\tsynced\n\
+             b\torigin\t\t\t\t\n",
+        );
+        let bookmarks = with_runner(Rc::new(runner), || list_bookmarks(false)).unwrap();
+        assert_eq!(bookmarks.len(), 2);
+        assert_eq!(bookmarks[0].state, BookmarkState::Present);
+        assert_eq!(bookmarks[0].remotes.len(), 1);
+        assert!(bookmarks[0].remotes[0].synced);
+        assert_eq!(bookmarks[1].state, BookmarkState::RemoteOnly);
+        assert_eq!(bookmarks[1].remotes[0].remote, "origin");
+    }
+
+    #[test]
+    fn get_parent_bookmark_picks_a_candidate() {
+        let runner = Rc::new(MockRunner::default().ok("jj log", "unrelated\na\n"));
+        let candidates = vec!["a".to_string(), "b".to_string()];
+        let parent = with_runner(runner.clone(), || get_parent_bookmark("b", &candidates));
+        assert_eq!(parent.unwrap(), Some("a".to_string()));
+        assert_eq!(runner.calls().len(), 1);
+    }
+
+    #[test]
+    fn get_parent_bookmark_without_candidates_runs_nothing() {
+        let runner = Rc::new(MockRunner::default());
+        let candidates = vec!["b".to_string()];
+        let parent = with_runner(runner.clone(), || get_parent_bookmark("b", &candidates));
+        assert_eq!(parent.unwrap(), None);
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn get_bookmark_parents_skips_unbookmarked_commits() {
+        let runner = MockRunner::default().ok(
+            "jj log",
+            "kkk\tyyy\tc\n\
+             yyy\txxx\t\n\
+             xxx\twww\ta other\n\
+             zzz\twww\tb\n",
+        );
+        let candidates = ["a", "b", "c"].map(String::from);
+        let parents = with_runner(Rc::new(runner), || {
+            get_bookmark_parents(&Config::default(), &candidates)
+        })
+        .unwrap();
+        assert_eq!(parents.len(), 3);
+        assert_eq!(parents["a"], None);
+        assert_eq!(parents["b"], None);
+        assert_eq!(parents["c"], Some("a".to_string()));
+    }
+
+    #[test]
+    fn get_bookmark_parents_finds_change_branches() {
+        let runner = MockRunner::default().ok(
+            "jj log",
+            "zzzzkkkk\tyyyy\t\n\
+             yyyy\txxxx\t\n\
+             xxxx\twwww\ta\n",
+        );
+        let candidates = ["a", "push-zzzz"].map(String::from);
+        let parents = with_runner(Rc::new(runner), || {
+            get_bookmark_parents(&Config::default(), &candidates)
+        })
+        .unwrap();
+        assert_eq!(parents["push-zzzz"], Some("a".to_string()));
+        assert_eq!(branch_change_id(&Config::default(), "push-main"), None);
+    }
+
+    #[test]
+    fn get_remote_url_prefers_upstream() {
+        let runner = Rc::new(MockRunner::default().ok(
+            "jj git remote list",
+            "origin git@github.com:me/repo.git\nupstream git@github.com:org/repo.git\n",
+        ));
+        let url = with_runner(runner.clone(), || get_remote_url(None)).unwrap();
+        assert_eq!(url, "git@github.com:org/repo.git");
+        let url = with_runner(runner.clone(), || get_remote_url(Some("origin"))).unwrap();
+        assert_eq!(url, "git@github.com:me/repo.git");
+        assert!(with_runner(runner, || get_remote_url(Some("fork"))).is_err());
+    }
+
+    #[test]
+    fn failing_push_is_an_error() {
+        let runner = MockRunner::default().fail("jj git push", "rejected");
+        let err = with_runner(Rc::new(runner), || git_push(&["a".to_string()])).unwrap_err();
+        assert!(err.to_string().contains("jj git push"), "{}", err);
+        assert!(
+            matches!(err, JjstackError::JjCommandFailed { .. }),
+            "{:?}",
+            err
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::JjstackError;
+
+/// Entry records the title and description of a PR before a run changed
+/// them.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub repo: String,
+    pub number: i32,
+    pub title: String,
+    pub body: String,
+    /// In comment mode, the navigation comment of the PR, empty when it had
+    /// none; the description is then left alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Journal keeps, one JSON line per PR, the titles and descriptions a run is
+/// about to replace, so that they can be restored when the run is
+/// interrupted, or later with --rollback and the run id.
+pub struct Journal {
+    pub run_id: String,
+    file: Mutex<File>,
+}
+
+/// Returns the directory of the journals: $XDG_STATE_HOME/jjstack/journal,
+/// or ~/.local/state/jjstack/journal.
+fn dir() -> Result<PathBuf, JjstackError> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").ok_or("cannot find the home directory")?)
+            .join(".local/state"),
+    };
+    Ok(dir.join("jjstack").join("journal"))
+}
+
+impl Journal {
+    /// Starts the journal of a new run, named after the current time.
+    pub fn create() -> Result<Self, JjstackError> {
+        let dir = dir()?;
+        fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?;
+        let run_id = format!("{}-{}", now.as_secs(), std::process::id());
+        let path = dir.join(format!("{}.jsonl", run_id));
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("cannot create {}: {}", path.display(), e))?;
+        Ok(Journal {
+            run_id,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends entry, on disk before returning, so that it survives the
+    /// process being killed.
+    pub fn record(&self, entry: &Entry) -> Result<(), JjstackError> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Reads the entries recorded by the run run_id.
+pub fn read(run_id: &str) -> Result<Vec<Entry>, JjstackError> {
+    if run_id.contains(['/', '\\']) || run_id.starts_with('.') {
+        return Err(format!("invalid run id: {:?}", run_id).into());
+    }
+    let path = dir()?.join(format!("{}.jsonl", run_id));
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read journal {}: {}", path.display(), e))?;
+    let mut entries = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        entries.push(serde_json::from_str(line)?);
+    }
+    Ok(entries)
+}
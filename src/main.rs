@@ -6,300 +6,3213 @@
 //
 // THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+mod bookmark;
+mod cli;
+mod completions;
+mod create;
+mod doctor;
+mod graph;
+mod init;
+mod plan;
+mod progress;
+mod stats;
+mod table;
+mod tui;
+mod unstack;
+
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use serde::Deserialize;
-use serde_json::json;
+use clap::Parser;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::SIGINT;
 
-const STACK_HEADER: &str = "<!-- STACK NAVIGATION -->";
-const STACK_FOOTER: &str = "<!-- END STACK NAVIGATION -->";
+use jjstack_core::azure::{self, AzureClient};
+use jjstack_core::bitbucket::{self, BitbucketClient};
+use jjstack_core::cache::Cache;
+use jjstack_core::config::{AnnotateMode, Config, Topology};
+use jjstack_core::forge::{parse_remote_url, Blocking, Comment, Forge, ForgeKind, QueueState};
+use jjstack_core::gitea::GiteaClient;
+use jjstack_core::github::{self, GithubApp, GithubClient, GithubEndpoints};
+use jjstack_core::gitlab::GitlabClient;
+use jjstack_core::jj::{
+    absorb, add_stack_ids, bookmark_commit_ids, bookmark_targets, branch_change_id,
+    get_bookmark_parents, get_bookmarks, get_conflicts, get_current_bookmarks,
+    get_current_stack_bookmarks, get_parent_bookmark, get_remote_url, get_renamed_bookmarks,
+    get_renamed_remote_bookmarks, get_stack_names, get_unmerged_bookmarks, git_fetch, git_push,
+    is_stack_bookmark, list_commits, list_mutable_change_ids, rebase_branches, set_stack_name,
+    squash, STACK_ID_TRAILER,
+};
+use jjstack_core::journal::{self, Journal};
+use jjstack_core::stack::{
+    append_related_prs, build_pr_stacks, generate_nav_block, remove_nav_block, render_body,
+    render_body_with_foreign_stack, render_nav_template, select_between, select_connected,
+    select_stacks, CheckState, NavBlock, NavOptions, PullRequest, Stack, STACK_FOOTER,
+    STACK_HEADER,
+};
+use jjstack_core::JjstackError;
 
-#[derive(Debug, Deserialize)]
-struct GithubPullRequest {
-    #[serde(rename = "number")]
-    number: i32,
-    #[serde(rename = "title")]
-    title: String,
-    #[serde(rename = "body")]
-    body: Option<String>,
-    #[serde(rename = "head")]
-    head: GithubReference,
-    #[serde(rename = "base")]
-    base: GithubReference,
+use cli::{
+    AbsorbArgs, AnnotateArgs, Cli, Commands, MergeArgs, OpenArgs, RebaseArgs, SyncArgs, WatchArgs,
+};
+use progress::Progress;
+
+const DEFAULT_LABEL_PREFIX: &str = "stack:";
+
+/// Exit code when some PRs could not be updated while the others were.
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+
+/// PartialFailure is returned once every PR was processed but some of them
+/// failed, and makes the process exit with EXIT_PARTIAL_FAILURE.
+#[derive(Debug)]
+struct PartialFailure {
+    failed: usize,
+    total: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} PRs failed to update", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for PartialFailure {}
+
+/// How many times a PR description edited concurrently is read again before
+/// giving up on updating it.
+const CONFLICT_RETRIES: u32 = 3;
+
+/// Set by SIGINT, which only interrupts the PR updates, to restore them, while
+/// SIGINT_TERMINATES is unset; at any other time SIGINT terminates jjstack.
+static INTERRUPTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(Default::default);
+static SIGINT_TERMINATES: LazyLock<Arc<AtomicBool>> =
+    LazyLock::new(|| Arc::new(AtomicBool::new(true)));
+
+/// Installs the SIGINT handlers, once for the whole process.
+fn catch_sigint() -> io::Result<()> {
+    signal_hook::flag::register_conditional_default(SIGINT, Arc::clone(&SIGINT_TERMINATES))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&INTERRUPTED))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Report {
+    repo: String,
+    applied: bool,
+    stacks: Vec<StackReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pruned: Vec<PrReport>,
 }
 
-#[derive(Debug, Deserialize)]
-struct GithubReference {
-    #[serde(rename = "ref")]
-    r#ref: String,
+#[derive(Serialize)]
+struct StackReport {
+    prs: Vec<PrReport>,
 }
 
-#[derive(Clone)]
-struct PullRequest {
+#[derive(Serialize)]
+struct PrReport {
     number: i32,
     title: String,
     head: String,
     base: String,
+    action: Action,
     body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_base: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Description, or navigation comment, the new body was computed from.
+    #[serde(skip)]
+    current_body: String,
+    /// Title of the PR before a new title prefix.
+    #[serde(skip)]
+    current_title: String,
+    /// Navigation block the new body is rendered with, empty to remove it.
+    #[serde(skip)]
+    nav_block: Option<String>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let out = Command::new("gh")
-        .args(["repo", "set-default", "--view"])
-        .output()?;
-    if !out.status.success() {
-        return Err(format!(
-            "cannot run 'gh repo set-default --view': {}",
-            String::from_utf8_lossy(&out.stderr)
-        )
-        .into());
+impl PrReport {
+    fn new(pr: &PullRequest, action: Action, body: String) -> Self {
+        PrReport {
+            number: pr.number,
+            title: pr.title.to_string(),
+            head: pr.head.to_string(),
+            base: pr.base.to_string(),
+            action,
+            body,
+            previous_base: None,
+            labels: None,
+            reviewers: None,
+            error: None,
+            current_body: pr.body.to_string(),
+            current_title: pr.title.to_string(),
+            nav_block: None,
+        }
     }
-    let repo = String::from_utf8(out.stdout)?.trim().to_string();
-    println!("repo: {:?}", repo);
+}
 
-    let args: Vec<String> = env::args().collect();
-    let apply = args.contains(&"--apply".to_string());
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Update,
+    Remove,
+    Skip,
+}
 
-    let bookmarks = get_bookmarks()?;
-    if bookmarks.is_empty() {
-        println!("no bookmarks found.");
-        return Ok(());
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            if let Some(hint) = e.downcast_ref::<JjstackError>().and_then(hint) {
+                eprintln!("hint: {}", hint);
+            }
+            if e.is::<PartialFailure>() {
+                ExitCode::from(EXIT_PARTIAL_FAILURE)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
     }
+}
 
-    let bookmark_idx: HashSet<String> = bookmarks.into_iter().collect();
-    let prs = get_open_prs(repo.to_string(), bookmark_idx.clone())?;
-    if prs.is_empty() {
-        println!("no matching PRs found for bookmarks.");
-        return Ok(());
+/// Suggests a fix for the failures that usually have the same cause.
+fn hint(e: &JjstackError) -> Option<&'static str> {
+    match e {
+        JjstackError::JjCommandFailed { .. } => {
+            Some("run 'jjstack doctor' to check that jj works in this directory")
+        }
+        JjstackError::GhCommandFailed { .. } => {
+            Some("run 'gh auth login', or name the repository with --repo")
+        }
+        JjstackError::ApiError { status: 401, .. } => {
+            Some("the forge token is invalid or expired; run 'jjstack doctor'")
+        }
+        JjstackError::ApiError { status: 403, .. } => {
+            Some("the forge token may lack the permission to write pull requests")
+        }
+        JjstackError::ApiError { status: 404, .. } => {
+            Some("check the repository name and that the forge token can read it")
+        }
+        JjstackError::RequestFailed { .. } => {
+            Some("check the network connection and the api_url setting")
+        }
+        JjstackError::ConfigError(_) => {
+            Some("run 'jjstack doctor', or 'jjstack init' to write .jjstack.toml")
+        }
+        _ => None,
     }
+}
 
-    let pr_stacks = build_pr_stacks(prs);
-    for stack in pr_stacks {
-        if stack.len() > 1 {
-            for pr in &stack {
-                let nav_block = generate_nav_block(stack.clone(), pr.head.to_string());
-                if apply {
-                    if let Err(e) = update_pr_description(pr.clone(), nav_block, repo.to_string()) {
-                        eprintln!("#{}: cannot update PR: {}", pr.number, e);
-                        continue;
-                    }
-                    println!("PR #{} {:?}: updated", pr.number, pr.title);
-                } else {
-                    println!("PR #{} {:?}: updates with", pr.number, pr.title);
-                    for line in nav_block.lines() {
-                        println!("\t{}", line);
-                    }
-                    println!();
-                }
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    cli.init_logging();
+    catch_sigint()?;
+    if let Some(Commands::Completions(args)) = &cli.command {
+        return completions::run(args);
+    }
+    let mut config = cli.override_config(Config::load()?);
+    // Terminals without a UTF-8 locale get ASCII, whatever PRs get.
+    table::set_ascii(!config.emoji.unwrap_or(true) || !table::utf8_locale());
+    match cli.command.unwrap_or(Commands::Annotate(cli.annotate)) {
+        Commands::Annotate(args) => {
+            let (repo, forge) = match &args.github_event {
+                Some(path) => connect_event(&mut config, path)?,
+                None => connect(&mut config)?,
+            };
+            if let Some(run_id) = &args.rollback {
+                return rollback(&repo, forge.as_ref(), run_id);
             }
-        } else {
-            let pr = &stack[0];
-            if !pr.body.contains(STACK_HEADER) && !pr.body.contains(STACK_FOOTER) {
-                continue;
+            annotate(repo, forge.as_ref(), args, &config)?;
+            Ok(())
+        }
+        Commands::Status => {
+            let (repo, forge) = connect(&mut config)?;
+            status(repo, forge.as_ref(), &config)
+        }
+        Commands::Stats(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            stats::run(repo, forge.as_ref(), args, &config)
+        }
+        Commands::Log(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            graph::run(repo, forge.as_ref(), args, &config)
+        }
+        Commands::Tui => {
+            let (repo, forge) = connect(&mut config)?;
+            tui::run(repo, forge.as_ref(), &config)
+        }
+        Commands::Merge(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            merge(repo, forge.as_ref(), args, &config)
+        }
+        Commands::Plan(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            let output = args.output.clone();
+            let report = annotate(repo, forge.as_ref(), args.into_annotate_args(), &config)?;
+            plan::write(&output, &report, &config)
+        }
+        Commands::Apply(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            plan::apply(repo, forge.as_ref(), args, &config)
+        }
+        Commands::Unstack(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            unstack::run(repo, forge.as_ref(), args, &config)
+        }
+        Commands::Open(args) => {
+            let (repo, forge) = connect(&mut config)?;
+            open(repo, forge.as_ref(), args, &config)
+        }
+        Commands::Create(mut args) => {
+            let (repo, forge) = connect(&mut config)?;
+            create::run(repo.to_string(), forge.as_ref(), &args, &config)?;
+            if args.annotate_args.apply {
+                args.annotate_args.bookmark = Some(args.head);
+                annotate(repo, forge.as_ref(), args.annotate_args, &config)?;
             }
-            if apply {
-                if let Err(e) = update_pr_description(pr.clone(), "".to_string(), repo.to_string())
-                {
-                    eprintln!(
-                        "#{}: cannot remove navigation block from PR: {}",
-                        pr.number, e
-                    );
-                    continue;
-                }
+            Ok(())
+        }
+        Commands::Completions(args) => completions::run(&args),
+        Commands::Doctor => doctor::run(&mut config),
+        Commands::Init(args) => init::run(args, &config),
+        Commands::Rebase(args) => rebase(args, &config),
+        Commands::Sync(args) => sync(args, &mut config),
+        Commands::Watch(args) => watch(args, &mut config),
+        Commands::Bookmark(args) => bookmark::run(args, &config),
+        Commands::Absorb(args) => {
+            absorb_fixes(&args, &config)?;
+            let (repo, forge) = connect(&mut config)?;
+            annotate(repo, forge.as_ref(), args.annotate_args, &config)?;
+            Ok(())
+        }
+        Commands::Push(args) => {
+            push_stack(&config)?;
+            if !args.annotate {
+                return Ok(());
             }
-            println!("PR #{} {:?}: removed", pr.number, pr.title);
+            let (repo, forge) = connect(&mut config)?;
+            annotate(repo, forge.as_ref(), args.annotate_args, &config)?;
+            Ok(())
         }
     }
-    Ok(())
 }
 
-fn get_bookmarks() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let out = Command::new("jj").args(["bookmark", "list"]).output()?;
-    if !out.status.success() {
-        return Err(format!(
-            "cannot run 'jj bookmark list': {}",
-            String::from_utf8_lossy(&out.stderr)
-        )
-        .into());
+fn connect(config: &mut Config) -> Result<(String, Box<dyn Forge>), Box<dyn std::error::Error>> {
+    let (repo, forge) = connect_forge(config)?;
+    detect_trunk(config, forge.as_ref())?;
+    Ok((repo, forge))
+}
+
+/// Connects to the repository of a GitHub Actions event, without a local jj
+/// repository. The endpoints default to those of the GitHub instance running
+/// the workflow.
+fn connect_event(
+    config: &mut Config,
+    path: &Path,
+) -> Result<(String, Box<dyn Forge>), Box<dyn std::error::Error>> {
+    if config.forge.is_some_and(|forge| forge != ForgeKind::Github) {
+        return Err("--github-event only works with GitHub".into());
     }
-    let text = String::from_utf8(out.stdout)?;
-    let mut bookmarks = Vec::new();
-    for line in text.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+    let repo = github::read_event(path)?.repo;
+    let api_url = config.api_url.clone().or(env::var("GITHUB_API_URL").ok());
+    let web_url = config
+        .web_url
+        .clone()
+        .or(env::var("GITHUB_SERVER_URL").ok());
+    let endpoints = GithubEndpoints::new(
+        config.host.as_deref(),
+        api_url.as_deref(),
+        web_url.as_deref(),
+    );
+    let cache = if config.cache.unwrap_or(true) {
+        Cache::open()
+    } else {
+        None
+    };
+    let forge = GithubClient::new(
+        repo.to_string(),
+        endpoints,
+        config.retry_policy(),
+        config.fork_owner.clone(),
+        cache,
+        github_app(config)?,
+    )?;
+    let forge = Blocking(forge);
+    detect_trunk(config, &forge)?;
+    Ok((repo, Box::new(forge)))
+}
+
+fn detect_trunk(config: &mut Config, forge: &dyn Forge) -> Result<(), Box<dyn std::error::Error>> {
+    // A local bookmark named after the default branch must never be taken
+    // for a stack member, so the trunk is always known once connected.
+    if config.trunk.is_none() {
+        let trunk = forge
+            .default_branch()
+            .map_err(|e| format!("cannot detect the trunk branch (use --trunk): {}", e))?;
+        config.trunk = Some(trunk);
+    }
+    Ok(())
+}
+
+/// Returns the GitHub App to authenticate as, when github_app_id is set. Its
+/// private key is read from github_app_key_file or GITHUB_APP_PRIVATE_KEY.
+fn github_app(config: &Config) -> Result<Option<GithubApp>, Box<dyn std::error::Error>> {
+    let Some(app_id) = config.github_app_id else {
+        return Ok(None);
+    };
+    let pem = match &config.github_app_key_file {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?,
+        None => env::var("GITHUB_APP_PRIVATE_KEY").map_err(|_| {
+            "missing GitHub App private key: set github_app_key_file or GITHUB_APP_PRIVATE_KEY"
+        })?,
+    };
+    let app = GithubApp::new(app_id, config.github_app_installation_id, &pem)?;
+    Ok(Some(app))
+}
+
+/// Returns the host and repository path of remote, each replaced by --host and
+/// --repo when given.
+fn override_remote(config: &Config, remote: Option<(String, String)>) -> Option<(String, String)> {
+    let (host, repo) = match remote {
+        Some((host, repo)) => (Some(host), Some(repo)),
+        None => (None, None),
+    };
+    Some((config.host.clone().or(host)?, config.repo.clone().or(repo)?))
+}
+
+fn connect_forge(config: &Config) -> Result<(String, Box<dyn Forge>), Box<dyn std::error::Error>> {
+    let remote = match &config.remote {
+        Some(name) => {
+            let url = get_remote_url(Some(name))?;
+            let remote = parse_remote_url(&url)
+                .ok_or_else(|| format!("cannot parse URL of remote {:?}: {}", name, url))?;
+            Some(remote)
         }
-        if let Some((bookmark, _)) = line.split_once(':') {
-            bookmarks.push(bookmark.trim().to_string());
-        } else {
-            eprintln!("skipping malformed bookmark line: {:?}", line);
+        None => get_remote_url(None)
+            .ok()
+            .and_then(|url| parse_remote_url(&url)),
+    };
+    let retry = config.retry_policy();
+    let forge_kind = config.forge.unwrap_or_else(|| {
+        remote
+            .as_ref()
+            .map_or(ForgeKind::Github, |(host, _)| ForgeKind::detect(host))
+    });
+    match forge_kind {
+        ForgeKind::Github => {
+            // The repository comes from --repo, then the remote URL;
+            // GITHUB_REPOSITORY (set in GitHub Actions) and then
+            // `gh repo set-default` are only consulted when there is neither.
+            let repo = match (&config.repo, &remote) {
+                (Some(repo), _) => repo.to_string(),
+                (None, Some((_, path))) => path.to_string(),
+                (None, None) => match env::var("GITHUB_REPOSITORY") {
+                    Ok(repo) => repo,
+                    Err(_) => github::default_repo().map_err(|e| {
+                        format!(
+                            "cannot detect GitHub repository from git remote (use --repo): {}",
+                            e
+                        )
+                    })?,
+                },
+            };
+            let endpoints = GithubEndpoints::new(
+                config.host.as_deref(),
+                config.api_url.as_deref(),
+                config.web_url.as_deref(),
+            );
+            let cache = if config.cache.unwrap_or(true) {
+                Cache::open()
+            } else {
+                None
+            };
+            let forge = GithubClient::new(
+                repo.to_string(),
+                endpoints,
+                retry,
+                config.fork_owner.clone(),
+                cache,
+                github_app(config)?,
+            )?;
+            Ok((repo, Box::new(Blocking(forge))))
+        }
+        ForgeKind::Gitlab => {
+            let (host, project) = override_remote(config, remote)
+                .ok_or("cannot detect GitLab project from git remote (use --repo)")?;
+            let forge = GitlabClient::new(host, project.to_string(), retry)?;
+            Ok((project, Box::new(Blocking(forge))))
+        }
+        ForgeKind::Gitea => {
+            let (host, repo) = override_remote(config, remote)
+                .ok_or("cannot detect Gitea repository from git remote (use --repo)")?;
+            let api_url = config
+                .api_url
+                .clone()
+                .unwrap_or_else(|| format!("https://{}/api/v1", host));
+            let forge = GiteaClient::new(api_url, repo.to_string(), retry)?;
+            Ok((repo, Box::new(Blocking(forge))))
+        }
+        ForgeKind::Bitbucket => {
+            let (_, repo) = override_remote(config, remote)
+                .ok_or("cannot detect Bitbucket repository from git remote (use --repo)")?;
+            let api_url = config
+                .api_url
+                .clone()
+                .unwrap_or_else(|| bitbucket::DEFAULT_API_URL.to_string());
+            let forge = BitbucketClient::new(api_url, repo.to_string(), retry)?;
+            Ok((repo, Box::new(Blocking(forge))))
+        }
+        ForgeKind::Azure => {
+            let (host, path) = override_remote(config, remote)
+                .ok_or("cannot detect Azure DevOps repository from git remote (use --repo)")?;
+            let (org, project, name) = azure::parse_repo_path(&host, &path).ok_or_else(|| {
+                format!(
+                    "cannot parse Azure DevOps repository {:?} (use --repo ORG/PROJECT/REPO)",
+                    path
+                )
+            })?;
+            let api_url = config
+                .api_url
+                .clone()
+                .unwrap_or_else(|| azure::api_url(&host, &org));
+            let repo = format!("{}/{}/{}", org, project, name);
+            let forge = AzureClient::new(api_url, project, name, retry)?;
+            Ok((repo, Box::new(Blocking(forge))))
         }
     }
-    Ok(bookmarks)
 }
 
-fn get_open_prs(
+fn status(
     repo: String,
-    bookmarks_idx: HashSet<String>,
-) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
-    let url = format!("repos/{}/pulls", repo);
-    let out = Command::new("gh").args(["api", &url]).output()?;
-    if !out.status.success() {
-        return Err(format!(
-            "cannot run 'gh api {}': {}",
-            url,
-            String::from_utf8_lossy(&out.stderr)
-        )
-        .into());
+    forge: &dyn Forge,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("repo: {:?}", repo);
+    let bookmarks = get_bookmarks(config)?;
+    let bookmark_idx: HashSet<String> = bookmarks.into_iter().collect();
+    let prs = get_open_prs(forge, bookmark_idx, &repo, config)?;
+    if prs.is_empty() {
+        println!("no matching PRs found for bookmarks.");
+        return Ok(());
     }
-    let gh_prs: Vec<GithubPullRequest> = serde_json::from_slice(&out.stdout)?;
-    let mut prs = Vec::new();
-    for gh in gh_prs {
-        if bookmarks_idx.contains(&gh.head.r#ref) {
-            prs.push(PullRequest {
-                number: gh.number,
-                title: gh.title,
-                head: gh.head.r#ref,
-                base: gh.base.r#ref,
-                body: gh.body.unwrap_or_default(),
-            });
+    let divergent = find_divergent_bases(&prs, config)?;
+    for (i, stack) in build_pr_stacks(prs).iter().enumerate() {
+        println!("stack {}:", i + 1);
+        for (j, pr) in stack.iter().enumerate() {
+            let conflicts = if pr.mergeable == Some(false) {
+                " [conflicts]"
+            } else {
+                ""
+            };
+            println!(
+                "\t{}. PR #{} {:?} (branch: {}, base: {}){}",
+                j + 1,
+                pr.number,
+                pr.title,
+                pr.head,
+                pr.base,
+                conflicts
+            );
         }
     }
-    Ok(prs)
+    if divergent.is_empty() {
+        println!("all PRs are in sync with jj.");
+        return Ok(());
+    }
+    println!("out of sync with jj:");
+    for (pr, expected) in divergent {
+        println!(
+            "\tPR #{} {:?}: base is {}, jj parent is {} (retarget to {})",
+            pr.number, pr.title, pr.base, expected, expected
+        );
+    }
+    Ok(())
 }
 
-fn build_pr_stacks(prs: Vec<PullRequest>) -> Vec<Vec<PullRequest>> {
-    let mut head: HashMap<String, PullRequest> = HashMap::new();
-    for pr in &prs {
-        head.insert(pr.head.clone(), pr.clone());
-    }
-    let mut child_idx: HashMap<String, PullRequest> = HashMap::new();
-    for pr in &prs {
-        if let Some(parent) = head.get(&pr.base) {
-            child_idx.insert(parent.head.clone(), pr.clone());
+/// Returns the PRs whose base differs from their parent in the jj commit
+/// graph, along with the base they should have.
+fn find_divergent_bases(
+    prs: &[PullRequest],
+    config: &Config,
+) -> Result<Vec<(PullRequest, String)>, Box<dyn std::error::Error>> {
+    let heads: Vec<String> = prs.iter().map(|pr| pr.head.clone()).collect();
+    let mut divergent = Vec::new();
+    for pr in prs {
+        let expected = match get_parent_bookmark(&pr.head, &heads)? {
+            Some(parent) => parent,
+            None => match &config.trunk {
+                Some(trunk) => trunk.to_string(),
+                None if heads.contains(&pr.base) => "the trunk".to_string(),
+                None => continue,
+            },
+        };
+        if expected != pr.base {
+            divergent.push((pr.clone(), expected));
         }
     }
-    let mut visited = HashSet::new();
-    let mut stacks = Vec::new();
-    for pr in &prs {
-        if visited.contains(&pr.head) {
-            continue;
-        }
-        let mut current = pr.clone();
-        while let Some(parent) = head.get(&current.base) {
-            current = parent.clone();
-        }
-        let mut chain = Vec::new();
-        loop {
-            visited.insert(current.head.clone());
-            chain.push(current.clone());
-            if let Some(next) = child_idx.get(&current.head) {
-                current = next.clone();
-            } else {
-                break;
-            }
-        }
-        stacks.push(chain);
+    Ok(divergent)
+}
+
+/// Restores the titles and descriptions recorded in the journal of a run.
+fn rollback(repo: &str, forge: &dyn Forge, run_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = journal::read(run_id)?;
+    if let Some(entry) = entries.iter().find(|entry| entry.repo != repo) {
+        return Err(format!("run {} changed {}, not {}", run_id, entry.repo, repo).into());
     }
-    stacks
+    restore_journal(forge, &entries)
 }
 
-fn generate_nav_block(chain: Vec<PullRequest>, current_branch: String) -> String {
-    let mut s = String::new();
-    use std::fmt::Write;
-    writeln!(s, "{}", STACK_HEADER).unwrap();
-    writeln!(s, "Stack of changes:").unwrap();
-    for (i, pr) in chain.iter().enumerate() {
-        let suffix = if pr.head == current_branch {
-            " ◁"
-        } else {
-            ""
-        };
-        writeln!(
-            s,
-            "{}. PR #{} (branch: {}){}",
-            i + 1,
-            pr.number,
-            pr.head,
-            suffix
-        )
-        .unwrap();
+/// Updates is what an annotate run, or an applied plan, changes in the PRs:
+/// the PRs as read from the forge, along with the navigation block to write,
+/// if any, in batches updated one after the other.
+struct Updates<'a> {
+    prs: &'a [(PullRequest, Option<String>)],
+    batches: &'a [Range<usize>],
+    titles: &'a HashMap<i32, String>,
+    labels: &'a HashMap<i32, Vec<String>>,
+    reviewers: &'a HashMap<i32, Vec<String>>,
+}
+
+/// UpdateResults holds the outcome of the update of each PR, by number.
+type UpdateResults = Vec<(i32, Result<(), String>)>;
+
+/// Writes updates, batches concurrently. The titles and texts about to be
+/// replaced are journaled first, to restore them when interrupted, which
+/// returns an error, or later on --rollback.
+fn write_updates(
+    forge: &dyn Forge,
+    updates: &Updates,
+    journaled: Vec<journal::Entry>,
+    progress: &Progress,
+    config: &Config,
+    jobs: usize,
+    fail_fast: bool,
+) -> Result<(UpdateResults, Option<Journal>), Box<dyn std::error::Error>> {
+    let journal = if journaled.is_empty() {
+        None
+    } else {
+        let journal = Journal::create()?;
+        for entry in &journaled {
+            journal.record(entry)?;
+        }
+        Some(journal)
+    };
+    let interrupted = &*INTERRUPTED;
+    interrupted.store(false, Ordering::SeqCst);
+    SIGINT_TERMINATES.store(false, Ordering::SeqCst);
+    let failed = AtomicBool::new(false);
+    // Stacks are independent and updated concurrently, so that a failing
+    // stack holds no other back, but the PRs of a stack are updated one after
+    // the other, from the bottom up.
+    let results: UpdateResults = run_concurrently(updates.batches, jobs, |batch| {
+        updates.prs[batch.clone()]
+            .iter()
+            .map(|(pr, nav_block)| {
+                if interrupted.load(Ordering::Relaxed) {
+                    return (pr.number, Err(JjstackError::Interrupted.to_string()));
+                }
+                if fail_fast && failed.load(Ordering::Relaxed) {
+                    progress.finish(pr.number);
+                    return (pr.number, Ok(()));
+                }
+                progress.start(pr.number);
+                let title = updates.titles.get(&pr.number).cloned();
+                let mut result = match (nav_block, title) {
+                    (Some(nav_block), title) => update_pr_description(
+                        forge,
+                        pr.clone(),
+                        nav_block.to_string(),
+                        title,
+                        config,
+                    ),
+                    (None, Some(title)) => forge
+                        .update_pr_title(pr.number, title, None)
+                        .map_err(Into::into),
+                    (None, None) => Ok(()),
+                };
+                if let (Ok(()), Some(labels)) = (&result, updates.labels.get(&pr.number)) {
+                    result = forge
+                        .set_pr_labels(pr.number, labels.clone())
+                        .map_err(Into::into);
+                }
+                if let (Ok(()), Some(reviewers)) = (&result, updates.reviewers.get(&pr.number)) {
+                    result = forge
+                        .request_reviewers(pr.number, reviewers.clone())
+                        .map_err(Into::into);
+                }
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                progress.finish(pr.number);
+                (pr.number, result.map_err(|e| e.to_string()))
+            })
+            .collect::<Vec<_>>()
+    })
+    .into_iter()
+    .flatten()
+    .collect();
+    SIGINT_TERMINATES.store(true, Ordering::SeqCst);
+    if interrupted.load(Ordering::Relaxed) {
+        restore_interrupted(forge, journaled, &results)?;
+        return Err(JjstackError::Interrupted.into());
     }
-    writeln!(s, "{}", STACK_FOOTER).unwrap();
-    s
+    Ok((results, journal))
 }
 
-fn update_pr_description(
-    pr: PullRequest,
-    nav_block: String,
-    repo: String,
+/// Restores the journaled PRs that an interrupted run already updated.
+fn restore_interrupted(
+    forge: &dyn Forge,
+    journaled: Vec<journal::Entry>,
+    results: &[(i32, Result<(), String>)],
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let url = format!("repos/{}/pulls/{}", repo, pr.number);
-    let out = Command::new("gh").args(["api", &url]).output()?;
-    if !out.status.success() {
-        return Err(format!(
-            "cannot run 'gh api {}': {}",
-            url,
-            String::from_utf8_lossy(&out.stderr)
-        )
-        .into());
-    }
-    let gh_pr: GithubPullRequest = serde_json::from_slice(&out.stdout)?;
-    let gh_pr_body = gh_pr.body.unwrap_or("".to_string());
-    let mut new_body = remove_nav_block(gh_pr_body.to_string());
-    if !nav_block.is_empty() {
-        if !new_body.is_empty() && !new_body.ends_with('\n') {
-            new_body.push('\n');
+    let updated: HashSet<i32> = results
+        .iter()
+        .filter(|(_, result)| result.is_ok())
+        .map(|(number, _)| *number)
+        .collect();
+    let restored: Vec<journal::Entry> = journaled
+        .into_iter()
+        .filter(|entry| updated.contains(&entry.number))
+        .collect();
+    eprintln!("interrupted, restoring {} PRs", restored.len());
+    restore_journal(forge, &restored)
+}
+
+fn restore_journal(
+    forge: &dyn Forge,
+    entries: &[journal::Entry],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failed = 0;
+    for entry in entries {
+        let restored = match &entry.comment {
+            // In comment mode only the title and the comment were changed.
+            Some(comment) => forge
+                .update_pr_title(entry.number, entry.title.to_string(), None)
+                .map_err(Into::into)
+                .and_then(|()| update_nav_comment(forge, entry.number, comment.to_string())),
+            None => forge
+                .update_pr_title(
+                    entry.number,
+                    entry.title.to_string(),
+                    Some(entry.body.clone()),
+                )
+                .map_err(Into::into),
+        };
+        match restored {
+            Ok(()) => println!("PR #{}: restored", entry.number),
+            Err(e) => {
+                eprintln!("#{}: cannot restore PR: {}", entry.number, e);
+                failed += 1;
+            }
         }
-        new_body.push('\n');
-        new_body.push_str(&nav_block);
-        new_body.push('\n');
     }
-    if new_body == gh_pr_body {
-        return Ok(());
-    }
-    let patch_data = serde_json::to_string(&json!({ "body": new_body }))?;
-    let mut patch_cmd = Command::new("gh")
-        .args(["api", "--input", "-", "-X", "PATCH", &url])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .spawn()?;
-    {
-        let stdin = patch_cmd.stdin.as_mut().ok_or("failed to open stdin")?;
-        stdin.write_all(patch_data.as_bytes())?;
-    }
-    let out = patch_cmd.wait_with_output()?;
-    if !out.status.success() {
-        return Err(format!(
-            "cannot run 'gh api -X PATCH {}': {}",
-            url,
-            String::from_utf8_lossy(&out.stderr)
-        )
-        .into());
+    if failed > 0 {
+        return Err(format!("cannot restore {} PRs", failed).into());
     }
     Ok(())
 }
 
-fn remove_nav_block(body: String) -> String {
-    let start = match body.find(STACK_HEADER) {
-        Some(pos) => pos,
-        None => return body.to_string(),
+fn annotate(
+    repo: String,
+    forge: &dyn Forge,
+    args: AnnotateArgs,
+    config: &Config,
+) -> Result<Report, Box<dyn std::error::Error>> {
+    let AnnotateArgs {
+        apply,
+        assume_yes,
+        check,
+        json,
+        restack,
+        jobs,
+        current,
+        bookmark,
+        diff,
+        prune_merged,
+        only,
+        fail_fast,
+        github_event,
+        no_jj,
+        fix_bases,
+        rollback: _,
+        stack_root,
+        between,
+        stack_name,
+        push,
+        message,
+    } = args;
+    let template = load_template(config)?;
+    // Nothing is written before the whole run is computed, and confirmed when
+    // asked: these are the changes to make besides updating the stacked PRs.
+    let mut pending = Vec::new();
+    let named;
+    let config = match (&stack_name, &bookmark) {
+        (Some(name), Some(bookmark)) => {
+            pending.push(format!("name the stack of {} {:?}", bookmark, name));
+            let mut config = config.clone();
+            config
+                .stack_names
+                .get_or_insert_default()
+                .insert(bookmark.to_string(), name.to_string());
+            named = config;
+            &named
+        }
+        _ => config,
+    };
+
+    let mut report = Report {
+        repo: repo.to_string(),
+        applied: apply,
+        stacks: Vec::new(),
+        pruned: Vec::new(),
     };
+    if !json {
+        println!("repo: {:?}", repo);
+    }
+    // Closed PRs whose navigation block is to be removed.
+    let mut closed = Vec::new();
+    if prune_merged {
+        closed = prune_closed_prs(forge, &repo, config, jobs)?;
+    }
+
+    for pr in &closed {
+        pending.push(format!("remove the navigation of closed PR #{}", pr.number));
+    }
+    let mut unpushed = Vec::new();
+    let mut prs = match &github_event {
+        Some(path) => {
+            let event = github::read_event(path)?;
+            // A closed PR has left its stack, which is still annotated from
+            // the PRs that were stacked on it.
+            if event.action == "closed"
+                && !closed
+                    .iter()
+                    .any(|pr: &PullRequest| pr.number == event.pull_request.number)
+            {
+                let event_pr = vec![event.pull_request.clone()];
+                closed.extend(find_nav_blocks(forge, event_pr, config, jobs)?);
+            }
+            let prs = list_forge_prs(forge, &repo, config)?;
+            let event_pr = &event.pull_request;
+            let mut selected = vec![event_pr.head.clone(), event_pr.base.clone()];
+            selected.extend(
+                prs.iter()
+                    .filter(|pr| pr.base == event_pr.head)
+                    .map(|pr| pr.head.clone()),
+            );
+            select_stacks(prs, &selected)
+        }
+        None if no_jj => {
+            let mut prs = get_stacked_prs(forge, &repo, config)?;
+            if let Some(bookmark) = &bookmark {
+                prs = select_stacks(prs, std::slice::from_ref(bookmark));
+            }
+            if let Some(root) = &stack_root {
+                prs = select_connected(prs, &HashMap::new(), root);
+            }
+            prs
+        }
+        None => {
+            let bookmarks = get_bookmarks(config)?;
+            if bookmarks.is_empty() && closed.is_empty() {
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    println!("no bookmarks found.");
+                    table::print_report(&report);
+                }
+                return Ok(report);
+            }
 
-    let end = match body.find(STACK_FOOTER) {
-        Some(pos) => pos + STACK_FOOTER.len(),
-        None => return body.to_string(),
+            let bookmark_idx: HashSet<String> = bookmarks.into_iter().collect();
+            let mut prs = get_open_prs(forge, bookmark_idx, &repo, config)?;
+            let mut selected = bookmark.iter().cloned().collect::<Vec<_>>();
+            if current {
+                selected.extend(get_current_stack_bookmarks(config)?);
+            }
+            if !selected.is_empty() {
+                prs = select_stacks(prs, &selected);
+            }
+            if let Some(root) = &stack_root {
+                prs = select_stack_root(prs, root, config)?;
+            }
+            if push {
+                unpushed = find_unpushed(&prs)?;
+                if !apply && !json && !unpushed.is_empty() {
+                    println!("would push: {}", unpushed.join(", "));
+                }
+            }
+            prs
+        }
     };
+    if !unpushed.is_empty() {
+        pending.push(format!("push {}", unpushed.join(", ")));
+    }
+    if prs.is_empty() && closed.is_empty() {
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("no matching PRs found for bookmarks.");
+            table::print_report(&report);
+        }
+        return Ok(report);
+    }
+
+    // The bases are only changed on the forge along with the PRs.
+    let mut previous_bases = HashMap::new();
+    if restack {
+        previous_bases = restack_prs(forge, &mut prs, config, false, |pr| is_selected(&only, pr))?;
+    }
 
-    let before = body[..start].trim();
-    let after = body[end..].trim();
+    let dangling = find_dangling_bases(forge, &prs, config)?;
+    let dangling: Vec<(i32, String)> = dangling
+        .into_iter()
+        .filter(|(number, _)| {
+            prs.iter()
+                .any(|pr| pr.number == *number && is_selected(&only, pr))
+        })
+        .collect();
+    if !json {
+        for (number, base) in &dangling {
+            let hint = if fix_bases { "" } else { " (use --fix-bases)" };
+            eprintln!("#{}: base branch {} no longer exists{}", number, base, hint);
+        }
+    }
+    if fix_bases {
+        let fixed = fix_dangling_bases(forge, &mut prs, &dangling, config, false)?;
+        previous_bases.extend(fixed);
+    }
 
-    if before.is_empty() || after.is_empty() {
-        return format!("{}{}", before, after);
+    // Without a local repository the forge is the only topology there is.
+    if github_event.is_none() && !no_jj {
+        apply_jj_topology(&mut prs, config)?;
     }
 
-    format!("{}\n{}", before, after)
+    let mode = config.mode.unwrap_or_default();
+    let mut pr_stacks = build_pr_stacks(prs);
+    let mut truncated = (0, 0);
+    if let Some((base, top)) = &between {
+        let (chain, more_below, more_above) = select_between(&pr_stacks, base, top)
+            .ok_or_else(|| format!("{} is not under {} in any stack", base, top))?;
+        pr_stacks = vec![chain];
+        truncated = (more_below, more_above);
+    }
+    // Descriptions replaced by the text of the navigation comment.
+    let mut descriptions = HashMap::new();
+    if mode == AnnotateMode::Comment {
+        // The navigation comment stands in for the description from here on,
+        // so that diffs and skipped updates are computed against it.
+        let numbers: Vec<i32> = pr_stacks.iter().flatten().map(|pr| pr.number).collect();
+        let comments: HashMap<i32, String> = run_concurrently(&numbers, jobs, |&number| {
+            let comment = find_nav_comment(forge, number);
+            comment.map(|c| (number, c.map(|c| c.body).unwrap_or_default()))
+        })
+        .into_iter()
+        .collect::<Result<_, String>>()?;
+        for pr in pr_stacks.iter_mut().flatten() {
+            let comment = comments[&pr.number].to_string();
+            descriptions.insert(pr.number, std::mem::replace(&mut pr.body, comment));
+        }
+    }
+    let mut titles = HashMap::new();
+    let mut current_titles = HashMap::new();
+    if config.title_prefix.unwrap_or_default() {
+        for stack in &pr_stacks {
+            titles.extend(stack_titles(stack));
+        }
+        titles.retain(|number, _| {
+            pr_stacks
+                .iter()
+                .flatten()
+                .any(|pr| pr.number == *number && is_selected(&only, pr))
+        });
+        // The navigation lists the PRs under their new titles.
+        for pr in pr_stacks.iter_mut().flatten() {
+            if let Some(title) = titles.get(&pr.number) {
+                current_titles.insert(pr.number, std::mem::replace(&mut pr.title, title.clone()));
+            }
+        }
+    }
+    let siblings = list_sibling_prs(config)?;
+    let mut nav_blocks = HashMap::new();
+    for stack in pr_stacks.iter().filter(|stack| stack.len() > 1) {
+        nav_blocks.extend(stack_nav_blocks(
+            stack,
+            truncated,
+            &siblings,
+            config,
+            template.as_deref(),
+        )?);
+    }
+    // Navigation blocks too long for their description live in a comment
+    // instead, with its text here, empty when missing.
+    let mut nav_comments: HashMap<i32, String> = HashMap::new();
+    if let (AnnotateMode::Body, Some(max)) = (mode, forge.max_body_len()) {
+        let numbers: Vec<i32> = pr_stacks
+            .iter()
+            .flatten()
+            .filter(|pr| {
+                nav_blocks.get(&pr.number).is_some_and(|nav_block| {
+                    render_navigation(config, pr.body.to_string(), nav_block.to_string())
+                        .chars()
+                        .count()
+                        > max
+                })
+            })
+            .map(|pr| pr.number)
+            .collect();
+        nav_comments = run_concurrently(&numbers, jobs, |&number| {
+            let comment = find_nav_comment(forge, number);
+            comment.map(|c| (number, c.map(|c| c.body).unwrap_or_default()))
+        })
+        .into_iter()
+        .collect::<Result<_, String>>()?;
+    }
+    let new_body = |pr: &PullRequest, nav_block: &str| {
+        let nav_block = if nav_comments.contains_key(&pr.number) {
+            moved_nav_block()
+        } else {
+            nav_block.to_string()
+        };
+        render_navigation(config, pr.body.to_string(), nav_block)
+    };
+    let nav_changed = |pr: &PullRequest, nav_block: &str| {
+        new_body(pr, nav_block) != pr.body
+            || nav_comments
+                .get(&pr.number)
+                .is_some_and(|comment| comment != nav_block)
+    };
+    let mut labels = HashMap::new();
+    if config.labels.unwrap_or_default() {
+        let prefix = config
+            .label_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_LABEL_PREFIX);
+        for stack in &pr_stacks {
+            labels.extend(stack_labels(stack, prefix));
+        }
+    }
+    let mut reviewers = HashMap::new();
+    if config.propagate_reviewers.unwrap_or_default() {
+        let defaults = config.reviewers.clone().unwrap_or_default();
+        for stack in pr_stacks.iter().filter(|stack| stack.len() > 1) {
+            reviewers.extend(stack_reviewers(forge, stack, &defaults, jobs)?);
+        }
+    }
+    // With --only, the whole stacks are still rendered so that the navigation
+    // of the selected PRs is complete, but nothing else is touched.
+    labels.retain(|number, _| {
+        pr_stacks
+            .iter()
+            .flatten()
+            .any(|pr| pr.number == *number && is_selected(&only, pr))
+    });
+    reviewers.retain(|number, _| {
+        pr_stacks
+            .iter()
+            .flatten()
+            .any(|pr| pr.number == *number && is_selected(&only, pr))
+    });
+    let mut updates = Vec::new();
+    // Ranges of updates, one for each stack.
+    let mut batches = Vec::new();
+    if apply {
+        for stack in &pr_stacks {
+            let pr = &stack[0];
+            if !stack.iter().any(|pr| is_selected(&only, pr)) {
+                continue;
+            }
+            let start = updates.len();
+            if stack.len() > 1 {
+                for pr in stack.iter().filter(|pr| is_selected(&only, pr)) {
+                    updates.push((pr.clone(), Some(nav_blocks[&pr.number].to_string())));
+                }
+            } else if pr.body.contains(STACK_HEADER) || pr.body.contains(STACK_FOOTER) {
+                updates.push((pr.clone(), Some("".to_string())));
+            } else if labels.contains_key(&pr.number) || titles.contains_key(&pr.number) {
+                updates.push((pr.clone(), None));
+            }
+            if updates.len() > start {
+                batches.push(start..updates.len());
+            }
+        }
+    }
+    if apply && !assume_yes && io::stdin().is_terminal() {
+        let mut changing: HashSet<i32> = updates
+            .iter()
+            .filter(|(pr, nav_block)| {
+                titles.contains_key(&pr.number)
+                    || labels.contains_key(&pr.number)
+                    || reviewers.contains_key(&pr.number)
+                    || nav_block
+                        .as_ref()
+                        .is_some_and(|nav_block| nav_changed(pr, nav_block))
+            })
+            .map(|(pr, _)| pr.number)
+            .collect();
+        changing.extend(previous_bases.keys());
+        if (!changing.is_empty() || !pending.is_empty())
+            && !confirm_updates(&pr_stacks, &changing, &pending)?
+        {
+            return Err("aborted: nothing was changed".into());
+        }
+    }
+    if apply {
+        if let (Some(name), Some(bookmark)) = (&stack_name, &bookmark) {
+            set_stack_name(bookmark, name)?;
+        }
+        if !unpushed.is_empty() {
+            push_bookmarks(&unpushed, config)?;
+        }
+        for pr in pr_stacks.iter_mut().flatten() {
+            let Some(previous_base) = previous_bases.get(&pr.number) else {
+                continue;
+            };
+            if let Err(e) = forge.update_pr_base(pr.number, pr.base.to_string()) {
+                eprintln!("#{}: cannot retarget PR to {}: {}", pr.number, pr.base, e);
+                pr.base = previous_base.to_string();
+                previous_bases.remove(&pr.number);
+            }
+        }
+    }
+    report.pruned = remove_nav_blocks(forge, &closed, config, apply, jobs);
+    if !json {
+        for pr_report in &report.pruned {
+            if let Some(e) = &pr_report.error {
+                eprintln!(
+                    "#{}: cannot remove navigation block from closed PR: {}",
+                    pr_report.number, e
+                );
+            }
+        }
+        for pr in pr_stacks.iter().flatten() {
+            if let Some(previous_base) = previous_bases.get(&pr.number) {
+                let verb = if apply { "retargeted" } else { "retargets" };
+                println!(
+                    "PR #{} {:?}: {} from {} to {}",
+                    pr.number, pr.title, verb, previous_base, pr.base
+                );
+            }
+        }
+    }
+    // The titles and descriptions, or navigation comments, about to be
+    // replaced are journaled, to restore them when interrupted or on
+    // --rollback.
+    let journaled: Vec<journal::Entry> = updates
+        .iter()
+        .filter(|(pr, nav_block)| {
+            titles.contains_key(&pr.number)
+                || nav_block
+                    .as_ref()
+                    .is_some_and(|nav_block| nav_changed(pr, nav_block))
+        })
+        .map(|(pr, _)| {
+            let title = current_titles.get(&pr.number).unwrap_or(&pr.title);
+            match descriptions.get(&pr.number) {
+                Some(description) => journal::Entry {
+                    repo: repo.to_string(),
+                    number: pr.number,
+                    title: title.to_string(),
+                    body: description.to_string(),
+                    comment: Some(pr.body.to_string()),
+                },
+                None => journal::Entry {
+                    repo: repo.to_string(),
+                    number: pr.number,
+                    title: title.to_string(),
+                    body: pr.body.to_string(),
+                    comment: None,
+                },
+            }
+        })
+        .collect();
+    let progress = Progress::new(
+        &pr_stacks
+            .iter()
+            .map(|stack| {
+                let numbers = stack
+                    .iter()
+                    .filter(|pr| updates.iter().any(|(update, _)| update.number == pr.number))
+                    .map(|pr| pr.number)
+                    .collect();
+                (stack[0].head.to_string(), numbers)
+            })
+            .collect::<Vec<_>>(),
+    );
+    let changes = Updates {
+        prs: &updates,
+        batches: &batches,
+        titles: &titles,
+        labels: &labels,
+        reviewers: &reviewers,
+    };
+    let (results, journal) = write_updates(
+        forge, &changes, journaled, &progress, config, jobs, fail_fast,
+    )?;
+    if let (Some(journal), false) = (&journal, json) {
+        println!("undo with: jjstack annotate --rollback {}", journal.run_id);
+    }
+    if fail_fast {
+        if let Some((number, Err(e))) = results.iter().find(|(_, r)| r.is_err()) {
+            return Err(format!("#{}: cannot update PR: {}", number, e).into());
+        }
+    }
+    let mut results: HashMap<i32, Result<(), String>> = results.into_iter().collect();
+    if apply && (config.audit.unwrap_or_default() || message.is_some()) {
+        let mut changes: HashMap<i32, Vec<String>> = HashMap::new();
+        for pr in pr_stacks.iter().flatten() {
+            if let Some(previous_base) = previous_bases.get(&pr.number) {
+                changes.entry(pr.number).or_default().push(format!(
+                    "retargeted the base from `{}` to `{}`",
+                    previous_base, pr.base
+                ));
+            }
+        }
+        for (pr, nav_block) in &updates {
+            if !matches!(results.get(&pr.number), Some(Ok(()))) {
+                continue;
+            }
+            let body_changed = nav_block
+                .as_ref()
+                .is_some_and(|nav_block| nav_changed(pr, nav_block));
+            if body_changed {
+                let change = match nav_block.as_deref() {
+                    Some("") => "removed the stack navigation".to_string(),
+                    _ => "updated the stack navigation".to_string(),
+                };
+                changes.entry(pr.number).or_default().push(change);
+            }
+            if let Some(title) = titles.get(&pr.number) {
+                changes
+                    .entry(pr.number)
+                    .or_default()
+                    .push(format!("retitled to {:?}", title));
+            }
+        }
+        let changes: Vec<(i32, Vec<String>)> = changes.into_iter().collect();
+        let posted = run_concurrently(&changes, jobs, |(number, changes)| {
+            forge
+                .create_pr_comment(*number, audit_comment(changes, message.as_deref()))
+                .map_err(|e| e.to_string())
+        });
+        for ((number, _), result) in changes.iter().zip(posted) {
+            if let (Err(e), false) = (result, json) {
+                eprintln!("#{}: cannot comment the changes: {}", number, e);
+            }
+        }
+    }
+    if !json {
+        for pr in pr_stacks.iter().flatten() {
+            if let (true, None | Some(Ok(()))) =
+                (titles.contains_key(&pr.number), results.get(&pr.number))
+            {
+                let verb = if apply { "retitled" } else { "retitles" };
+                println!("PR #{}: {} to {:?}", pr.number, verb, pr.title);
+            }
+            if let (Some(reviewers), None | Some(Ok(()))) =
+                (reviewers.get(&pr.number), results.get(&pr.number))
+            {
+                let verb = if apply { "requested" } else { "requests" };
+                println!(
+                    "PR #{} {:?}: {} review from {}",
+                    pr.number,
+                    pr.title,
+                    verb,
+                    reviewers.join(", ")
+                );
+            }
+        }
+    }
+    for stack in pr_stacks {
+        let mut stack_report = StackReport { prs: Vec::new() };
+        if stack.len() > 1 {
+            for pr in &stack {
+                if !is_selected(&only, pr) {
+                    stack_report
+                        .prs
+                        .push(PrReport::new(pr, Action::Skip, pr.body.to_string()));
+                    continue;
+                }
+                let nav_block = &nav_blocks[&pr.number];
+                let mut pr_report = PrReport::new(pr, Action::Update, new_body(pr, nav_block));
+                pr_report.nav_block = Some(nav_block.to_string());
+                if !nav_changed(pr, nav_block) && !titles.contains_key(&pr.number) {
+                    info!("#{}: navigation is up to date", pr.number);
+                    pr_report.action = Action::Skip;
+                    pr_report.nav_block = None;
+                }
+                if let Some(Err(e)) = results.remove(&pr.number) {
+                    if !json {
+                        eprintln!("#{}: cannot update PR: {}", pr.number, e);
+                    }
+                    pr_report.error = Some(e);
+                } else if diff && !apply && !json && pr_report.action == Action::Update {
+                    println!("PR #{} {:?}:", pr.number, pr.title);
+                    print_body_diff(&pr.body, &pr_report.body);
+                    println!();
+                }
+                stack_report.prs.push(pr_report);
+            }
+        } else {
+            let pr = &stack[0];
+            let has_block = pr.body.contains(STACK_HEADER) || pr.body.contains(STACK_FOOTER);
+            if !has_block || !is_selected(&only, pr) {
+                stack_report
+                    .prs
+                    .push(PrReport::new(pr, Action::Skip, pr.body.to_string()));
+                report.stacks.push(stack_report);
+                continue;
+            }
+            let mut pr_report =
+                PrReport::new(pr, Action::Remove, remove_nav_block(pr.body.to_string()));
+            pr_report.nav_block = Some(String::new());
+            if let Some(Err(e)) = results.remove(&pr.number) {
+                if !json {
+                    eprintln!(
+                        "#{}: cannot remove navigation block from PR: {}",
+                        pr.number, e
+                    );
+                }
+                pr_report.error = Some(e);
+            } else if diff && !apply && !json {
+                println!("PR #{} {:?}:", pr.number, pr.title);
+                print_body_diff(&pr.body, &pr_report.body);
+                println!();
+            }
+            stack_report.prs.push(pr_report);
+        }
+        report.stacks.push(stack_report);
+    }
+    for pr_report in report.stacks.iter_mut().flat_map(|s| s.prs.iter_mut()) {
+        pr_report.previous_base = previous_bases.get(&pr_report.number).cloned();
+        pr_report.labels = labels.remove(&pr_report.number);
+        pr_report.reviewers = reviewers.remove(&pr_report.number);
+        if let Some(title) = current_titles.remove(&pr_report.number) {
+            pr_report.current_title = title;
+        }
+    }
+    if !json {
+        table::print_report(&report);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    let prs: Vec<&PrReport> = report
+        .stacks
+        .iter()
+        .flat_map(|s| &s.prs)
+        .chain(&report.pruned)
+        .collect();
+    let failed = prs.iter().filter(|pr| pr.error.is_some()).count();
+    if failed > 0 {
+        return Err(PartialFailure {
+            failed,
+            total: prs.len(),
+        }
+        .into());
+    }
+    if check {
+        let outdated = prs
+            .iter()
+            .filter(|pr| {
+                pr.action != Action::Skip
+                    || pr.previous_base.is_some()
+                    || pr.labels.is_some()
+                    || pr.reviewers.is_some()
+                    || pr.current_title != pr.title
+            })
+            .count();
+        if outdated > 0 {
+            return Err(format!("{} of {} PRs are out of date", outdated, prs.len()).into());
+        }
+        if !json {
+            println!("all {} PRs are up to date.", prs.len());
+        }
+    }
+    Ok(report)
+}
+
+fn merge(
+    repo: String,
+    forge: &dyn Forge,
+    args: MergeArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let trunk = config.trunk.clone().unwrap_or_default();
+    let template = load_template(config)?;
+    let siblings = list_sibling_prs(config)?;
+    let selected = match &args.bookmark {
+        Some(bookmark) => vec![bookmark.to_string()],
+        None => get_current_stack_bookmarks(config)?,
+    };
+    if selected.is_empty() {
+        println!("no bookmarks found in the current stack.");
+        return Ok(());
+    }
+    let bookmark_idx: HashSet<String> = get_bookmarks(config)?.into_iter().collect();
+    let queue = forge.has_merge_queue(&trunk)?;
+    if queue {
+        println!(
+            "{} has a merge queue: PRs are added to it instead of merged",
+            trunk
+        );
+    }
+    let mut waiting_since = Instant::now();
+    loop {
+        let prs = get_open_prs(forge, bookmark_idx.clone(), &repo, config)?;
+        let Some(stack) = build_pr_stacks(select_stacks(prs, &selected))
+            .into_iter()
+            .next()
+        else {
+            println!("no open PRs left in the stack.");
+            return Ok(());
+        };
+        let pr = &stack[0];
+        if pr.base != trunk {
+            return Err(format!(
+                "#{}: targets {} instead of {}; run annotate --restack first",
+                pr.number, pr.base, trunk
+            )
+            .into());
+        }
+        if pr.mergeable == Some(false) {
+            return Err(format!("#{}: has conflicts with {}", pr.number, pr.base).into());
+        }
+        match pr.checks {
+            Some(CheckState::Failure) => {
+                return Err(format!("#{}: checks failed", pr.number).into());
+            }
+            Some(CheckState::Pending) => {
+                if waiting_since.elapsed() >= Duration::from_secs(args.timeout) {
+                    return Err(format!("#{}: timed out waiting for checks", pr.number).into());
+                }
+                println!("PR #{} {:?}: waiting for checks", pr.number, pr.title);
+                thread::sleep(Duration::from_secs(args.poll_interval));
+                continue;
+            }
+            Some(CheckState::Success) | None => {}
+        }
+
+        if queue {
+            wait_for_merge_queue(forge, pr, &args)?;
+        } else {
+            forge
+                .merge_pr(pr.number, args.method)
+                .map_err(|e| format!("#{}: cannot merge PR: {}", pr.number, e))?;
+        }
+        println!("PR #{} {:?}: merged", pr.number, pr.title);
+        waiting_since = Instant::now();
+
+        let mut rest = stack[1..].to_vec();
+        for child in rest.iter_mut().filter(|child| child.base == pr.head) {
+            check_required_checks(forge, child, &pr.base, config)
+                .map_err(|e| format!("#{}: cannot retarget PR: {}", child.number, e))?;
+            forge
+                .update_pr_base(child.number, pr.base.clone())
+                .map_err(|e| format!("#{}: cannot retarget PR: {}", child.number, e))?;
+            println!(
+                "PR #{} {:?}: retargeted from {} to {}",
+                child.number, child.title, child.base, pr.base
+            );
+            child.base = pr.base.clone();
+        }
+        for mut stack in build_pr_stacks(rest) {
+            let titles = if config.title_prefix.unwrap_or_default() {
+                stack_titles(&stack)
+            } else {
+                HashMap::new()
+            };
+            for pr in &mut stack {
+                if let Some(title) = titles.get(&pr.number) {
+                    pr.title = title.to_string();
+                }
+            }
+            let nav_blocks = if stack.len() > 1 {
+                stack_nav_blocks(&stack, (0, 0), &siblings, config, template.as_deref())?
+            } else {
+                HashMap::new()
+            };
+            for pr in stack {
+                let nav_block = nav_blocks.get(&pr.number).cloned().unwrap_or_default();
+                let title = titles.get(&pr.number).cloned();
+                let number = pr.number;
+                update_pr_description(forge, pr, nav_block, title, config)
+                    .map_err(|e| format!("#{}: cannot update PR: {}", number, e))?;
+            }
+        }
+        if args.until == Some(pr.number) {
+            return Ok(());
+        }
+    }
+}
+
+/// Adds pr to the merge queue, unless it is already in it, and waits for it
+/// to land.
+fn wait_for_merge_queue(
+    forge: &dyn Forge,
+    pr: &PullRequest,
+    args: &MergeArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let waiting_since = Instant::now();
+    let mut enqueued = false;
+    loop {
+        match forge.merge_queue_state(pr.number)? {
+            QueueState::Merged => return Ok(()),
+            QueueState::NotQueued if enqueued => {
+                return Err(format!("#{}: removed from the merge queue", pr.number).into());
+            }
+            QueueState::NotQueued => {
+                forge.enqueue_pr(pr.number).map_err(|e| {
+                    format!("#{}: cannot add PR to the merge queue: {}", pr.number, e)
+                })?;
+                println!("PR #{} {:?}: added to the merge queue", pr.number, pr.title);
+                enqueued = true;
+            }
+            QueueState::Queued(position) => {
+                enqueued = true;
+                if waiting_since.elapsed() >= Duration::from_secs(args.timeout) {
+                    return Err(
+                        format!("#{}: timed out waiting for the merge queue", pr.number).into(),
+                    );
+                }
+                println!(
+                    "PR #{} {:?}: position {} in the merge queue",
+                    pr.number, pr.title, position
+                );
+            }
+        }
+        thread::sleep(Duration::from_secs(args.poll_interval));
+    }
+}
+
+fn open(
+    repo: String,
+    forge: &dyn Forge,
+    args: OpenArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let selected = match args.bookmark {
+        Some(bookmark) => vec![bookmark],
+        None => get_current_bookmarks(config)?,
+    };
+    if selected.is_empty() {
+        return Err(
+            "no bookmark on the working-copy commit or its ancestors (use --bookmark)".into(),
+        );
+    }
+    let bookmark_idx: HashSet<String> = get_bookmarks(config)?.into_iter().collect();
+    let mut prs = get_open_prs(forge, bookmark_idx, &repo, config)?;
+    apply_jj_topology(&mut prs, config)?;
+    if args.all {
+        prs = select_stacks(prs, &selected);
+    } else {
+        prs.retain(|pr| selected.contains(&pr.head));
+    }
+    if prs.is_empty() {
+        return Err(format!("no open PR found for {}", selected.join(", ")).into());
+    }
+    for pr in build_pr_stacks(prs).into_iter().flatten() {
+        if args.print {
+            println!("{}", pr.url);
+            continue;
+        }
+        open_url(&pr.url).map_err(|e| format!("cannot open {}: {}", pr.url, e))?;
+        println!("PR #{} {:?}: opened", pr.number, pr.title);
+    }
+    Ok(())
+}
+
+fn open_url(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+    cmd.arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+fn push_stack(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let bookmarks = get_current_stack_bookmarks(config)?;
+    if bookmarks.is_empty() {
+        println!("no bookmarks found in the current stack.");
+        return Ok(());
+    }
+    push_bookmarks(&bookmarks, config)
+}
+
+/// Pushes the bookmarks, first stamping their commits with JJStack-Id
+/// trailers when stack_ids is set.
+fn push_bookmarks(bookmarks: &[String], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    if config.stack_ids.unwrap_or_default() {
+        let symbols: Vec<String> = bookmarks.iter().map(|b| format!("{:?}", b)).collect();
+        let added = add_stack_ids(&symbols.join(" | "))?;
+        if added > 0 {
+            println!("added {} trailers to {} commits", STACK_ID_TRAILER, added);
+        }
+    }
+    println!("pushing: {}", bookmarks.join(", "));
+    Ok(git_push(bookmarks)?)
+}
+
+/// Folds the fixes of args.from into the current stack and pushes it.
+fn absorb_fixes(args: &AbsorbArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let bookmarks = get_current_stack_bookmarks(config)?;
+    if bookmarks.is_empty() {
+        return Err("no bookmarks found in the current stack".into());
+    }
+    match &args.into {
+        Some(into) => {
+            println!("squashing {} into {}", args.from, into);
+            squash(&args.from, into)?;
+        }
+        None => {
+            let symbols: Vec<String> = bookmarks.iter().map(|b| format!("{:?}", b)).collect();
+            println!("absorbing {} into: {}", args.from, bookmarks.join(", "));
+            absorb(&args.from, &format!("trunk()..({})", symbols.join(" | ")))?;
+        }
+    }
+    let conflicts = get_conflicts(&bookmarks)?;
+    if !conflicts.is_empty() {
+        println!("conflicts:");
+        for conflict in &conflicts {
+            println!("\t{}", conflict);
+        }
+        return Err(format!(
+            "{} commits have conflicts; resolve them before pushing",
+            conflicts.len()
+        )
+        .into());
+    }
+    push_stack(config)
+}
+
+fn rebase(args: RebaseArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    if !args.no_fetch {
+        git_fetch()?;
+    }
+    let bookmarks = if args.current {
+        get_current_stack_bookmarks(config)?
+    } else {
+        get_unmerged_bookmarks(config)?
+    };
+    if bookmarks.is_empty() {
+        println!("no bookmarks to rebase.");
+        return Ok(());
+    }
+    rebase_onto_trunk(&bookmarks, config)?;
+    if args.push {
+        println!("pushing: {}", bookmarks.join(", "));
+        git_push(&bookmarks)?;
+    }
+    Ok(())
+}
+
+/// Revset naming the trunk the stacks are rebased onto.
+fn trunk_revset(config: &Config) -> String {
+    match &config.trunk {
+        Some(trunk) => format!("{:?}", trunk),
+        None => "trunk()".to_string(),
+    }
+}
+
+/// Rebases the branches of the bookmarks onto the trunk, failing when that
+/// leaves commits with conflicts.
+fn rebase_onto_trunk(
+    bookmarks: &[String],
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let destination = trunk_revset(config);
+    println!("rebasing onto {}: {}", destination, bookmarks.join(", "));
+    rebase_branches(bookmarks, &destination)?;
+    let conflicts = get_conflicts(bookmarks)?;
+    if !conflicts.is_empty() {
+        println!("conflicts:");
+        for conflict in &conflicts {
+            println!("\t{}", conflict);
+        }
+        return Err(format!(
+            "{} commits have conflicts; resolve them before pushing",
+            conflicts.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Runs the daily loop in one go: fetch, rebase the stacks onto the updated
+/// trunk, push them and refresh their PRs. Without --apply, each step only
+/// reports what it would do.
+fn sync(args: SyncArgs, config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
+    let apply = args.annotate_args.apply;
+    if args.no_fetch {
+        println!("fetch: skipped");
+    } else if apply {
+        git_fetch()?;
+    } else {
+        println!("fetch: would run 'jj git fetch'");
+    }
+    let bookmarks = if args.annotate_args.current {
+        get_current_stack_bookmarks(config)?
+    } else {
+        get_unmerged_bookmarks(config)?
+    };
+    if bookmarks.is_empty() {
+        println!("no bookmarks to sync.");
+        return Ok(());
+    }
+    if apply {
+        rebase_onto_trunk(&bookmarks, config)?;
+        push_bookmarks(&bookmarks, config)?;
+    } else {
+        println!(
+            "rebase: would rebase onto {}: {}",
+            trunk_revset(config),
+            bookmarks.join(", ")
+        );
+        println!("push: would push: {}", bookmarks.join(", "));
+    }
+    let (repo, forge) = connect(config)?;
+    annotate(repo, forge.as_ref(), args.annotate_args, config)?;
+    Ok(())
+}
+
+/// Refreshes the PRs each time the bookmarks move, once they have stayed put
+/// for the debounce delay, until interrupted.
+fn watch(args: WatchArgs, config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
+    let (repo, forge) = connect(config)?;
+    let interval = Duration::from_secs(args.interval);
+    let debounce = Duration::from_secs(args.debounce);
+    let mut annotated: Option<String> = None;
+    loop {
+        let mut targets = bookmark_targets()?;
+        if annotated.as_ref() != Some(&targets) {
+            loop {
+                thread::sleep(debounce);
+                let settled = bookmark_targets()?;
+                if settled == targets {
+                    break;
+                }
+                targets = settled;
+            }
+            // A failed run is retried on the next change rather than ending
+            // the watch, unless it was interrupted.
+            if let Err(e) = annotate(
+                repo.clone(),
+                forge.as_ref(),
+                args.annotate_args.clone(),
+                config,
+            ) {
+                if let Some(JjstackError::Interrupted) = e.downcast_ref() {
+                    return Err(e);
+                }
+                eprintln!("cannot refresh PRs: {}", e);
+            }
+            annotated = Some(targets);
+            println!("watching bookmarks...");
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn get_open_prs(
+    forge: &dyn Forge,
+    bookmarks_idx: HashSet<String>,
+    repo: &str,
+    config: &Config,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    let prs = list_repo_prs(forge, repo, config)?;
+    // Branches pushed with `jj git push --change` belong to the repository
+    // as long as their change does, even without a local bookmark.
+    let change_ids = if prs
+        .iter()
+        .any(|pr| !bookmarks_idx.contains(&pr.head) && branch_change_id(config, &pr.head).is_some())
+    {
+        list_mutable_change_ids()?
+    } else {
+        Vec::new()
+    };
+    let is_local = |head: &str| {
+        bookmarks_idx.contains(head)
+            || branch_change_id(config, head).is_some_and(|id| {
+                is_stack_bookmark(config, head) && change_ids.iter().any(|c| c.starts_with(id))
+            })
+    };
+    // A bookmark renamed after it was pushed still names the same change,
+    // which its JJStack-Id trailer tells.
+    let mut renamed = HashMap::new();
+    if prs.iter().any(|pr| !is_local(&pr.head)) {
+        renamed = get_renamed_remote_bookmarks(config)?;
+        if config.stack_ids.unwrap_or_default() {
+            renamed.extend(get_renamed_bookmarks(config)?);
+        }
+    }
+    let prs = prs
+        .into_iter()
+        .filter(|pr| {
+            if let Some(bookmark) = renamed.get(&pr.head) {
+                // No forge lets a PR change its head branch, so the PR
+                // follows the new name only once it is renamed back.
+                warn!(
+                    "#{}: bookmark {} was renamed to {}, but the PR still tracks {}: \
+                     run 'jj bookmark rename {} {}' to keep updating it, or \
+                     'jj git push -b {}' to open a new PR",
+                    pr.number, pr.head, bookmark, pr.head, bookmark, pr.head, bookmark
+                );
+                return true;
+            }
+            if !is_local(&pr.head) {
+                debug!(
+                    "#{}: skipped: {} is not a local bookmark",
+                    pr.number, pr.head
+                );
+                return false;
+            }
+            true
+        })
+        .collect();
+    Ok(prs)
+}
+
+/// Lists the open PRs that are stacked on another one or have another one
+/// stacked on them, going by the forge alone.
+fn get_stacked_prs(
+    forge: &dyn Forge,
+    repo: &str,
+    config: &Config,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    let prs = list_forge_prs(forge, repo, config)?;
+    Ok(build_pr_stacks(prs)
+        .into_iter()
+        .filter(|stack| stack.len() > 1)
+        .flatten()
+        .collect())
+}
+
+/// Keeps the PRs in the stack of bookmark, connected to it through their
+/// base on the forge or their parent bookmark in jj.
+fn select_stack_root(
+    prs: Vec<PullRequest>,
+    bookmark: &str,
+    config: &Config,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    let mut heads: Vec<String> = prs.iter().map(|pr| pr.head.clone()).collect();
+    if !heads.iter().any(|head| head == bookmark) {
+        heads.push(bookmark.to_string());
+    }
+    let jj_bases: HashMap<String, String> = get_bookmark_parents(config, &heads)?
+        .into_iter()
+        .filter_map(|(head, parent)| Some((head, parent?)))
+        .collect();
+    Ok(select_connected(prs, &jj_bases, bookmark))
+}
+
+/// Lists the open PRs of the repository whose head is a stack bookmark.
+fn list_forge_prs(
+    forge: &dyn Forge,
+    repo: &str,
+    config: &Config,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    Ok(list_repo_prs(forge, repo, config)?
+        .into_iter()
+        .filter(|pr| is_stack_bookmark(config, &pr.head))
+        .collect())
+}
+
+/// Lists the open PRs whose head is pushed to the repository jjstack
+/// manages, whether or not there is a local bookmark for it.
+fn list_repo_prs(
+    forge: &dyn Forge,
+    repo: &str,
+    config: &Config,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    // Bookmarks are pushed either to the base repository or to the fork
+    // named in the configuration, so heads are matched as OWNER:BRANCH.
+    let owner = match &config.fork_owner {
+        Some(owner) => owner.as_str(),
+        None => repo.split('/').next().unwrap_or_default(),
+    };
+    let prs = forge
+        .list_open_prs()?
+        .into_iter()
+        .filter(|pr| {
+            if let Some(head_owner) = pr.head_owner.as_deref().filter(|o| *o != owner) {
+                info!("#{}: skipped: head is in {}'s fork", pr.number, head_owner);
+                return false;
+            }
+            if pr.draft && config.skip_drafts.unwrap_or_default() {
+                info!("#{}: skipped: draft", pr.number);
+                return false;
+            }
+            true
+        })
+        .collect();
+    Ok(prs)
+}
+
+/// Strips the navigation block from the recently merged or closed PRs of
+/// the repository that still carry one, as the stack they described has
+/// moved on without them.
+fn prune_closed_prs(
+    forge: &dyn Forge,
+    repo: &str,
+    config: &Config,
+    jobs: usize,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    let owner = match &config.fork_owner {
+        Some(owner) => owner.as_str(),
+        None => repo.split('/').next().unwrap_or_default(),
+    };
+    let prs: Vec<PullRequest> = forge
+        .list_closed_prs()?
+        .into_iter()
+        .filter(|pr| {
+            is_stack_bookmark(config, &pr.head)
+                && pr.head_owner.as_deref().is_none_or(|o| o == owner)
+        })
+        .collect();
+    find_nav_blocks(forge, prs, config, jobs)
+}
+
+/// Strips the navigation block from the PRs carrying one, in their
+/// description or, in comment mode, in their navigation comment. Without
+/// apply, only reports what would be removed.
+fn strip_nav_blocks(
+    forge: &dyn Forge,
+    prs: Vec<PullRequest>,
+    config: &Config,
+    apply: bool,
+    jobs: usize,
+) -> Result<Vec<PrReport>, Box<dyn std::error::Error>> {
+    let prs = find_nav_blocks(forge, prs, config, jobs)?;
+    Ok(remove_nav_blocks(forge, &prs, config, apply, jobs))
+}
+
+/// Keeps the PRs of prs carrying a navigation block. In comment mode, their
+/// body is replaced by the text of their navigation comment.
+fn find_nav_blocks(
+    forge: &dyn Forge,
+    mut prs: Vec<PullRequest>,
+    config: &Config,
+    jobs: usize,
+) -> Result<Vec<PullRequest>, Box<dyn std::error::Error>> {
+    if config.mode.unwrap_or_default() == AnnotateMode::Comment {
+        let numbers: Vec<i32> = prs.iter().map(|pr| pr.number).collect();
+        let comments: HashMap<i32, String> = run_concurrently(&numbers, jobs, |&number| {
+            let comment = find_nav_comment(forge, number);
+            comment.map(|c| (number, c.map(|c| c.body).unwrap_or_default()))
+        })
+        .into_iter()
+        .collect::<Result<_, String>>()?;
+        for pr in &mut prs {
+            pr.body = comments[&pr.number].to_string();
+        }
+    }
+    prs.retain(|pr| pr.body.contains(STACK_HEADER) || pr.body.contains(STACK_FOOTER));
+    Ok(prs)
+}
+
+/// Removes the navigation block found by find_nav_blocks from each of prs.
+fn remove_nav_blocks(
+    forge: &dyn Forge,
+    prs: &[PullRequest],
+    config: &Config,
+    apply: bool,
+    jobs: usize,
+) -> Vec<PrReport> {
+    let mut results: HashMap<i32, Result<(), String>> = HashMap::new();
+    if apply {
+        results = run_concurrently(prs, jobs, |pr| {
+            let result = update_pr_description(forge, pr.clone(), String::new(), None, config);
+            (pr.number, result.map_err(|e| e.to_string()))
+        })
+        .into_iter()
+        .collect();
+    }
+    prs.iter()
+        .map(|pr| {
+            let body = render_navigation(config, pr.body.to_string(), String::new());
+            let mut pr_report = PrReport::new(pr, Action::Remove, body);
+            pr_report.nav_block = Some(String::new());
+            if let Some(Err(e)) = results.remove(&pr.number) {
+                pr_report.error = Some(e);
+            }
+            pr_report
+        })
+        .collect()
+}
+
+/// With the jj topology, rewires the base of each PR to the bookmark below
+/// it in the jj commit graph, so that stacks follow the commits even when a
+/// PR was retargeted by hand on the forge. PRs of branches pushed with `jj
+/// git push --change` always follow the jj graph, as their base on the forge
+/// is usually just the trunk.
+fn apply_jj_topology(
+    prs: &mut [PullRequest],
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let all = config.topology.unwrap_or_default() == Topology::Jj;
+    let follows_jj = |pr: &PullRequest| all || branch_change_id(config, &pr.head).is_some();
+    if !prs.iter().any(follows_jj) {
+        return Ok(());
+    }
+    let heads: Vec<String> = prs.iter().map(|pr| pr.head.clone()).collect();
+    let parents = get_bookmark_parents(config, &heads)?;
+    for pr in prs.iter_mut().filter(|pr| follows_jj(pr)) {
+        let Some(parent) = parents.get(&pr.head) else {
+            continue;
+        };
+        let Some(base) = parent.as_ref().or(config.trunk.as_ref()) else {
+            continue;
+        };
+        if *base != pr.base {
+            info!(
+                "#{}: base is {} on the forge but {} in jj, following jj",
+                pr.number, pr.base, base
+            );
+            pr.base = base.to_string();
+        }
+    }
+    Ok(())
+}
+
+/// Runs f over items on up to jobs scoped threads, returning the results in
+/// the order of items.
+fn run_concurrently<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.clamp(1, items.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        done.push((i, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Reports whether pr is picked by the --only selectors, PR numbers or
+/// bookmark names; no selector picks every PR.
+fn is_selected(only: &[String], pr: &PullRequest) -> bool {
+    only.is_empty()
+        || only.iter().any(|selector| {
+            selector.trim_start_matches('#').parse() == Ok(pr.number) || *selector == pr.head
+        })
+}
+
+fn restack_prs(
+    forge: &dyn Forge,
+    prs: &mut [PullRequest],
+    config: &Config,
+    apply: bool,
+    selected: impl Fn(&PullRequest) -> bool,
+) -> Result<HashMap<i32, String>, Box<dyn std::error::Error>> {
+    let heads: HashSet<String> = prs.iter().map(|pr| pr.head.clone()).collect();
+    let mut previous_bases = HashMap::new();
+    for pr in prs.iter_mut() {
+        if heads.contains(&pr.base) || !selected(pr) {
+            continue;
+        }
+        let mut base = pr.base.clone();
+        let mut seen = HashSet::new();
+        while seen.insert(base.clone()) {
+            match forge.find_closed_pr(&base)? {
+                Some(parent) => base = parent.base,
+                None => break,
+            }
+        }
+        if base == pr.base {
+            continue;
+        }
+        if let Err(e) = check_required_checks(forge, pr, &base, config) {
+            eprintln!("#{}: cannot retarget PR to {}: {}", pr.number, base, e);
+            continue;
+        }
+        if apply {
+            if let Err(e) = forge.update_pr_base(pr.number, base.to_string()) {
+                eprintln!("#{}: cannot retarget PR to {}: {}", pr.number, base, e);
+                continue;
+            }
+        }
+        previous_bases.insert(pr.number, std::mem::replace(&mut pr.base, base));
+    }
+    Ok(previous_bases)
+}
+
+/// Compares the status checks that branch protection requires on base with
+/// those reported on the head of pr, so that retargeting does not leave the
+/// PR waiting for checks that will never run. Missing checks are reported,
+/// or turned into an error with strict_protection.
+fn check_required_checks(
+    forge: &dyn Forge,
+    pr: &PullRequest,
+    base: &str,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(required) = forge.required_checks(base)? else {
+        debug!("cannot read the branch protection of {}", base);
+        return Ok(());
+    };
+    if required.is_empty() {
+        return Ok(());
+    }
+    let reported = forge.reported_checks(&pr.head)?;
+    let missing: Vec<&str> = required
+        .iter()
+        .filter(|check| !reported.contains(check))
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "{} requires checks that never reported on {}: {}",
+        base,
+        pr.head,
+        missing.join(", ")
+    );
+    if config.strict_protection.unwrap_or_default() {
+        return Err(format!("{} (drop --strict-protection to retarget anyway)", message).into());
+    }
+    warn!("#{}: {}", pr.number, message);
+    Ok(())
+}
+
+/// Warns about the PRs whose head differs from the commit of their local
+/// bookmark, whose navigation would describe stale branches, and returns
+/// the bookmarks with local commits left to push.
+fn find_unpushed(prs: &[PullRequest]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let heads: Vec<String> = prs
+        .iter()
+        .filter(|pr| pr.head_sha.is_some())
+        .map(|pr| pr.head.clone())
+        .collect();
+    let local = bookmark_commit_ids(&heads)?;
+    let mut unpushed = Vec::new();
+    for pr in prs {
+        let (Some(remote), Some(local)) = (&pr.head_sha, local.get(&pr.head)) else {
+            continue;
+        };
+        // Some forges abbreviate the commit id.
+        if local.starts_with(remote.as_str()) {
+            continue;
+        }
+        let count = |revset: String| list_commits(&revset).map(|commits| commits.len());
+        match (
+            count(format!("{}..{}", remote, local)),
+            count(format!("{}..{}", local, remote)),
+        ) {
+            (Ok(ahead), Ok(0)) => {
+                warn!(
+                    "bookmark {} not pushed (local ahead by {} commits)",
+                    pr.head, ahead
+                );
+                unpushed.push(pr.head.clone());
+            }
+            (Ok(0), Ok(behind)) => warn!(
+                "bookmark {} is behind the head of PR #{} by {} commits (run 'jj git fetch')",
+                pr.head, pr.number, behind
+            ),
+            _ => {
+                warn!(
+                    "bookmark {} not pushed (local differs from the head of PR #{})",
+                    pr.head, pr.number
+                );
+                unpushed.push(pr.head.clone());
+            }
+        }
+    }
+    Ok(unpushed)
+}
+
+/// Finds the PRs based on a branch deleted from the repository, returning
+/// their numbers and the missing bases.
+fn find_dangling_bases(
+    forge: &dyn Forge,
+    prs: &[PullRequest],
+    config: &Config,
+) -> Result<Vec<(i32, String)>, Box<dyn std::error::Error>> {
+    let heads: HashSet<&str> = prs.iter().map(|pr| pr.head.as_str()).collect();
+    let mut missing: HashMap<&str, bool> = HashMap::new();
+    let mut dangling = Vec::new();
+    for pr in prs {
+        if heads.contains(pr.base.as_str()) || config.trunk.as_ref() == Some(&pr.base) {
+            continue;
+        }
+        let is_missing = match missing.get(pr.base.as_str()) {
+            Some(&is_missing) => is_missing,
+            None => {
+                let is_missing = !forge.branch_exists(&pr.base)?;
+                missing.insert(&pr.base, is_missing);
+                is_missing
+            }
+        };
+        if is_missing {
+            dangling.push((pr.number, pr.base.clone()));
+        }
+    }
+    Ok(dangling)
+}
+
+/// Retargets the PRs of dangling onto their nearest live ancestor: the first
+/// existing base among the closed PRs of the missing branches, or else the
+/// trunk. Returns the previous base of each retargeted PR.
+fn fix_dangling_bases(
+    forge: &dyn Forge,
+    prs: &mut [PullRequest],
+    dangling: &[(i32, String)],
+    config: &Config,
+    apply: bool,
+) -> Result<HashMap<i32, String>, Box<dyn std::error::Error>> {
+    let heads: HashSet<String> = prs.iter().map(|pr| pr.head.clone()).collect();
+    let trunk = config
+        .trunk
+        .clone()
+        .ok_or("cannot fix bases without knowing the trunk (use --trunk)")?;
+    let mut previous_bases = HashMap::new();
+    for (number, missing) in dangling {
+        let Some(pr) = prs.iter_mut().find(|pr| pr.number == *number) else {
+            continue;
+        };
+        let mut base = trunk.to_string();
+        let mut current = missing.to_string();
+        let mut seen = HashSet::new();
+        while seen.insert(current.clone()) {
+            let Some(parent) = forge.find_closed_pr(&current)? else {
+                break;
+            };
+            current = parent.base;
+            if current == trunk || heads.contains(&current) || forge.branch_exists(&current)? {
+                base = current;
+                break;
+            }
+        }
+        if let Err(e) = check_required_checks(forge, pr, &base, config) {
+            eprintln!("#{}: cannot retarget PR to {}: {}", pr.number, base, e);
+            continue;
+        }
+        if apply {
+            if let Err(e) = forge.update_pr_base(pr.number, base.to_string()) {
+                eprintln!("#{}: cannot retarget PR to {}: {}", pr.number, base, e);
+                continue;
+            }
+        }
+        previous_bases.insert(pr.number, std::mem::replace(&mut pr.base, base));
+    }
+    Ok(previous_bases)
+}
+
+fn load_template(config: &Config) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let Some(path) = &config.template_file else {
+        return Ok(None);
+    };
+    let template =
+        fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    Ok(Some(template))
+}
+
+/// Renders the comment telling what an annotate run changed in a PR.
+fn audit_comment(changes: &[String], message: Option<&str>) -> String {
+    let mut s = String::from("jjstack updated this PR:\n");
+    for change in changes {
+        s.push_str(&format!("- {}\n", change));
+    }
+    if let Some(message) = message {
+        s.push_str(&format!("\n{}\n", message));
+    }
+    s
+}
+
+/// Asks on the terminal whether to update the changing PRs, listed by stack,
+/// and to make the pending changes.
+fn confirm_updates(
+    stacks: &[Stack],
+    changing: &HashSet<i32>,
+    pending: &[String],
+) -> io::Result<bool> {
+    let mut breakdown = Vec::new();
+    for stack in stacks {
+        let numbers: Vec<String> = stack
+            .iter()
+            .filter(|pr| changing.contains(&pr.number))
+            .map(|pr| format!("#{}", pr.number))
+            .collect();
+        if !numbers.is_empty() {
+            breakdown.push(format!(
+                "  stack of {}: {}",
+                stack[0].head,
+                numbers.join(", ")
+            ));
+        }
+    }
+    for line in &breakdown {
+        eprintln!("{}", line);
+    }
+    for change in pending {
+        eprintln!("  {}", change);
+    }
+    let prs = if changing.len() == 1 { "PR" } else { "PRs" };
+    let stacks = if breakdown.len() == 1 {
+        "stack"
+    } else {
+        "stacks"
+    };
+    let others = match pending.len() {
+        0 => String::new(),
+        1 => " and make 1 other change".to_string(),
+        n => format!(" and make {} other changes", n),
+    };
+    eprint!(
+        "Update {} {} across {} {}{}? [y/N] ",
+        changing.len(),
+        prs,
+        breakdown.len(),
+        stacks,
+        others
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Renders the navigation of each PR of stack, noting how many PRs of the
+/// whole stack are left out under and over it, and linking the PRs of
+/// siblings on the same branch.
+fn stack_nav_blocks(
+    stack: &Stack,
+    (more_below, more_above): (usize, usize),
+    siblings: &[SiblingPrs],
+    config: &Config,
+    template: Option<&str>,
+) -> Result<HashMap<i32, NavBlock>, Box<dyn std::error::Error>> {
+    let (format, order) = config.nav_layout();
+    let options = NavOptions {
+        style: config.nav_style.unwrap_or_default(),
+        format,
+        order,
+        ci_status: config.ci_status.unwrap_or_default(),
+        diff_stats: config.diff_stats.unwrap_or_default(),
+        neighbors: config.neighbors.unwrap_or_default(),
+        ascii: !config.emoji.unwrap_or(true),
+        more_below,
+        more_above,
+        name: stack_name(stack, config),
+    };
+    let related = find_related_prs(stack, siblings, config);
+    let mut nav_blocks = HashMap::new();
+    for pr in stack {
+        let mut nav_block = match template {
+            Some(template) => render_nav_template(
+                template,
+                stack.clone(),
+                pr.head.to_string(),
+                options.name.as_deref(),
+            )?,
+            None => generate_nav_block(stack.clone(), pr.head.to_string(), &options),
+        };
+        if let Some(related) = related.get(&pr.number) {
+            nav_block = append_related_prs(&nav_block, related);
+        }
+        nav_blocks.insert(pr.number, nav_block);
+    }
+    Ok(nav_blocks)
+}
+
+/// Finds the name of stack in the stack_names setting of one of its bookmarks
+/// or else in the JJStack-Name trailer of one of their commits, the topmost
+/// first.
+fn stack_name(stack: &Stack, config: &Config) -> Option<String> {
+    if let Some(names) = &config.stack_names {
+        if let Some(name) = stack.iter().rev().find_map(|pr| names.get(&pr.head)) {
+            return Some(name.to_string());
+        }
+    }
+    let heads: Vec<String> = stack.iter().map(|pr| pr.head.clone()).collect();
+    match get_stack_names(&heads) {
+        Ok(names) => stack
+            .iter()
+            .rev()
+            .find_map(|pr| names.get(&pr.head).cloned()),
+        Err(e) => {
+            debug!("cannot read the stack names: {}", e);
+            None
+        }
+    }
+}
+
+/// RelatedPrs maps a PR to the PRs of other repositories of the workspace,
+/// each given along with its repository.
+type RelatedPrs = HashMap<i32, Vec<(String, PullRequest)>>;
+
+/// SiblingPrs are the open PRs of another repository of the workspace.
+struct SiblingPrs {
+    repo: String,
+    bookmark_prefix: String,
+    prs: Vec<PullRequest>,
+}
+
+/// Lists the open PRs of the other repositories of the workspace, reaching
+/// them with the settings of this one.
+fn list_sibling_prs(config: &Config) -> Result<Vec<SiblingPrs>, Box<dyn std::error::Error>> {
+    let mut siblings = Vec::new();
+    for sibling in config.workspace.iter().flatten() {
+        // The endpoints of this repository only apply to siblings on the
+        // same host.
+        let same_host = sibling.host.is_none();
+        let sibling_config = Config {
+            forge: sibling.forge.or(config.forge),
+            repo: Some(sibling.repo.to_string()),
+            host: sibling.host.clone().or(config.host.clone()),
+            api_url: config.api_url.clone().filter(|_| same_host),
+            web_url: config.web_url.clone().filter(|_| same_host),
+            ..config.clone()
+        };
+        let (repo, forge) = connect_forge(&sibling_config)?;
+        let prs = forge
+            .list_open_prs()
+            .map_err(|e| format!("{}: cannot list PRs: {}", repo, e))?;
+        siblings.push(SiblingPrs {
+            repo,
+            bookmark_prefix: sibling.bookmark_prefix.clone().unwrap_or_default(),
+            prs,
+        });
+    }
+    Ok(siblings)
+}
+
+/// Finds, for each PR of stack, the open PRs of siblings whose branch has the
+/// same name once the bookmark prefix of each repository is dropped.
+fn find_related_prs(stack: &Stack, siblings: &[SiblingPrs], config: &Config) -> RelatedPrs {
+    let mut related = RelatedPrs::new();
+    let prefix = config.bookmark_prefix.as_deref().unwrap_or_default();
+    for sibling in siblings {
+        for pr in stack {
+            let Some(name) = pr.head.strip_prefix(prefix) else {
+                continue;
+            };
+            for other in sibling
+                .prs
+                .iter()
+                .filter(|other| other.head.strip_prefix(&*sibling.bookmark_prefix) == Some(name))
+            {
+                related
+                    .entry(pr.number)
+                    .or_default()
+                    .push((sibling.repo.to_string(), other.clone()));
+            }
+        }
+    }
+    related
+}
+
+/// Computes the labels of the PRs of stack whose stack position label is
+/// missing or stale: labels starting with prefix are replaced by
+/// prefix + "POSITION/TOTAL", or dropped for a stack of one PR.
+fn stack_labels(stack: &Stack, prefix: &str) -> HashMap<i32, Vec<String>> {
+    let mut labels = HashMap::new();
+    for (i, pr) in stack.iter().enumerate() {
+        let mut pr_labels: Vec<String> = pr
+            .labels
+            .iter()
+            .filter(|label| !label.starts_with(prefix))
+            .cloned()
+            .collect();
+        if stack.len() > 1 {
+            pr_labels.push(format!("{}{}/{}", prefix, i + 1, stack.len()));
+        }
+        let mut current = pr.labels.clone();
+        current.sort();
+        let mut wanted = pr_labels.clone();
+        wanted.sort();
+        if current != wanted {
+            labels.insert(pr.number, pr_labels);
+        }
+    }
+    labels
+}
+
+/// Computes the titles of the PRs of stack whose [POSITION/TOTAL] prefix is
+/// missing or stale; the prefix is dropped for a stack of one PR.
+fn stack_titles(stack: &Stack) -> HashMap<i32, String> {
+    let mut titles = HashMap::new();
+    for (i, pr) in stack.iter().enumerate() {
+        let title = strip_title_prefix(&pr.title);
+        let title = if stack.len() > 1 {
+            format!("[{}/{}] {}", i + 1, stack.len(), title)
+        } else {
+            title.to_string()
+        };
+        if title != pr.title {
+            titles.insert(pr.number, title);
+        }
+    }
+    titles
+}
+
+fn strip_title_prefix(title: &str) -> &str {
+    let Some((position, rest)) = title
+        .strip_prefix('[')
+        .and_then(|title| title.split_once("] "))
+    else {
+        return title;
+    };
+    match position.split_once('/') {
+        Some((i, n)) if i.parse::<usize>().is_ok() && n.parse::<usize>().is_ok() => rest,
+        _ => title,
+    }
+}
+
+/// Computes the reviewers each PR of stack is missing: those requested on
+/// the bottom PR or, when it has none, the defaults.
+fn stack_reviewers(
+    forge: &dyn Forge,
+    stack: &Stack,
+    defaults: &[String],
+    jobs: usize,
+) -> Result<HashMap<i32, Vec<String>>, Box<dyn std::error::Error>> {
+    let requested: HashMap<i32, Vec<String>> = run_concurrently(stack, jobs, |pr| {
+        let reviewers = forge.list_requested_reviewers(pr.number);
+        reviewers
+            .map(|reviewers| (pr.number, reviewers))
+            .map_err(|e| format!("#{}: cannot list requested reviewers: {}", pr.number, e))
+    })
+    .into_iter()
+    .collect::<Result<_, String>>()?;
+    let wanted = match &requested[&stack[0].number] {
+        bottom if bottom.is_empty() => defaults,
+        bottom => bottom.as_slice(),
+    };
+    let mut reviewers = HashMap::new();
+    for pr in stack {
+        let missing: Vec<String> = wanted
+            .iter()
+            .filter(|r| !requested[&pr.number].contains(r))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            reviewers.insert(pr.number, missing);
+        }
+    }
+    Ok(reviewers)
+}
+
+fn print_body_diff(old: &str, new: &str) {
+    let color = table::use_color();
+    let text_diff = similar::TextDiff::from_lines(old, new);
+    let unified = text_diff
+        .unified_diff()
+        .header("current", "planned")
+        .to_string();
+    for line in unified.lines() {
+        let code = match line.chars().next() {
+            Some('+') if !line.starts_with("+++") => "32",
+            Some('-') if !line.starts_with("---") => "31",
+            Some('@') => "36",
+            _ => "",
+        };
+        if color && !code.is_empty() {
+            println!("\t\x1b[{}m{}\x1b[0m", code, line);
+        } else {
+            println!("\t{}", line);
+        }
+    }
+}
+
+fn render_navigation(config: &Config, body: String, nav_block: String) -> String {
+    if config.mode.unwrap_or_default() == AnnotateMode::Comment {
+        return nav_block;
+    }
+    let foreign = config.foreign_stacks.unwrap_or_default();
+    match render_body_with_foreign_stack(&body, &nav_block, foreign) {
+        Some(new_body) => new_body,
+        None => render_body(
+            body,
+            nav_block,
+            config.placement.unwrap_or_default(),
+            config.placement_heading.as_deref(),
+        ),
+    }
+}
+
+/// Stands in the description for a navigation block moved to a comment, so
+/// that the comment is only looked for in the PRs that have one.
+fn moved_nav_block() -> String {
+    format!("{}\n{}\n", STACK_HEADER, STACK_FOOTER)
+}
+
+fn find_nav_comment(forge: &dyn Forge, number: i32) -> Result<Option<Comment>, String> {
+    let comments = forge
+        .list_pr_comments(number)
+        .map_err(|e| format!("#{}: cannot list comments: {}", number, e))?;
+    Ok(comments
+        .into_iter()
+        .find(|comment| comment.body.contains(STACK_HEADER)))
+}
+
+/// Writes nav_block to the description or navigation comment of pr, along
+/// with its new title, if any.
+fn update_pr_description(
+    forge: &dyn Forge,
+    pr: PullRequest,
+    nav_block: String,
+    title: Option<String>,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if config.mode.unwrap_or_default() == AnnotateMode::Comment {
+        if let Some(title) = title {
+            forge.update_pr_title(pr.number, title, None)?;
+        }
+        return update_nav_comment(forge, pr.number, nav_block);
+    }
+    // The description is only written if it still reads as it did, otherwise
+    // the navigation is rendered again on the fresh text; an edit racing the
+    // write itself can still be lost (see update_pr_body_if).
+    let mut gh_pr_body = forge.get_pr_body(pr.number)?;
+    for attempt in 1..=CONFLICT_RETRIES {
+        let fits = |body: &str| {
+            forge
+                .max_body_len()
+                .is_none_or(|max| body.chars().count() <= max)
+        };
+        let mut new_body = render_navigation(config, gh_pr_body.to_string(), nav_block.to_string());
+        // A description too long for the forge keeps its text, and the
+        // navigation moves to a comment, leaving an empty block behind when
+        // there is room for it.
+        let too_long = !fits(&new_body);
+        if too_long {
+            new_body = render_navigation(config, gh_pr_body.to_string(), moved_nav_block());
+            if !fits(&new_body) {
+                new_body = render_navigation(config, gh_pr_body.to_string(), String::new());
+            }
+        }
+        let done = if new_body == gh_pr_body {
+            if let Some(title) = title.clone() {
+                forge.update_pr_title(pr.number, title, None)?;
+            }
+            true
+        } else {
+            forge.update_pr_body_if(pr.number, &gh_pr_body, title.clone(), new_body)?
+        };
+        // A comment left from when the description was too long goes away
+        // once the navigation is back in the description.
+        if done && !too_long && gh_pr_body.contains(&moved_nav_block()) {
+            return update_nav_comment(forge, pr.number, String::new());
+        }
+        if done && too_long {
+            warn!(
+                "#{}: description with the navigation block exceeds the {} characters \
+                 the forge accepts, writing the navigation in a comment instead",
+                pr.number,
+                forge.max_body_len().unwrap_or_default()
+            );
+            return update_nav_comment(forge, pr.number, nav_block);
+        }
+        if done {
+            return Ok(());
+        }
+        warn!(
+            "#{}: description changed while updating it (attempt {} of {})",
+            pr.number, attempt, CONFLICT_RETRIES
+        );
+        gh_pr_body = forge.get_pr_body(pr.number)?;
+    }
+    Err(format!(
+        "description kept changing after {} attempts",
+        CONFLICT_RETRIES
+    )
+    .into())
+}
+
+/// Writes nav_block in the navigation comment of a PR, creating it when
+/// missing, or deletes the comment when nav_block is empty.
+fn update_nav_comment(
+    forge: &dyn Forge,
+    number: i32,
+    nav_block: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match find_nav_comment(forge, number)? {
+        Some(comment) if nav_block.is_empty() => forge.delete_pr_comment(number, comment.id)?,
+        Some(comment) if comment.body == nav_block => {}
+        Some(comment) => forge.update_pr_comment(number, comment.id, nav_block)?,
+        None if nav_block.is_empty() => {}
+        None => forge.create_pr_comment(number, nav_block)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::sync::Mutex;
+
+    use jjstack_core::command::{with_runner, MockRunner};
+    use jjstack_core::forge::MergeMethod;
+
+    use super::*;
+
+    /// MockForge keeps pull request bodies and comments in memory and records
+    /// the writes made to it.
+    #[derive(Default)]
+    struct MockForge {
+        prs: Vec<PullRequest>,
+        closed: Vec<PullRequest>,
+        deleted_branches: Vec<String>,
+        bodies: Mutex<HashMap<i32, String>>,
+        comments: Mutex<Vec<Comment>>,
+        reviewers: HashMap<i32, Vec<String>>,
+        max_body_len: Option<usize>,
+        writes: Mutex<Vec<String>>,
+    }
+
+    impl MockForge {
+        fn write(&self, call: String) {
+            self.writes.lock().unwrap().push(call);
+        }
+
+        fn writes(&self) -> Vec<String> {
+            self.writes.lock().unwrap().clone()
+        }
+    }
+
+    impl Forge for MockForge {
+        fn max_body_len(&self) -> Option<usize> {
+            self.max_body_len
+        }
+
+        fn list_open_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+            Ok(self.prs.clone())
+        }
+
+        fn get_pr_body(&self, number: i32) -> Result<String, JjstackError> {
+            Ok(self
+                .bodies
+                .lock()
+                .unwrap()
+                .get(&number)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        fn update_pr_body(&self, number: i32, body: String) -> Result<(), JjstackError> {
+            self.write(format!("update_pr_body #{}", number));
+            self.bodies.lock().unwrap().insert(number, body);
+            Ok(())
+        }
+
+        fn update_pr_title(
+            &self,
+            number: i32,
+            title: String,
+            body: Option<String>,
+        ) -> Result<(), JjstackError> {
+            self.write(format!("update_pr_title #{} {}", number, title));
+            if let Some(body) = body {
+                self.bodies.lock().unwrap().insert(number, body);
+            }
+            Ok(())
+        }
+
+        fn find_closed_pr(&self, head: &str) -> Result<Option<PullRequest>, JjstackError> {
+            Ok(self.closed.iter().find(|pr| pr.head == head).cloned())
+        }
+
+        fn list_closed_prs(&self) -> Result<Vec<PullRequest>, JjstackError> {
+            Ok(self.closed.clone())
+        }
+
+        fn branch_exists(&self, branch: &str) -> Result<bool, JjstackError> {
+            Ok(!self.deleted_branches.iter().any(|b| b == branch))
+        }
+
+        fn update_pr_base(&self, number: i32, base: String) -> Result<(), JjstackError> {
+            self.write(format!("update_pr_base #{} {}", number, base));
+            Ok(())
+        }
+
+        fn default_branch(&self) -> Result<String, JjstackError> {
+            Ok("main".to_string())
+        }
+
+        fn list_pr_comments(&self, _number: i32) -> Result<Vec<Comment>, JjstackError> {
+            Ok(self.comments.lock().unwrap().clone())
+        }
+
+        fn create_pr_comment(&self, number: i32, body: String) -> Result<(), JjstackError> {
+            self.write(format!("create_pr_comment #{}", number));
+            let mut comments = self.comments.lock().unwrap();
+            let id = comments.len() as u64 + 1;
+            comments.push(Comment { id, body });
+            Ok(())
+        }
+
+        fn update_pr_comment(
+            &self,
+            number: i32,
+            id: u64,
+            body: String,
+        ) -> Result<(), JjstackError> {
+            self.write(format!("update_pr_comment #{} {}", number, id));
+            let mut comments = self.comments.lock().unwrap();
+            if let Some(comment) = comments.iter_mut().find(|c| c.id == id) {
+                comment.body = body;
+            }
+            Ok(())
+        }
+
+        fn delete_pr_comment(&self, number: i32, id: u64) -> Result<(), JjstackError> {
+            self.write(format!("delete_pr_comment #{} {}", number, id));
+            self.comments.lock().unwrap().retain(|c| c.id != id);
+            Ok(())
+        }
+
+        fn set_pr_labels(&self, number: i32, labels: Vec<String>) -> Result<(), JjstackError> {
+            self.write(format!("set_pr_labels #{} {}", number, labels.join(",")));
+            Ok(())
+        }
+
+        fn list_requested_reviewers(&self, number: i32) -> Result<Vec<String>, JjstackError> {
+            Ok(self.reviewers.get(&number).cloned().unwrap_or_default())
+        }
+
+        fn request_reviewers(
+            &self,
+            number: i32,
+            reviewers: Vec<String>,
+        ) -> Result<(), JjstackError> {
+            self.write(format!(
+                "request_reviewers #{} {}",
+                number,
+                reviewers.join(",")
+            ));
+            Ok(())
+        }
+
+        fn create_pr(
+            &self,
+            head: &str,
+            base: &str,
+            _title: String,
+            _body: String,
+            _draft: bool,
+        ) -> Result<(), JjstackError> {
+            self.write(format!("create_pr {} onto {}", head, base));
+            Ok(())
+        }
+
+        fn merge_pr(&self, number: i32, _method: MergeMethod) -> Result<(), JjstackError> {
+            self.write(format!("merge_pr #{}", number));
+            Ok(())
+        }
+    }
+
+    fn pr(number: i32, head: &str, base: &str) -> PullRequest {
+        PullRequest {
+            number,
+            title: format!("change {}", number),
+            url: format!("https://example.com/pull/{}", number),
+            head: head.to_string(),
+            base: base.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn nav_block(text: &str) -> String {
+        format!("{}\n{}\n{}\n", STACK_HEADER, text, STACK_FOOTER)
+    }
+
+    #[test]
+    fn get_open_prs_keeps_local_bookmarks_of_the_repository() {
+        let mut foreign = pr(3, "c", "b");
+        foreign.head_owner = Some("someone".to_string());
+        let mut own = pr(2, "b", "a");
+        own.head_owner = Some("org".to_string());
+        let mut draft = pr(4, "d", "b");
+        draft.draft = true;
+        let forge = MockForge {
+            prs: vec![pr(1, "a", "main"), own, foreign, draft, pr(5, "e", "main")],
+            ..Default::default()
+        };
+        let bookmarks: HashSet<String> = ["a", "b", "c", "d"].map(String::from).into();
+        // e may be a renamed bookmark, which jj does not know of.
+        let runner = Rc::new(MockRunner::default().ok("jj bookmark list", ""));
+
+        let prs = with_runner(runner.clone(), || {
+            get_open_prs(&forge, bookmarks.clone(), "org/repo", &Config::default())
+        })
+        .unwrap();
+        let numbers: Vec<i32> = prs.iter().map(|pr| pr.number).collect();
+        assert_eq!(numbers, vec![1, 2, 4]);
+
+        let config = Config {
+            skip_drafts: Some(true),
+            ..Default::default()
+        };
+        let prs = with_runner(runner, || {
+            get_open_prs(&forge, bookmarks, "org/repo", &config)
+        })
+        .unwrap();
+        let numbers: Vec<i32> = prs.iter().map(|pr| pr.number).collect();
+        assert_eq!(numbers, vec![1, 2]);
+    }
+
+    #[test]
+    fn get_stacked_prs_keeps_prs_based_on_each_other() {
+        let forge = MockForge {
+            prs: vec![
+                pr(1, "a", "main"),
+                pr(2, "b", "a"),
+                pr(3, "c", "main"),
+                pr(4, "d", "b"),
+                pr(5, "main", "release"),
+            ],
+            ..Default::default()
+        };
+        let config = Config {
+            trunk: Some("main".to_string()),
+            ..Default::default()
+        };
+
+        let prs = get_stacked_prs(&forge, "org/repo", &config).unwrap();
+        let mut numbers: Vec<i32> = prs.iter().map(|pr| pr.number).collect();
+        numbers.sort();
+        assert_eq!(numbers, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn fix_dangling_bases_retargets_onto_live_ancestors() {
+        let mut prs = vec![pr(1, "a", "gone"), pr(2, "b", "a"), pr(3, "c", "lost")];
+        let forge = MockForge {
+            closed: vec![pr(9, "gone", "kept")],
+            deleted_branches: vec!["gone".to_string(), "lost".to_string()],
+            ..Default::default()
+        };
+        let config = Config {
+            trunk: Some("main".to_string()),
+            ..Default::default()
+        };
+        let dangling = find_dangling_bases(&forge, &prs, &config).unwrap();
+        assert_eq!(
+            dangling,
+            vec![(1, "gone".to_string()), (3, "lost".to_string())]
+        );
+
+        let previous = fix_dangling_bases(&forge, &mut prs, &dangling, &config, true).unwrap();
+        assert_eq!(previous.len(), 2);
+        assert_eq!(prs[0].base, "kept");
+        assert_eq!(prs[2].base, "main");
+        assert_eq!(
+            forge.writes(),
+            vec!["update_pr_base #1 kept", "update_pr_base #3 main"]
+        );
+    }
+
+    #[test]
+    fn stack_titles_renumbers_prefixes() {
+        let mut stack = vec![pr(1, "a", "main"), pr(2, "b", "a"), pr(3, "c", "b")];
+        stack[0].title = "[1/3] one".to_string();
+        stack[1].title = "[3/3] two".to_string();
+        stack[2].title = "[WIP] three".to_string();
+        let titles = stack_titles(&stack);
+        assert_eq!(
+            titles,
+            HashMap::from([
+                (2, "[2/3] two".to_string()),
+                (3, "[3/3] [WIP] three".to_string()),
+            ])
+        );
+
+        let titles = stack_titles(&stack[..1].to_vec());
+        assert_eq!(titles, HashMap::from([(1, "one".to_string())]));
+    }
+
+    #[test]
+    fn stack_reviewers_copies_the_bottom_reviewers() {
+        let stack = vec![pr(1, "a", "main"), pr(2, "b", "a"), pr(3, "c", "b")];
+        let defaults = vec!["default".to_string()];
+        let forge = MockForge {
+            reviewers: HashMap::from([
+                (1, vec!["alice".to_string(), "org/team".to_string()]),
+                (2, vec!["alice".to_string()]),
+            ]),
+            ..Default::default()
+        };
+        let reviewers = stack_reviewers(&forge, &stack, &defaults, 2).unwrap();
+        assert_eq!(
+            reviewers,
+            HashMap::from([
+                (2, vec!["org/team".to_string()]),
+                (3, vec!["alice".to_string(), "org/team".to_string()]),
+            ])
+        );
+
+        let forge = MockForge::default();
+        let reviewers = stack_reviewers(&forge, &stack, &defaults, 2).unwrap();
+        assert_eq!(reviewers.len(), 3);
+        assert_eq!(reviewers[&1], defaults);
+    }
+
+    #[test]
+    fn find_related_prs_matches_branches_without_their_prefixes() {
+        let stack = vec![pr(1, "uc/a", "main"), pr(2, "uc/b", "uc/a")];
+        let siblings = vec![SiblingPrs {
+            repo: "org/api".to_string(),
+            bookmark_prefix: "api/".to_string(),
+            prs: vec![pr(7, "api/b", "main"), pr(8, "uc/a", "main")],
+        }];
+        let config = Config {
+            bookmark_prefix: Some("uc/".to_string()),
+            ..Default::default()
+        };
+        let related = find_related_prs(&stack, &siblings, &config);
+        assert_eq!(related.len(), 1);
+        let numbers: Vec<(&str, i32)> = related[&2]
+            .iter()
+            .map(|(repo, pr)| (repo.as_str(), pr.number))
+            .collect();
+        assert_eq!(numbers, [("org/api", 7)]);
+    }
+
+    #[test]
+    fn interrupted_comment_mode_restores_comments_not_descriptions() {
+        let forge = MockForge::default();
+        forge
+            .bodies
+            .lock()
+            .unwrap()
+            .insert(1, "Description".to_string());
+        let config = Config {
+            mode: Some(AnnotateMode::Comment),
+            ..Default::default()
+        };
+        update_pr_description(&forge, pr(1, "a", "main"), nav_block("one"), None, &config).unwrap();
+        let journaled = vec![
+            journal::Entry {
+                repo: "org/repo".to_string(),
+                number: 1,
+                title: "change 1".to_string(),
+                body: "Description".to_string(),
+                comment: Some(String::new()),
+            },
+            // Not updated before the interruption, so left alone.
+            journal::Entry {
+                repo: "org/repo".to_string(),
+                number: 2,
+                title: "change 2".to_string(),
+                body: "Other".to_string(),
+                comment: Some(String::new()),
+            },
+        ];
+        let results = vec![(1, Ok(())), (2, Err("interrupted".to_string()))];
+
+        restore_interrupted(&forge, journaled, &results).unwrap();
+        assert_eq!(
+            forge.writes(),
+            vec![
+                "create_pr_comment #1",
+                "update_pr_title #1 change 1",
+                "delete_pr_comment #1 1"
+            ]
+        );
+        assert_eq!(forge.get_pr_body(1).unwrap(), "Description");
+        assert!(forge.comments.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_updates_writes_every_change_kind() {
+        let forge = MockForge::default();
+        forge
+            .bodies
+            .lock()
+            .unwrap()
+            .insert(1, "Description".to_string());
+        let prs = vec![
+            (pr(1, "a", "main"), Some(nav_block("one"))),
+            (pr(2, "b", "a"), None),
+        ];
+        let titles = HashMap::from([(1, "[1/2] change 1".to_string())]);
+        let labels = HashMap::from([(2, vec!["stack:a".to_string()])]);
+        let updates = Updates {
+            prs: &prs,
+            batches: &[0..1, 1..2],
+            titles: &titles,
+            labels: &labels,
+            reviewers: &HashMap::new(),
+        };
+
+        let (results, journal) = write_updates(
+            &forge,
+            &updates,
+            Vec::new(),
+            &Progress::new(&[]),
+            &Config::default(),
+            1,
+            false,
+        )
+        .unwrap();
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert!(journal.is_none());
+        assert_eq!(
+            forge.writes(),
+            vec![
+                "update_pr_title #1 [1/2] change 1",
+                "set_pr_labels #2 stack:a"
+            ]
+        );
+        assert_eq!(
+            forge.get_pr_body(1).unwrap(),
+            format!("Description\n\n{}\n", nav_block("one"))
+        );
+    }
+
+    #[test]
+    fn update_pr_description_writes_only_changes() {
+        let forge = MockForge::default();
+        forge
+            .bodies
+            .lock()
+            .unwrap()
+            .insert(1, "Description".to_string());
+        let config = Config::default();
+
+        update_pr_description(&forge, pr(1, "a", "main"), nav_block("one"), None, &config).unwrap();
+        update_pr_description(&forge, pr(1, "a", "main"), nav_block("one"), None, &config).unwrap();
+        assert_eq!(forge.writes(), vec!["update_pr_body #1"]);
+        assert_eq!(
+            forge.get_pr_body(1).unwrap(),
+            format!("Description\n\n{}\n", nav_block("one"))
+        );
+
+        update_pr_description(&forge, pr(1, "a", "main"), String::new(), None, &config).unwrap();
+        assert_eq!(forge.get_pr_body(1).unwrap(), "Description");
+    }
+
+    #[test]
+    fn update_pr_description_moves_long_navigation_to_a_comment() {
+        let long = nav_block("one two three four five");
+        let mut forge = MockForge {
+            max_body_len: Some(80),
+            ..Default::default()
+        };
+        forge
+            .bodies
+            .lock()
+            .unwrap()
+            .insert(1, "Description".to_string());
+        let config = Config::default();
+
+        update_pr_description(&forge, pr(1, "a", "main"), long.clone(), None, &config).unwrap();
+        update_pr_description(&forge, pr(1, "a", "main"), long.clone(), None, &config).unwrap();
+        assert_eq!(
+            forge.writes(),
+            vec!["update_pr_body #1", "create_pr_comment #1"]
+        );
+        assert_eq!(
+            forge.get_pr_body(1).unwrap(),
+            format!("Description\n\n{}\n", moved_nav_block())
+        );
+
+        // Only a PR whose navigation was moved looks for the comment.
+        forge.bodies.lock().unwrap().insert(2, "Text".to_string());
+        update_pr_description(&forge, pr(2, "b", "a"), nav_block("two"), None, &config).unwrap();
+        assert_eq!(forge.comments.lock().unwrap().len(), 1);
+
+        // Once the navigation fits again, it leaves the comment.
+        forge.max_body_len = Some(1000);
+        update_pr_description(&forge, pr(1, "a", "main"), long.clone(), None, &config).unwrap();
+        assert_eq!(
+            forge.writes(),
+            vec![
+                "update_pr_body #1",
+                "create_pr_comment #1",
+                "update_pr_body #2",
+                "update_pr_body #1",
+                "delete_pr_comment #1 1"
+            ]
+        );
+        assert_eq!(
+            forge.get_pr_body(1).unwrap(),
+            format!("Description\n\n{}\n", long)
+        );
+        assert!(forge.comments.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn update_pr_description_in_comment_mode() {
+        let forge = MockForge::default();
+        forge.comments.lock().unwrap().push(Comment {
+            id: 1,
+            body: "LGTM".to_string(),
+        });
+        let config = Config {
+            mode: Some(AnnotateMode::Comment),
+            ..Default::default()
+        };
+        let annotate = |text: &str| {
+            let nav = if text.is_empty() {
+                String::new()
+            } else {
+                nav_block(text)
+            };
+            update_pr_description(&forge, pr(1, "a", "main"), nav, None, &config).unwrap();
+        };
+
+        annotate("one");
+        annotate("one");
+        annotate("two");
+        annotate("");
+        assert_eq!(
+            forge.writes(),
+            vec![
+                "create_pr_comment #1",
+                "update_pr_comment #1 2",
+                "delete_pr_comment #1 2",
+            ]
+        );
+        assert_eq!(forge.comments.lock().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,248 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use jjstack_core::config::{AnnotateMode, Config};
+use jjstack_core::forge::Forge;
+use jjstack_core::journal;
+use jjstack_core::stack::PullRequest;
+
+use crate::cli::ApplyArgs;
+use crate::progress::Progress;
+use crate::{
+    find_nav_comment, run_concurrently, write_updates, Action, PartialFailure, Report, Updates,
+};
+
+/// Plan records the changes computed by `jjstack plan`, so that `jjstack
+/// apply` can make exactly those once it has checked that nobody edited the
+/// PRs in the meantime.
+#[derive(Deserialize, Serialize)]
+struct Plan {
+    repo: String,
+    mode: AnnotateMode,
+    changes: Vec<Change>,
+}
+
+/// Change is what changes in one PR: the new text of its description, or of
+/// its navigation comment in comment mode, its title, labels and reviewers.
+#[derive(Deserialize, Serialize)]
+struct Change {
+    number: i32,
+    title: String,
+    /// Title before the change, when the title changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    current_title: Option<String>,
+    action: Action,
+    /// SHA-256 of the text the plan was computed from.
+    current_sha256: String,
+    body: String,
+    /// Navigation block body is rendered with, empty to remove it, or none
+    /// when the text is left alone.
+    nav_block: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reviewers: Option<Vec<String>>,
+}
+
+/// Writes the changes of a dry run report to path.
+pub fn write(
+    path: &Path,
+    report: &Report,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let changes = report
+        .stacks
+        .iter()
+        .flat_map(|s| &s.prs)
+        .chain(&report.pruned)
+        .filter(|pr| {
+            pr.error.is_none()
+                && (pr.action != Action::Skip
+                    || pr.current_title != pr.title
+                    || pr.labels.is_some()
+                    || pr.reviewers.is_some())
+        })
+        .map(|pr| Change {
+            number: pr.number,
+            title: pr.title.to_string(),
+            current_title: Some(pr.current_title.to_string()).filter(|title| *title != pr.title),
+            action: pr.action,
+            current_sha256: sha256(&pr.current_body),
+            body: pr.body.to_string(),
+            nav_block: pr.nav_block.clone(),
+            labels: pr.labels.clone(),
+            reviewers: pr.reviewers.clone(),
+        })
+        .collect();
+    let plan = Plan {
+        repo: report.repo.to_string(),
+        mode: config.mode.unwrap_or_default(),
+        changes,
+    };
+    fs::write(path, serde_json::to_string_pretty(&plan)?)
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    println!(
+        "plan with {} changes written to {}",
+        plan.changes.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Applies the plan read from the file given in args, refusing to touch any
+/// PR when one of them changed since the plan was made. Like annotate, the
+/// replaced titles and texts are journaled.
+pub fn apply(
+    repo: String,
+    forge: &dyn Forge,
+    args: ApplyArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(&args.plan)
+        .map_err(|e| format!("cannot read {}: {}", args.plan.display(), e))?;
+    let plan: Plan = serde_json::from_str(&text)
+        .map_err(|e| format!("cannot parse {}: {}", args.plan.display(), e))?;
+    if plan.repo != repo {
+        return Err(format!("plan is for {}, not {}", plan.repo, repo).into());
+    }
+    if plan.changes.is_empty() {
+        println!("nothing to apply.");
+        return Ok(());
+    }
+
+    // The description is also read in comment mode, to journal it.
+    let currents: Vec<Result<(String, String), String>> =
+        run_concurrently(&plan.changes, args.jobs, |c| {
+            let current = forge.get_pr_body(c.number).map_err(|e| e.to_string());
+            let current = match plan.mode {
+                AnnotateMode::Body => current.map(|body| (body.clone(), body)),
+                AnnotateMode::Comment => current.and_then(|body| {
+                    let comment = find_nav_comment(forge, c.number)?;
+                    Ok((body, comment.map(|c| c.body).unwrap_or_default()))
+                }),
+            };
+            current.map_err(|e| format!("#{}: cannot read PR: {}", c.number, e))
+        });
+    // Titles are only compared where the plan changes them, so the open PRs
+    // are only listed then.
+    let live_titles: HashMap<i32, String> =
+        if plan.changes.iter().any(|c| c.current_title.is_some()) {
+            forge
+                .list_open_prs()
+                .map_err(|e| format!("cannot list PRs: {}", e))?
+                .into_iter()
+                .map(|pr| (pr.number, pr.title))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+    let mut prs = Vec::new();
+    let mut journaled = Vec::new();
+    let mut stale = Vec::new();
+    for (change, current) in plan.changes.iter().zip(currents) {
+        let (description, current) = current?;
+        let retitled = change
+            .current_title
+            .as_ref()
+            .is_some_and(|title| live_titles.get(&change.number) != Some(title));
+        if sha256(&current) != change.current_sha256 || retitled {
+            stale.push(format!("#{}", change.number));
+        }
+        let title = change.current_title.as_ref().unwrap_or(&change.title);
+        if change.nav_block.is_some() || change.current_title.is_some() {
+            journaled.push(journal::Entry {
+                repo: repo.to_string(),
+                number: change.number,
+                title: title.to_string(),
+                body: description,
+                comment: (plan.mode == AnnotateMode::Comment).then(|| current.clone()),
+            });
+        }
+        let pr = PullRequest {
+            number: change.number,
+            title: title.to_string(),
+            body: current,
+            ..Default::default()
+        };
+        prs.push((pr, change.nav_block.clone()));
+    }
+    if !stale.is_empty() {
+        return Err(format!(
+            "{} changed since the plan was made; run 'jjstack plan' again",
+            stale.join(", ")
+        )
+        .into());
+    }
+
+    let titles: HashMap<i32, String> = plan
+        .changes
+        .iter()
+        .filter(|c| c.current_title.is_some())
+        .map(|c| (c.number, c.title.to_string()))
+        .collect();
+    let labels: HashMap<i32, Vec<String>> = plan
+        .changes
+        .iter()
+        .filter_map(|c| Some((c.number, c.labels.clone()?)))
+        .collect();
+    let reviewers: HashMap<i32, Vec<String>> = plan
+        .changes
+        .iter()
+        .filter_map(|c| Some((c.number, c.reviewers.clone()?)))
+        .collect();
+    // The plan does not keep the stacks, so every PR is a batch of its own.
+    let batches: Vec<_> = (0..prs.len()).map(|i| i..i + 1).collect();
+    let updates = Updates {
+        prs: &prs,
+        batches: &batches,
+        titles: &titles,
+        labels: &labels,
+        reviewers: &reviewers,
+    };
+    let config = Config {
+        mode: Some(plan.mode),
+        ..config.clone()
+    };
+    let numbers = plan.changes.iter().map(|c| c.number).collect();
+    let progress = Progress::new(&[(plan.repo.to_string(), numbers)]);
+    let (results, journal) = write_updates(
+        forge, &updates, journaled, &progress, &config, args.jobs, false,
+    )?;
+    let mut failures = 0;
+    for (change, (_, result)) in plan.changes.iter().zip(results) {
+        match result {
+            Ok(()) => println!("PR #{} {:?}: updated", change.number, change.title),
+            Err(e) => {
+                eprintln!("#{}: cannot update PR: {}", change.number, e);
+                failures += 1;
+            }
+        }
+    }
+    if let Some(journal) = &journal {
+        println!("undo with: jjstack annotate --rollback {}", journal.run_id);
+    }
+    if failures > 0 {
+        return Err(PartialFailure {
+            failed: failures,
+            total: plan.changes.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn sha256(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
@@ -0,0 +1,70 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{log_enabled, Level};
+
+/// Progress of an --apply run over many PRs, drawn on stderr as one bar per
+/// stack.
+pub struct Progress {
+    bars: HashMap<i32, ProgressBar>,
+    _multi: MultiProgress,
+}
+
+impl Progress {
+    /// Sets up a bar for each named group of PR numbers. Nothing is drawn
+    /// when stdout is not a terminal, or when only errors are logged
+    /// (--quiet).
+    pub fn new(groups: &[(String, Vec<i32>)]) -> Self {
+        let multi = if io::stdout().is_terminal() && log_enabled!(Level::Warn) {
+            MultiProgress::new()
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        };
+        let style = ProgressStyle::with_template(
+            "{prefix:.bold} [{bar:20}] {pos}/{len} {wide_msg} (eta {eta})",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> ");
+        let mut bars = HashMap::new();
+        for (name, numbers) in groups.iter().filter(|(_, numbers)| !numbers.is_empty()) {
+            let bar = multi.add(ProgressBar::new(numbers.len() as u64));
+            bar.set_style(style.clone());
+            bar.set_prefix(name.to_string());
+            for number in numbers {
+                bars.insert(*number, bar.clone());
+            }
+        }
+        Progress {
+            bars,
+            _multi: multi,
+        }
+    }
+
+    /// Shows PR number as the one being worked on in its stack.
+    pub fn start(&self, number: i32) {
+        if let Some(bar) = self.bars.get(&number) {
+            bar.set_message(format!("#{}", number));
+        }
+    }
+
+    /// Counts PR number as done, finishing the bar of its stack after its
+    /// last PR.
+    pub fn finish(&self, number: i32) {
+        let Some(bar) = self.bars.get(&number) else {
+            return;
+        };
+        bar.inc(1);
+        if Some(bar.position()) == bar.length() {
+            bar.finish_with_message("done");
+        }
+    }
+}
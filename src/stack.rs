@@ -0,0 +1,1140 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{HashMap, HashSet};
+
+use log::warn;
+use serde_json::json;
+
+use crate::config::{ForeignStacks, NavFormat, NavOrder, NavStyle, Placement};
+use crate::error::JjstackError;
+
+pub const STACK_HEADER: &str = "<!-- STACK NAVIGATION -->";
+pub const STACK_FOOTER: &str = "<!-- END STACK NAVIGATION -->";
+
+/// PLACEHOLDER marks where the navigation block goes in a PR description
+/// with the placeholder placement.
+pub const PLACEHOLDER: &str = "<!-- jjstack -->";
+
+/// PullRequest is the forge-agnostic view of a pull (or merge) request.
+#[derive(Clone, Debug, Default)]
+pub struct PullRequest {
+    pub number: i32,
+    pub title: String,
+    pub url: String,
+    pub head: String,
+    /// Owner of the repository holding the head branch, which differs from
+    /// the base repository owner for PRs opened from a fork.
+    pub head_owner: Option<String>,
+    /// Commit the head branch points to on the forge.
+    pub head_sha: Option<String>,
+    pub base: String,
+    pub body: String,
+    pub mergeable: Option<bool>,
+    pub draft: bool,
+    pub labels: Vec<String>,
+    pub checks: Option<CheckState>,
+    pub review: Option<ReviewState>,
+    /// When the PR was opened, as an RFC 3339 timestamp.
+    pub created_at: Option<String>,
+    pub diff_stat: Option<DiffStat>,
+}
+
+/// DiffStat is the size of the changes of a PR.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiffStat {
+    pub additions: u64,
+    pub deletions: u64,
+    /// Number of commits, when the forge reports it.
+    pub commits: Option<u64>,
+}
+
+impl DiffStat {
+    /// Summary shown after the PR in the navigation block, such as
+    /// "(+120 −35, 3 commits)".
+    fn summary(self, ascii: bool) -> String {
+        let minus = if ascii { "-" } else { "−" };
+        let commits = match self.commits {
+            Some(1) => ", 1 commit".to_string(),
+            Some(n) => format!(", {} commits", n),
+            None => String::new(),
+        };
+        format!(
+            "(+{} {}{}{})",
+            self.additions, minus, self.deletions, commits
+        )
+    }
+}
+
+/// CheckState summarizes the CI checks of the head commit of a PR.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckState {
+    Success,
+    Failure,
+    Pending,
+}
+
+impl CheckState {
+    /// Icon shown next to the PR in the navigation block.
+    pub fn icon(self) -> &'static str {
+        match self {
+            CheckState::Success => "✅",
+            CheckState::Failure => "❌",
+            CheckState::Pending => "🟡",
+        }
+    }
+
+    /// Plain-text stand-in for icon.
+    pub fn ascii_icon(self) -> &'static str {
+        match self {
+            CheckState::Success => "[pass]",
+            CheckState::Failure => "[fail]",
+            CheckState::Pending => "[pending]",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            CheckState::Success => "success",
+            CheckState::Failure => "failure",
+            CheckState::Pending => "pending",
+        }
+    }
+}
+
+/// ReviewState is the review decision of a PR.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReviewState {
+    Approved,
+    ChangesRequested,
+    ReviewRequired,
+}
+
+/// Stack is a group of pull requests chained by their base and head
+/// branches, ordered from the bottom of the stack upwards. Branching stacks
+/// are flattened depth-first, so every PR comes after the one it is based on.
+pub type Stack = Vec<PullRequest>;
+
+/// NavBlock is the Markdown navigation section inserted in PR descriptions,
+/// delimited by STACK_HEADER and STACK_FOOTER.
+pub type NavBlock = String;
+
+/// NavOptions controls how the built-in navigation block is rendered.
+#[derive(Clone, Debug, Default)]
+pub struct NavOptions {
+    pub style: NavStyle,
+    pub format: NavFormat,
+    /// The top-down format is always listed from the top.
+    pub order: NavOrder,
+    /// Show the CI status of each PR next to its entry.
+    pub ci_status: bool,
+    /// Show the lines added and removed, and the commits, of each PR after
+    /// its entry.
+    pub diff_stats: bool,
+    /// Follow the list with "Depends on #N" and "Required by #M" lines, so
+    /// the forge cross-references the neighbors of each PR.
+    pub neighbors: bool,
+    /// Only use ASCII, with no emoji or arrows, for readers such as the
+    /// plain-text email notifications of PR edits.
+    pub ascii: bool,
+    /// PRs of the stack left out under and over the chain, which the list
+    /// mentions at its ends.
+    pub more_below: usize,
+    pub more_above: usize,
+    /// Name given to the stack, which heads the block along with its size.
+    pub name: Option<String>,
+}
+
+/// Keeps only the PRs of the stacks that contain one of the bookmarks.
+pub fn select_stacks(prs: Vec<PullRequest>, bookmarks: &[String]) -> Vec<PullRequest> {
+    build_pr_stacks(prs)
+        .into_iter()
+        .filter(|stack| stack.iter().any(|pr| bookmarks.contains(&pr.head)))
+        .flatten()
+        .collect()
+}
+
+/// Returns the PRs connected to bookmark, from the root of its stack down to
+/// every leaf, following both the base of each PR and its parent in
+/// jj_bases, which maps a head to its parent bookmark in the jj graph. The
+/// bookmark itself needs no PR of its own.
+pub fn select_connected(
+    prs: Vec<PullRequest>,
+    jj_bases: &HashMap<String, String>,
+    bookmark: &str,
+) -> Vec<PullRequest> {
+    let mut neighbors: HashMap<&str, Vec<&str>> = HashMap::new();
+    let edges = prs
+        .iter()
+        .map(|pr| (pr.head.as_str(), pr.base.as_str()))
+        .chain(jj_bases.iter().map(|(h, b)| (h.as_str(), b.as_str())));
+    for (head, base) in edges {
+        neighbors.entry(head).or_default().push(base);
+        neighbors.entry(base).or_default().push(head);
+    }
+    // Only PR heads join the stack: walking through the trunk, the base
+    // every stack shares, would select all of them.
+    let heads: HashSet<&str> = prs.iter().map(|pr| pr.head.as_str()).collect();
+    let mut connected = HashSet::from([bookmark]);
+    let mut queue = vec![bookmark];
+    while let Some(branch) = queue.pop() {
+        for &next in neighbors.get(branch).into_iter().flatten() {
+            if heads.contains(next) && connected.insert(next) {
+                queue.push(next);
+            }
+        }
+    }
+    let connected: HashSet<String> = connected.into_iter().map(str::to_string).collect();
+    prs.into_iter()
+        .filter(|pr| connected.contains(&pr.head))
+        .collect()
+}
+
+/// Cuts the stack holding top, a bookmark or a PR number, down to the PRs
+/// from base up to top. Returns that chain along with how many PRs of the
+/// stack were left out under and over it, or None when base is not under
+/// top.
+pub fn select_between(stacks: &[Stack], base: &str, top: &str) -> Option<(Stack, usize, usize)> {
+    let matches = |pr: &PullRequest, selector: &str| {
+        selector.trim_start_matches('#').parse() == Ok(pr.number) || pr.head == selector
+    };
+    let stack = stacks
+        .iter()
+        .find(|stack| stack.iter().any(|pr| matches(pr, top)))?;
+    let parent = |pr: &PullRequest| stack.iter().find(|parent| parent.head == pr.base);
+    let mut current = stack.iter().find(|pr| matches(pr, top))?;
+    let mut chain = vec![current.clone()];
+    while !matches(current, base) {
+        current = parent(current)?;
+        chain.push(current.clone());
+    }
+    chain.reverse();
+    let mut below = 0;
+    while let Some(pr) = parent(current) {
+        below += 1;
+        current = pr;
+    }
+    let above = stack.len() - chain.len() - below;
+    Some((chain, below, above))
+}
+
+/// Groups PRs into stacks by following base branches to the PRs whose head
+/// they point at.
+pub fn build_pr_stacks(prs: Vec<PullRequest>) -> Vec<Stack> {
+    let mut head: HashMap<String, PullRequest> = HashMap::new();
+    for pr in &prs {
+        head.insert(pr.head.clone(), pr.clone());
+    }
+    let mut child_idx: HashMap<String, Vec<PullRequest>> = HashMap::new();
+    for pr in &prs {
+        if let Some(parent) = head.get(&pr.base) {
+            child_idx
+                .entry(parent.head.clone())
+                .or_default()
+                .push(pr.clone());
+        }
+    }
+    for children in child_idx.values_mut() {
+        children.sort_by_key(|pr| pr.number);
+    }
+    let mut visited = HashSet::new();
+    let mut stacks = Vec::new();
+    for pr in &prs {
+        if visited.contains(&pr.head) {
+            continue;
+        }
+        let mut current = pr.clone();
+        let mut path = vec![current.number];
+        let mut cycle = None;
+        while let Some(parent) = head.get(&current.base) {
+            if let Some(start) = path.iter().position(|&n| n == parent.number) {
+                cycle = Some(path.split_off(start));
+                break;
+            }
+            current = parent.clone();
+            path.push(current.number);
+        }
+        if let Some(mut cycle) = cycle {
+            cycle.sort();
+            let numbers: Vec<String> = cycle.iter().map(|n| format!("#{}", n)).collect();
+            warn!(
+                "skipping the stack of PRs {}: their bases form a cycle",
+                numbers.join(", ")
+            );
+            // Whatever is stacked on the cycle has no bottom either.
+            let mut pending = vec![current];
+            while let Some(current) = pending.pop() {
+                if visited.insert(current.head.clone()) {
+                    if let Some(children) = child_idx.get(&current.head) {
+                        pending.extend(children.iter().cloned());
+                    }
+                }
+            }
+            continue;
+        }
+        let mut chain = Vec::new();
+        let mut pending = vec![current];
+        while let Some(current) = pending.pop() {
+            visited.insert(current.head.clone());
+            if let Some(children) = child_idx.get(&current.head) {
+                pending.extend(children.iter().rev().cloned());
+            }
+            chain.push(current);
+        }
+        stacks.push(chain);
+    }
+    stacks
+}
+
+/// Renders the navigation block for the PR of current_branch within chain.
+pub fn generate_nav_block(
+    chain: Vec<PullRequest>,
+    current_branch: String,
+    options: &NavOptions,
+) -> NavBlock {
+    if options.style == NavStyle::Mermaid {
+        return mermaid_nav_block(&chain, &current_branch, options);
+    }
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    let mut entries = Vec::new();
+    for (i, pr) in chain.iter().enumerate() {
+        let depth = depths.get(pr.base.as_str()).map_or(0, |d| d + 1);
+        depths.insert(&pr.head, depth);
+        entries.push((i, depth, pr));
+    }
+    let heads: HashSet<&str> = chain.iter().map(|pr| pr.head.as_str()).collect();
+    let mut bases: Vec<&str> = Vec::new();
+    for pr in &chain {
+        if !heads.contains(pr.base.as_str()) && !bases.contains(&pr.base.as_str()) {
+            bases.push(&pr.base);
+        }
+    }
+    let top_down = options.order == NavOrder::TopDown;
+    if top_down {
+        entries.reverse();
+    }
+    let mut s = String::new();
+    use std::fmt::Write;
+    writeln!(s, "{}", STACK_HEADER).unwrap();
+    let heading = stack_heading(chain.len(), options);
+    writeln!(s, "{}", heading.as_deref().unwrap_or("Stack of changes:")).unwrap();
+    let ellipsis = if options.ascii { "..." } else { "…" };
+    let (first, last) = if top_down {
+        (("above", options.more_above), ("below", options.more_below))
+    } else {
+        (("below", options.more_below), ("above", options.more_above))
+    };
+    if first.1 > 0 {
+        writeln!(s, "{} {} more {}", ellipsis, first.1, first.0).unwrap();
+    }
+    for (i, depth, pr) in entries {
+        // Nested lists only read well from the bottom up, where each level
+        // is introduced by its parent entry.
+        let indent = if top_down {
+            String::new()
+        } else {
+            "    ".repeat(depth)
+        };
+        let bullet = match options.format {
+            NavFormat::Numbered | NavFormat::Arrow => format!("{}.", options.more_below + i + 1),
+            NavFormat::Bullets | NavFormat::TopDown => "-".to_string(),
+        };
+        let draft = if pr.draft { " (draft)" } else { "" };
+        let suffix = match (pr.head == current_branch, options.format, options.ascii) {
+            (false, _, _) => "",
+            (true, NavFormat::Arrow, false) => " ⬅ you are here",
+            (true, NavFormat::Arrow, true) => " <- you are here",
+            (true, _, false) => " ◁",
+            (true, _, true) => " <-",
+        };
+        let checks = match pr.checks {
+            Some(checks) if options.ci_status => format!("{} ", check_icon(checks, options)),
+            _ => String::new(),
+        };
+        let size = diff_stat_suffix(pr, options);
+        match options.style {
+            NavStyle::Links => writeln!(
+                s,
+                "{}{} {}[#{}]({}) {}{}{}{}",
+                indent, bullet, checks, pr.number, pr.url, pr.title, draft, size, suffix
+            ),
+            NavStyle::Plain | NavStyle::Mermaid => writeln!(
+                s,
+                "{}{} {}PR #{} (branch: {}){}{}{}",
+                indent, bullet, checks, pr.number, pr.head, draft, size, suffix
+            ),
+        }
+        .unwrap();
+    }
+    if last.1 > 0 {
+        writeln!(s, "{} {} more {}", ellipsis, last.1, last.0).unwrap();
+    }
+    // A top-down bulleted list ends where the stack starts.
+    let bullets = matches!(options.format, NavFormat::Bullets | NavFormat::TopDown);
+    if top_down && bullets {
+        for base in bases {
+            writeln!(s, "- `{}`", base).unwrap();
+        }
+    }
+    if options.neighbors {
+        s.push_str(&neighbor_lines(&chain, &current_branch, options.ascii));
+    }
+    writeln!(s, "{}", STACK_FOOTER).unwrap();
+    s
+}
+
+/// Lists the PRs of other repositories related to the PR of nav_block, each
+/// given along with its repository, at the end of the block.
+pub fn append_related_prs(nav_block: &str, related: &[(String, PullRequest)]) -> NavBlock {
+    let Some(footer) = nav_block.rfind(STACK_FOOTER) else {
+        return nav_block.to_string();
+    };
+    let mut s = nav_block[..footer].to_string();
+    use std::fmt::Write;
+    writeln!(s).unwrap();
+    writeln!(s, "Related PRs in other repositories:").unwrap();
+    for (repo, pr) in related {
+        writeln!(s, "- [{}#{}]({}) {}", repo, pr.number, pr.url, pr.title).unwrap();
+    }
+    s.push_str(&nav_block[footer..]);
+    s
+}
+
+/// Heads the block of a named stack with its name and number of PRs,
+/// including those left out of the chain.
+fn stack_heading(chain_len: usize, options: &NavOptions) -> Option<String> {
+    let name = options.name.as_deref()?;
+    let total = options.more_below + chain_len + options.more_above;
+    Some(match total {
+        1 => format!("Stack: {} (1 PR)", name),
+        _ => format!("Stack: {} ({} PRs)", name, total),
+    })
+}
+
+fn check_icon(checks: CheckState, options: &NavOptions) -> &'static str {
+    if options.ascii {
+        checks.ascii_icon()
+    } else {
+        checks.icon()
+    }
+}
+
+fn diff_stat_suffix(pr: &PullRequest, options: &NavOptions) -> String {
+    match pr.diff_stat {
+        Some(stat) if options.diff_stats => format!(" {}", stat.summary(options.ascii)),
+        _ => String::new(),
+    }
+}
+
+/// Names the PR current_branch is based on and the PRs based on it.
+fn neighbor_lines(chain: &[PullRequest], current_branch: &str, ascii: bool) -> String {
+    let Some(current) = chain.iter().find(|pr| pr.head == current_branch) else {
+        return String::new();
+    };
+    let mut s = String::new();
+    use std::fmt::Write;
+    writeln!(s).unwrap();
+    let (up, down) = if ascii { ("", "") } else { ("⬆ ", "⬇ ") };
+    if let Some(parent) = chain.iter().find(|pr| pr.head == current.base) {
+        writeln!(s, "{}Depends on #{}", up, parent.number).unwrap();
+    }
+    let children: Vec<String> = chain
+        .iter()
+        .filter(|pr| pr.base == current.head)
+        .map(|pr| format!("#{}", pr.number))
+        .collect();
+    if !children.is_empty() {
+        writeln!(s, "{}Required by {}", down, children.join(", ")).unwrap();
+    }
+    if s.len() == 1 {
+        return String::new();
+    }
+    s
+}
+
+/// Draws chain as a Mermaid flowchart, from the base branches of the stack
+/// down to its tips, with a link on every PR.
+fn mermaid_nav_block(
+    chain: &[PullRequest],
+    current_branch: &str,
+    options: &NavOptions,
+) -> NavBlock {
+    use std::fmt::Write;
+    let heads: HashSet<&str> = chain.iter().map(|pr| pr.head.as_str()).collect();
+    let mut s = String::new();
+    writeln!(s, "{}", STACK_HEADER).unwrap();
+    if let Some(heading) = stack_heading(chain.len(), options) {
+        writeln!(s, "{}", heading).unwrap();
+    }
+    writeln!(s, "```mermaid").unwrap();
+    writeln!(s, "graph TD").unwrap();
+    let mut bases = Vec::new();
+    for pr in chain {
+        if !heads.contains(pr.base.as_str()) && !bases.contains(&pr.base.as_str()) {
+            bases.push(pr.base.as_str());
+            writeln!(
+                s,
+                "    base{}[\"{}\"]",
+                bases.len(),
+                mermaid_escape(&pr.base)
+            )
+            .unwrap();
+        }
+    }
+    for pr in chain {
+        let checks = match pr.checks {
+            Some(checks) if options.ci_status => format!("{} ", check_icon(checks, options)),
+            _ => String::new(),
+        };
+        let draft = if pr.draft { " (draft)" } else { "" };
+        writeln!(
+            s,
+            "    pr{}[\"{}#{} {}{}{}\"]",
+            pr.number,
+            checks,
+            pr.number,
+            mermaid_escape(&pr.title),
+            draft,
+            diff_stat_suffix(pr, options)
+        )
+        .unwrap();
+    }
+    for pr in chain {
+        match bases.iter().position(|base| *base == pr.base) {
+            Some(i) => writeln!(s, "    base{} --> pr{}", i + 1, pr.number),
+            None => {
+                let parent = chain.iter().find(|p| p.head == pr.base).unwrap();
+                writeln!(s, "    pr{} --> pr{}", parent.number, pr.number)
+            }
+        }
+        .unwrap();
+    }
+    for pr in chain {
+        writeln!(s, "    click pr{} \"{}\"", pr.number, pr.url).unwrap();
+        if pr.head == current_branch {
+            writeln!(s, "    style pr{} stroke-width:4px", pr.number).unwrap();
+        }
+    }
+    writeln!(s, "```").unwrap();
+    if options.neighbors {
+        s.push_str(&neighbor_lines(chain, current_branch, options.ascii));
+    }
+    writeln!(s, "{}", STACK_FOOTER).unwrap();
+    s
+}
+
+/// Escapes text for a quoted Mermaid node label.
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+}
+
+/// Like generate_nav_block, but renders the entries with a minijinja
+/// template.
+pub fn render_nav_template(
+    template: &str,
+    chain: Vec<PullRequest>,
+    current_branch: String,
+    name: Option<&str>,
+) -> Result<NavBlock, JjstackError> {
+    let mut depths: HashMap<&str, usize> = HashMap::new();
+    let mut prs = Vec::new();
+    let mut position = 0;
+    for (i, pr) in chain.iter().enumerate() {
+        let depth = depths.get(pr.base.as_str()).map_or(0, |d| d + 1);
+        depths.insert(&pr.head, depth);
+        let current = pr.head == current_branch;
+        if current {
+            position = i + 1;
+        }
+        prs.push(json!({
+            "number": pr.number,
+            "title": pr.title,
+            "url": pr.url,
+            "head": pr.head,
+            "base": pr.base,
+            "depth": depth,
+            "position": i + 1,
+            "current": current,
+            "checks": pr.checks.map(CheckState::name),
+            "draft": pr.draft,
+            "additions": pr.diff_stat.map(|stat| stat.additions),
+            "deletions": pr.diff_stat.map(|stat| stat.deletions),
+            "commits": pr.diff_stat.and_then(|stat| stat.commits),
+        }));
+    }
+    let current = prs.iter().find(|pr| pr["current"] == true).cloned();
+    let env = minijinja::Environment::new();
+    let rendered = env
+        .render_str(
+            template,
+            json!({
+                "prs": prs,
+                "current": current,
+                "position": position,
+                "total": chain.len(),
+                "name": name,
+            }),
+        )
+        .map_err(|e| format!("cannot render navigation template: {}", e))?;
+    let mut s = String::new();
+    s.push_str(STACK_HEADER);
+    s.push('\n');
+    s.push_str(rendered.trim_end());
+    s.push('\n');
+    s.push_str(STACK_FOOTER);
+    s.push('\n');
+    Ok(s)
+}
+
+/// Replaces any navigation block in body with nav_block, placed according to
+/// placement. An empty nav_block just strips the existing one. When the
+/// heading or placeholder a placement refers to is missing, the block is
+/// appended at the end.
+pub fn render_body(
+    body: String,
+    nav_block: String,
+    placement: Placement,
+    heading: Option<&str>,
+) -> String {
+    let mut new_body = remove_nav_block(body);
+    if nav_block.is_empty() {
+        return new_body;
+    }
+    let split = match placement {
+        Placement::Top => Some(0),
+        Placement::Bottom => None,
+        Placement::AfterHeading => heading.and_then(|heading| {
+            let mut pos = 0;
+            for line in new_body.split_inclusive('\n') {
+                pos += line.len();
+                if line.trim() == heading.trim() {
+                    return Some(pos);
+                }
+            }
+            None
+        }),
+        Placement::Placeholder => new_body
+            .find(PLACEHOLDER)
+            .map(|pos| pos + PLACEHOLDER.len()),
+    };
+    if let Some(pos) = split {
+        return insert_nav_block(&new_body, pos, &nav_block);
+    }
+    if !new_body.is_empty() && !new_body.ends_with('\n') {
+        new_body.push('\n');
+    }
+    new_body.push('\n');
+    new_body.push_str(&nav_block);
+    new_body.push('\n');
+    new_body
+}
+
+fn insert_nav_block(body: &str, pos: usize, nav_block: &str) -> String {
+    let before = body[..pos].trim_end();
+    let after = body[pos..].trim_start();
+    let mut s = String::new();
+    if !before.is_empty() {
+        s.push_str(before);
+        s.push('\n');
+    }
+    s.push_str(nav_block);
+    if !after.is_empty() {
+        s.push('\n');
+        s.push_str(after);
+    }
+    s
+}
+
+/// First and last lines of the stack sections other tools write in PR
+/// descriptions: Graphite, in its two wordings, and spr.
+const FOREIGN_STACK_MARKERS: &[(&str, &str)] = &[
+    (
+        "Current dependencies on/for this PR:",
+        "auto-generated by [Graphite]",
+    ),
+    (
+        "Current dependencies on/for this PR:",
+        "managed by Graphite",
+    ),
+    ("**Stack**:", "Part of a stack created by [spr]"),
+];
+
+/// Finds the stack section another tool wrote in body, returning its byte
+/// range, whole lines included.
+pub fn find_foreign_stack(body: &str) -> Option<(usize, usize)> {
+    FOREIGN_STACK_MARKERS.iter().find_map(|(first, last)| {
+        let start = body.find(first)?;
+        let start = body[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let end = start + body[start..].find(last)?;
+        let end = body[end..]
+            .find('\n')
+            .map_or(body.len(), |pos| end + pos + 1);
+        Some((start, end))
+    })
+}
+
+/// Replaces any navigation block in body with nav_block, placed next to or
+/// instead of the stack section of another tool. Returns None when body has
+/// no such section, or the configuration ignores them.
+pub fn render_body_with_foreign_stack(
+    body: &str,
+    nav_block: &str,
+    foreign: ForeignStacks,
+) -> Option<String> {
+    if foreign == ForeignStacks::Ignore || nav_block.is_empty() {
+        return None;
+    }
+    let mut body = remove_nav_block(body.to_string());
+    let (start, end) = find_foreign_stack(&body)?;
+    if foreign == ForeignStacks::Replace {
+        body.replace_range(start..end, "");
+        return Some(insert_nav_block(&body, start, nav_block));
+    }
+    Some(insert_nav_block(&body, end, nav_block))
+}
+
+/// Strips the navigation block from body. Descriptions merged by hand may
+/// hold several blocks, or a footer before its header: every block, from a
+/// footer back to the nearest header, and every stray footer is removed. A
+/// header with no footer after it is kept, as the end of its block is
+/// unknown.
+pub fn remove_nav_block(body: String) -> String {
+    if !body.contains(STACK_FOOTER) {
+        return body;
+    }
+    let mut kept = Vec::new();
+    let mut blocks = 0;
+    let mut stray = 0;
+    let mut rest = body.as_str();
+    while let Some(footer) = rest.find(STACK_FOOTER) {
+        match rest[..footer].rfind(STACK_HEADER) {
+            Some(header) => {
+                blocks += 1;
+                kept.push(&rest[..header]);
+            }
+            None => {
+                stray += 1;
+                kept.push(&rest[..footer]);
+            }
+        }
+        rest = &rest[footer + STACK_FOOTER.len()..];
+    }
+    kept.push(rest);
+    if blocks > 1 || stray > 0 {
+        warn!(
+            "repaired a description with {} navigation blocks and {} stray footers",
+            blocks, stray
+        );
+    }
+    kept.into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pr(number: i32, head: &str, base: &str) -> PullRequest {
+        PullRequest {
+            number,
+            title: format!("change {}", number),
+            url: format!("https://example.com/pull/{}", number),
+            head: head.to_string(),
+            base: base.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn numbers(stack: &Stack) -> Vec<i32> {
+        stack.iter().map(|pr| pr.number).collect()
+    }
+
+    #[test]
+    fn build_pr_stacks_orders_from_the_bottom() {
+        let prs = vec![pr(3, "c", "b"), pr(1, "a", "main"), pr(2, "b", "a")];
+        let stacks = build_pr_stacks(prs);
+        assert_eq!(stacks.len(), 1);
+        assert_eq!(numbers(&stacks[0]), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn build_pr_stacks_walks_branches_depth_first() {
+        let prs = vec![
+            pr(1, "a", "main"),
+            pr(3, "c", "a"),
+            pr(2, "b", "a"),
+            pr(4, "d", "b"),
+        ];
+        let stacks = build_pr_stacks(prs);
+        assert_eq!(stacks.len(), 1);
+        assert_eq!(numbers(&stacks[0]), vec![1, 2, 4, 3]);
+    }
+
+    #[test]
+    fn build_pr_stacks_separates_unrelated_prs() {
+        let prs = vec![pr(1, "a", "main"), pr(2, "b", "main"), pr(3, "c", "b")];
+        let stacks = build_pr_stacks(prs);
+        assert_eq!(stacks.len(), 2);
+        assert_eq!(numbers(&stacks[0]), vec![1]);
+        assert_eq!(numbers(&stacks[1]), vec![2, 3]);
+    }
+
+    #[test]
+    fn build_pr_stacks_skips_cycles() {
+        let prs = vec![
+            pr(1, "a", "b"),
+            pr(2, "b", "a"),
+            pr(3, "c", "b"),
+            pr(4, "d", "main"),
+            pr(5, "e", "e"),
+        ];
+        let stacks = build_pr_stacks(prs);
+        let numbers: Vec<Vec<i32>> = stacks
+            .iter()
+            .map(|stack| stack.iter().map(|pr| pr.number).collect())
+            .collect();
+        assert_eq!(numbers, vec![vec![4]]);
+    }
+
+    #[test]
+    fn select_stacks_keeps_stacks_with_the_bookmark() {
+        let prs = vec![pr(1, "a", "main"), pr(2, "b", "main"), pr(3, "c", "b")];
+        let selected = select_stacks(prs, &["c".to_string()]);
+        let numbers: Vec<i32> = selected.iter().map(|pr| pr.number).collect();
+        assert_eq!(numbers, vec![2, 3]);
+    }
+
+    #[test]
+    fn select_connected_follows_forge_and_jj_bases() {
+        // c is stacked on b in jj but still targets main on the forge, and d
+        // is another stack on main.
+        let prs = vec![
+            pr(1, "a", "main"),
+            pr(2, "b", "a"),
+            pr(3, "c", "main"),
+            pr(4, "d", "main"),
+        ];
+        let jj_bases = HashMap::from([("c".to_string(), "b".to_string())]);
+        let selected = select_connected(prs, &jj_bases, "b");
+        let numbers: Vec<i32> = selected.iter().map(|pr| pr.number).collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn generate_nav_block_indents_branches_and_marks_current() {
+        let chain = vec![pr(1, "a", "main"), pr(2, "b", "a"), pr(3, "c", "a")];
+        let nav_block = generate_nav_block(chain, "b".to_string(), &NavOptions::default());
+        let expected = [
+            STACK_HEADER,
+            "Stack of changes:",
+            "1. [#1](https://example.com/pull/1) change 1",
+            "    2. [#2](https://example.com/pull/2) change 2 ◁",
+            "    3. [#3](https://example.com/pull/3) change 3",
+            STACK_FOOTER,
+            "",
+        ]
+        .join("\n");
+        assert_eq!(nav_block, expected);
+    }
+
+    #[test]
+    fn generate_nav_block_heads_named_stacks() {
+        let chain = vec![pr(2, "b", "a"), pr(3, "c", "b")];
+        let options = NavOptions {
+            more_below: 1,
+            name: Some("payment-refactor".to_string()),
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "b".to_string(), &options);
+        let expected = [
+            STACK_HEADER,
+            "Stack: payment-refactor (3 PRs)",
+            "… 1 more below",
+            "2. [#2](https://example.com/pull/2) change 2 ◁",
+            "    3. [#3](https://example.com/pull/3) change 3",
+            STACK_FOOTER,
+            "",
+        ]
+        .join("\n");
+        assert_eq!(nav_block, expected);
+    }
+
+    #[test]
+    fn generate_nav_block_top_down_ends_with_the_trunk() {
+        let chain = vec![pr(1, "a", "main"), pr(2, "b", "a")];
+        let options = NavOptions {
+            format: NavFormat::Bullets,
+            order: NavOrder::TopDown,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "a".to_string(), &options);
+        let expected = [
+            STACK_HEADER,
+            "Stack of changes:",
+            "- [#2](https://example.com/pull/2) change 2",
+            "- [#1](https://example.com/pull/1) change 1 ◁",
+            "- `main`",
+            STACK_FOOTER,
+            "",
+        ]
+        .join("\n");
+        assert_eq!(nav_block, expected);
+    }
+
+    #[test]
+    fn generate_nav_block_top_down_order_keeps_the_numbers() {
+        let chain = vec![pr(1, "a", "main"), pr(2, "b", "a"), pr(3, "c", "b")];
+        let options = NavOptions {
+            order: NavOrder::TopDown,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "b".to_string(), &options);
+        let expected = [
+            STACK_HEADER,
+            "Stack of changes:",
+            "3. [#3](https://example.com/pull/3) change 3",
+            "2. [#2](https://example.com/pull/2) change 2 ◁",
+            "1. [#1](https://example.com/pull/1) change 1",
+            STACK_FOOTER,
+            "",
+        ]
+        .join("\n");
+        assert_eq!(nav_block, expected);
+    }
+
+    #[test]
+    fn generate_nav_block_names_neighbors() {
+        let chain = vec![pr(1, "a", "main"), pr(2, "b", "a"), pr(3, "c", "a")];
+        let options = NavOptions {
+            neighbors: true,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain.clone(), "a".to_string(), &options);
+        assert!(!nav_block.contains("Depends on"));
+        assert!(nav_block.contains("\n\n⬇ Required by #2, #3\n"));
+        let nav_block = generate_nav_block(chain, "b".to_string(), &options);
+        assert!(nav_block.contains("\n\n⬆ Depends on #1\n"));
+        assert!(!nav_block.contains("Required by"));
+    }
+
+    #[test]
+    fn generate_nav_block_ascii_has_no_emoji() {
+        let mut chain = vec![pr(1, "a", "main"), pr(2, "b", "a")];
+        chain[0].checks = Some(CheckState::Success);
+        let options = NavOptions {
+            ci_status: true,
+            neighbors: true,
+            ascii: true,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "a".to_string(), &options);
+        assert!(nav_block.is_ascii());
+        assert!(nav_block.contains("1. [pass] [#1](https://example.com/pull/1) change 1 <-\n"));
+        assert!(nav_block.contains("\nRequired by #2\n"));
+    }
+
+    #[test]
+    fn generate_nav_block_plain_style_shows_drafts() {
+        let mut draft = pr(2, "b", "a");
+        draft.draft = true;
+        let chain = vec![pr(1, "a", "main"), draft];
+        let options = NavOptions {
+            style: NavStyle::Plain,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "a".to_string(), &options);
+        assert!(nav_block.contains("1. PR #1 (branch: a) ◁\n"));
+        assert!(nav_block.contains("    2. PR #2 (branch: b) (draft)\n"));
+    }
+
+    #[test]
+    fn generate_nav_block_shows_diff_stats() {
+        let mut chain = vec![pr(1, "a", "main"), pr(2, "b", "a")];
+        chain[0].diff_stat = Some(DiffStat {
+            additions: 120,
+            deletions: 35,
+            commits: Some(3),
+        });
+        let options = NavOptions {
+            diff_stats: true,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "a".to_string(), &options);
+        assert!(nav_block
+            .contains("1. [#1](https://example.com/pull/1) change 1 (+120 −35, 3 commits) ◁\n"));
+        assert!(nav_block.contains("    2. [#2](https://example.com/pull/2) change 2\n"));
+    }
+
+    #[test]
+    fn append_related_prs_links_other_repositories() {
+        let chain = vec![pr(1, "a", "main"), pr(2, "b", "a")];
+        let nav_block = generate_nav_block(chain, "a".to_string(), &NavOptions::default());
+        let related = [("acme/frontend".to_string(), pr(7, "fe-a", "main"))];
+        let nav_block = append_related_prs(&nav_block, &related);
+        let expected = format!(
+            "\n\nRelated PRs in other repositories:\n- [acme/frontend#7](https://example.com/pull/7) change 7\n{}\n",
+            STACK_FOOTER
+        );
+        assert!(nav_block.ends_with(&expected));
+    }
+
+    #[test]
+    fn select_between_marks_the_truncated_ends() {
+        let stacks = build_pr_stacks(vec![
+            pr(1, "a", "main"),
+            pr(2, "b", "a"),
+            pr(3, "c", "b"),
+            pr(4, "d", "c"),
+            pr(5, "e", "d"),
+        ]);
+        assert!(select_between(&stacks, "d", "b").is_none());
+        let (chain, more_below, more_above) = select_between(&stacks, "#2", "c").unwrap();
+        assert_eq!(chain.iter().map(|pr| pr.number).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!((more_below, more_above), (1, 2));
+        let options = NavOptions {
+            more_below,
+            more_above,
+            ..Default::default()
+        };
+        let nav_block = generate_nav_block(chain, "b".to_string(), &options);
+        let expected = [
+            STACK_HEADER,
+            "Stack of changes:",
+            "… 1 more below",
+            "2. [#2](https://example.com/pull/2) change 2 ◁",
+            "    3. [#3](https://example.com/pull/3) change 3",
+            "… 2 more above",
+            STACK_FOOTER,
+            "",
+        ]
+        .join("\n");
+        assert_eq!(nav_block, expected);
+    }
+
+    fn nav_block() -> NavBlock {
+        format!("{}\nStack of changes:\n{}\n", STACK_HEADER, STACK_FOOTER)
+    }
+
+    #[test]
+    fn render_body_appends_once() {
+        let body = render_body(
+            "Description".to_string(),
+            nav_block(),
+            Placement::Bottom,
+            None,
+        );
+        assert_eq!(body, format!("Description\n\n{}\n", nav_block()));
+        let again = render_body(body.clone(), nav_block(), Placement::Bottom, None);
+        assert_eq!(again, body);
+    }
+
+    #[test]
+    fn render_body_replaces_a_block_in_the_middle() {
+        let body = format!("Before\n\n{}\nAfter", nav_block().replace("Stack", "Old"));
+        let new_body = render_body(body, nav_block(), Placement::Bottom, None);
+        assert_eq!(new_body, format!("Before\nAfter\n\n{}\n", nav_block()));
+    }
+
+    #[test]
+    fn render_body_with_empty_block_strips_it() {
+        let body = format!("Description\n\n{}\n", nav_block());
+        let new_body = render_body(body, String::new(), Placement::Bottom, None);
+        assert_eq!(new_body, "Description");
+    }
+
+    #[test]
+    fn render_body_keeps_a_body_without_footer() {
+        let body = format!("Description\n{}\nno footer", STACK_HEADER);
+        assert_eq!(remove_nav_block(body.clone()), body);
+    }
+
+    #[test]
+    fn remove_nav_block_repairs_duplicated_and_misordered_markers() {
+        let body = format!(
+            "{}\nBefore\n{}Between\n{}\nAfter",
+            STACK_FOOTER,
+            nav_block(),
+            nav_block()
+        );
+        assert_eq!(remove_nav_block(body), "Before\nBetween\nAfter");
+    }
+
+    #[test]
+    fn render_body_places_the_block_on_top() {
+        let body = render_body("Description".to_string(), nav_block(), Placement::Top, None);
+        assert_eq!(body, format!("{}\nDescription", nav_block()));
+        let again = render_body(body.clone(), nav_block(), Placement::Top, None);
+        assert_eq!(again, body);
+    }
+
+    #[test]
+    fn render_body_places_the_block_below_the_placeholder() {
+        let original = format!("Intro\n{}\nDetails", PLACEHOLDER);
+        let body = render_body(original, nav_block(), Placement::Placeholder, None);
+        assert_eq!(
+            body,
+            format!("Intro\n{}\n{}\nDetails", PLACEHOLDER, nav_block())
+        );
+        let again = render_body(body.clone(), nav_block(), Placement::Placeholder, None);
+        assert_eq!(again, body);
+    }
+
+    #[test]
+    fn render_body_places_the_block_below_the_heading() {
+        let original = "Intro\n## Stack\nDetails".to_string();
+        let heading = Some("## Stack");
+        let body = render_body(original, nav_block(), Placement::AfterHeading, heading);
+        assert_eq!(body, format!("Intro\n## Stack\n{}\nDetails", nav_block()));
+        let again = render_body(body.clone(), nav_block(), Placement::AfterHeading, heading);
+        assert_eq!(again, body);
+    }
+
+    #[test]
+    fn render_body_falls_back_to_the_bottom() {
+        let body = render_body(
+            "Description".to_string(),
+            nav_block(),
+            Placement::AfterHeading,
+            Some("## Missing"),
+        );
+        assert_eq!(body, format!("Description\n\n{}\n", nav_block()));
+    }
+
+    #[test]
+    fn render_body_with_foreign_stack_replaces_or_follows_spr() {
+        let body = "Description\n\n**Stack**:\n- #2\n- #1 ⬅\n\n\n\
+                    ⚠️ *Part of a stack created by [spr](https://github.com/ejoffe/spr).*\n\
+                    Footer";
+        assert_eq!(find_foreign_stack(body), Some((13, body.len() - 6)));
+
+        let replaced = render_body_with_foreign_stack(body, &nav_block(), ForeignStacks::Replace);
+        assert_eq!(
+            replaced.unwrap(),
+            format!("Description\n{}\nFooter", nav_block())
+        );
+        let adjacent =
+            render_body_with_foreign_stack(body, &nav_block(), ForeignStacks::Adjacent).unwrap();
+        assert_eq!(
+            adjacent,
+            format!("{}{}\nFooter", &body[..body.len() - 6], nav_block())
+        );
+        assert_eq!(
+            render_body_with_foreign_stack(&adjacent, &nav_block(), ForeignStacks::Adjacent),
+            Some(adjacent)
+        );
+        assert_eq!(
+            render_body_with_foreign_stack("Description", &nav_block(), ForeignStacks::Replace),
+            None
+        );
+    }
+}
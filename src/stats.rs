@@ -0,0 +1,158 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use jjstack_core::config::{AnnotateMode, Config};
+use jjstack_core::forge::Forge;
+use jjstack_core::jj::get_bookmarks;
+use jjstack_core::stack::{build_pr_stacks, STACK_FOOTER, STACK_HEADER};
+use jjstack_core::PullRequest;
+
+use crate::cli::StatsArgs;
+use crate::{find_divergent_bases, find_nav_comment, get_open_prs, run_concurrently};
+
+#[derive(Serialize)]
+struct Stats {
+    repo: String,
+    stacks: usize,
+    prs: usize,
+    average_depth: f64,
+    max_depth: usize,
+    oldest: Option<StatsPr>,
+    missing_nav_blocks: Vec<StatsPr>,
+    stale_bases: Vec<StaleBase>,
+}
+
+#[derive(Serialize)]
+struct StatsPr {
+    number: i32,
+    title: String,
+    head: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+}
+
+impl StatsPr {
+    fn new(pr: &PullRequest) -> Self {
+        StatsPr {
+            number: pr.number,
+            title: pr.title.to_string(),
+            head: pr.head.to_string(),
+            created_at: pr.created_at.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StaleBase {
+    number: i32,
+    title: String,
+    base: String,
+    expected: String,
+}
+
+/// Reports the health of the stacks built from local bookmarks: how many
+/// there are and how deep, their oldest PR, the PRs without a navigation
+/// block and those whose base does not match the jj commit graph.
+pub fn run(
+    repo: String,
+    forge: &dyn Forge,
+    args: StatsArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bookmarks: HashSet<String> = get_bookmarks(config)?.into_iter().collect();
+    let prs = get_open_prs(forge, bookmarks, &repo, config)?;
+    let stale_bases = find_divergent_bases(&prs, config)?
+        .into_iter()
+        .map(|(pr, expected)| StaleBase {
+            number: pr.number,
+            title: pr.title,
+            base: pr.base,
+            expected,
+        })
+        .collect();
+    // A lone PR is not stacked, and carries no navigation block.
+    let stacks: Vec<_> = build_pr_stacks(prs)
+        .into_iter()
+        .filter(|stack| stack.len() > 1)
+        .collect();
+    let members: Vec<PullRequest> = stacks.iter().flatten().cloned().collect();
+    let missing = run_concurrently(&members, args.jobs, |pr| -> Result<bool, String> {
+        match config.mode.unwrap_or_default() {
+            AnnotateMode::Body => {
+                Ok(!pr.body.contains(STACK_HEADER) && !pr.body.contains(STACK_FOOTER))
+            }
+            AnnotateMode::Comment => Ok(find_nav_comment(forge, pr.number)?.is_none()),
+        }
+    });
+    let mut missing_nav_blocks = Vec::new();
+    for (pr, missing) in members.iter().zip(missing) {
+        if missing? {
+            missing_nav_blocks.push(StatsPr::new(pr));
+        }
+    }
+    let stats = Stats {
+        repo,
+        stacks: stacks.len(),
+        prs: members.len(),
+        average_depth: if stacks.is_empty() {
+            0.0
+        } else {
+            members.len() as f64 / stacks.len() as f64
+        },
+        max_depth: stacks.iter().map(Vec::len).max().unwrap_or_default(),
+        oldest: members
+            .iter()
+            .filter(|pr| pr.created_at.is_some())
+            .min_by(|a, b| a.created_at.cmp(&b.created_at))
+            .map(StatsPr::new),
+        missing_nav_blocks,
+        stale_bases,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+    } else {
+        print_stats(&stats);
+    }
+    Ok(())
+}
+
+fn print_stats(stats: &Stats) {
+    println!("repo: {:?}", stats.repo);
+    println!("stacks: {} ({} PRs)", stats.stacks, stats.prs);
+    println!(
+        "average depth: {:.1} (deepest: {})",
+        stats.average_depth, stats.max_depth
+    );
+    if let Some(pr) = &stats.oldest {
+        println!(
+            "oldest PR: #{} {:?} (branch: {}), opened {}",
+            pr.number,
+            pr.title,
+            pr.head,
+            pr.created_at.as_deref().unwrap_or_default()
+        );
+    }
+    println!(
+        "missing navigation blocks: {}",
+        stats.missing_nav_blocks.len()
+    );
+    for pr in &stats.missing_nav_blocks {
+        println!("\tPR #{} {:?} (branch: {})", pr.number, pr.title, pr.head);
+    }
+    println!("stale bases: {}", stats.stale_bases.len());
+    for stale in &stats.stale_bases {
+        println!(
+            "\tPR #{} {:?}: base is {}, jj parent is {}",
+            stale.number, stale.title, stale.base, stale.expected
+        );
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{Action, PrReport, Report};
+
+/// Titles longer than this are truncated in the tables.
+const TITLE_WIDTH: usize = 40;
+
+/// Set when terminal output is limited to ASCII.
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+/// Limits terminal output to ASCII, replacing the symbols and emoji that
+/// some terminals cannot show.
+pub fn set_ascii(ascii: bool) {
+    ASCII.store(ascii, Ordering::Relaxed);
+}
+
+/// Reports whether terminal output is limited to ASCII.
+pub fn ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+/// Reports whether the locale, from LC_ALL, LC_CTYPE or LANG, uses UTF-8.
+/// An unset locale counts as UTF-8, as on Windows and in most containers.
+pub fn utf8_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .is_none_or(|value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+}
+
+/// Reports whether output to the terminal should be colored: stdout is a
+/// terminal and NO_COLOR is not set.
+pub fn use_color() -> bool {
+    io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+/// Prints one table per stack, then the closed PRs that were pruned, and
+/// finally a count of the PRs by outcome.
+pub fn print_report(report: &Report) {
+    let color = use_color();
+    for (i, stack) in report.stacks.iter().enumerate() {
+        println!("stack {}:", i + 1);
+        print_table(&stack.prs, report.applied, color);
+    }
+    if !report.pruned.is_empty() {
+        println!("closed PRs:");
+        print_table(&report.pruned, report.applied, color);
+    }
+    let prs: Vec<&PrReport> = report
+        .stacks
+        .iter()
+        .flat_map(|s| &s.prs)
+        .chain(&report.pruned)
+        .collect();
+    print_summary(&prs, report.applied);
+}
+
+/// Prints a single table of PRs under a heading, followed by the summary.
+pub fn print_prs(heading: &str, prs: &[PrReport], applied: bool) {
+    if !prs.is_empty() {
+        println!("{}:", heading);
+        print_table(prs, applied, use_color());
+    }
+    print_summary(&prs.iter().collect::<Vec<_>>(), applied);
+}
+
+fn print_summary(prs: &[&PrReport], applied: bool) {
+    if prs.is_empty() {
+        return;
+    }
+    let count = |action: Action| {
+        prs.iter()
+            .filter(|pr| pr.error.is_none() && pr.action == action)
+            .count()
+    };
+    let errors = prs.iter().filter(|pr| pr.error.is_some()).count();
+    let (updated, removed) = if applied {
+        ("updated", "removed")
+    } else {
+        ("to update", "to remove")
+    };
+    println!(
+        "{} {}, {} {}, {} unchanged, {} errors",
+        count(Action::Update),
+        updated,
+        count(Action::Remove),
+        removed,
+        count(Action::Skip),
+        errors
+    );
+}
+
+fn print_table(prs: &[PrReport], applied: bool, color: bool) {
+    let rows: Vec<[String; 5]> = prs
+        .iter()
+        .enumerate()
+        .map(|(i, pr)| {
+            let mut outcome = outcome(pr, applied).to_string();
+            if let Some(base) = &pr.previous_base {
+                outcome.push_str(&format!(", base was {}", base));
+            }
+            if let Some(labels) = &pr.labels {
+                outcome.push_str(&format!(", labels {}", labels.join(" ")));
+            }
+            [
+                (i + 1).to_string(),
+                format!("#{}", pr.number),
+                truncate(&pr.title, TITLE_WIDTH),
+                pr.head.to_string(),
+                outcome,
+            ]
+        })
+        .collect();
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for (pr, row) in prs.iter().zip(rows) {
+        let line = format!(
+            "  {:>w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+        let code = match (&pr.error, pr.action) {
+            (Some(_), _) => "31",
+            (None, Action::Update) => "32",
+            (None, Action::Remove) => "33",
+            (None, Action::Skip) => "2",
+        };
+        if color {
+            println!("\x1b[{}m{}\x1b[0m", code, line.trim_end());
+        } else {
+            println!("{}", line.trim_end());
+        }
+    }
+}
+
+fn outcome(pr: &PrReport, applied: bool) -> &'static str {
+    match (&pr.error, pr.action, applied) {
+        (Some(_), _, _) => "error",
+        (None, Action::Update, true) => "updated",
+        (None, Action::Update, false) => "update",
+        (None, Action::Remove, true) => "removed",
+        (None, Action::Remove, false) => "remove",
+        (None, Action::Skip, _) => "unchanged",
+    }
+}
+
+/// Shortens text to at most width characters, ending it with an ellipsis
+/// when cut.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(width - 1).collect();
+    if ascii() {
+        truncated.pop();
+        truncated.pop();
+        truncated.push_str("...");
+    } else {
+        truncated.push('…');
+    }
+    truncated
+}
@@ -0,0 +1,247 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::{HashMap, HashSet};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use jjstack_core::config::Config;
+use jjstack_core::forge::Forge;
+use jjstack_core::jj::get_bookmarks;
+use jjstack_core::stack::{build_pr_stacks, CheckState, ReviewState, Stack};
+
+use crate::{
+    apply_jj_topology, get_open_prs, list_sibling_prs, load_template, open_url, restack_prs,
+    stack_nav_blocks, table, update_pr_description, SiblingPrs,
+};
+
+const HELP: &str = "↑/↓ move  o open  a annotate  r retarget  R reload  q quit";
+
+/// HELP without arrows, for ASCII-only terminals.
+const ASCII_HELP: &str = "up/down move  o open  a annotate  r retarget  R reload  q quit";
+
+enum Row {
+    Stack(usize),
+    Pr(usize, usize, usize),
+}
+
+struct App<'a> {
+    repo: String,
+    forge: &'a dyn Forge,
+    config: &'a Config,
+    stacks: Vec<Stack>,
+    siblings: Vec<SiblingPrs>,
+    rows: Vec<Row>,
+    state: ListState,
+    message: String,
+}
+
+/// Runs the interactive stack viewer until the user quits.
+pub fn run(
+    repo: String,
+    forge: &dyn Forge,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = App {
+        repo,
+        forge,
+        config,
+        stacks: Vec::new(),
+        siblings: Vec::new(),
+        rows: Vec::new(),
+        state: ListState::default(),
+        message: if table::ascii() { ASCII_HELP } else { HELP }.to_string(),
+    };
+    app.reload()?;
+    let mut terminal = ratatui::init();
+    let result = app.event_loop(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App<'_> {
+    fn reload(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let bookmarks = get_bookmarks(self.config)?;
+        let bookmark_idx: HashSet<String> = bookmarks.into_iter().collect();
+        let mut prs = get_open_prs(self.forge, bookmark_idx, &self.repo, self.config)?;
+        apply_jj_topology(&mut prs, self.config)?;
+        self.stacks = build_pr_stacks(prs);
+        self.siblings = list_sibling_prs(self.config)?;
+        self.rows.clear();
+        for (i, stack) in self.stacks.iter().enumerate() {
+            self.rows.push(Row::Stack(i));
+            let mut depths: HashMap<&str, usize> = HashMap::new();
+            for (j, pr) in stack.iter().enumerate() {
+                let depth = depths.get(pr.base.as_str()).map_or(0, |d| d + 1);
+                depths.insert(&pr.head, depth);
+                self.rows.push(Row::Pr(i, j, depth));
+            }
+        }
+        if self.state.selected().is_none_or(|i| i >= self.rows.len()) {
+            self.state
+                .select(if self.rows.is_empty() { None } else { Some(0) });
+        }
+        Ok(())
+    }
+
+    fn event_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.state.select_previous(),
+                KeyCode::Enter | KeyCode::Char('o') => self.open(),
+                KeyCode::Char('a') => self.annotate(),
+                KeyCode::Char('r') => self.retarget(),
+                KeyCode::Char('R') => {
+                    self.message = match self.reload() {
+                        Ok(()) => "reloaded".to_string(),
+                        Err(e) => format!("cannot reload: {}", e),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, status_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let items: Vec<ListItem> = self.rows.iter().map(|row| self.row_item(row)).collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" jjstack: {} ", self.repo)))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.state);
+        frame.render_widget(Paragraph::new(self.message.as_str()), status_area);
+    }
+
+    fn row_item(&self, row: &Row) -> ListItem<'static> {
+        match *row {
+            Row::Stack(i) => ListItem::new(Line::from(Span::styled(
+                format!("stack {} ({} PRs)", i + 1, self.stacks[i].len()),
+                Style::default().add_modifier(Modifier::BOLD),
+            ))),
+            Row::Pr(i, j, depth) => {
+                let pr = &self.stacks[i][j];
+                let (checks, checks_color) = match (pr.checks, table::ascii()) {
+                    (Some(CheckState::Success), false) => ("✓ CI", Color::Green),
+                    (Some(CheckState::Failure), false) => ("✗ CI", Color::Red),
+                    (Some(CheckState::Pending), false) => ("● CI", Color::Yellow),
+                    (Some(CheckState::Success), true) => ("+ CI", Color::Green),
+                    (Some(CheckState::Failure), true) => ("x CI", Color::Red),
+                    (Some(CheckState::Pending), true) => ("* CI", Color::Yellow),
+                    (None, _) => ("  CI", Color::DarkGray),
+                };
+                let arrow = if table::ascii() { "->" } else { "→" };
+                let (review, review_color) = match pr.review {
+                    Some(ReviewState::Approved) => ("approved", Color::Green),
+                    Some(ReviewState::ChangesRequested) => ("changes requested", Color::Red),
+                    Some(ReviewState::ReviewRequired) => ("review required", Color::Yellow),
+                    None => ("", Color::DarkGray),
+                };
+                let mut spans = vec![
+                    Span::raw(format!(
+                        "  {}#{} {} ",
+                        "  ".repeat(depth),
+                        pr.number,
+                        pr.title
+                    )),
+                    Span::styled(
+                        format!("({} {} {}) ", pr.head, arrow, pr.base),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(format!("{} ", checks), Style::default().fg(checks_color)),
+                    Span::styled(review.to_string(), Style::default().fg(review_color)),
+                ];
+                if pr.draft {
+                    spans.push(Span::styled(" draft", Style::default().fg(Color::DarkGray)));
+                }
+                if pr.mergeable == Some(false) {
+                    spans.push(Span::styled(" conflicts", Style::default().fg(Color::Red)));
+                }
+                ListItem::new(Line::from(spans))
+            }
+        }
+    }
+
+    fn selected_stack(&self) -> Option<usize> {
+        match self.rows.get(self.state.selected()?)? {
+            Row::Stack(i) | Row::Pr(i, _, _) => Some(*i),
+        }
+    }
+
+    fn open(&mut self) {
+        let Some(Row::Pr(i, j, _)) = self.state.selected().and_then(|s| self.rows.get(s)) else {
+            return;
+        };
+        let pr = &self.stacks[*i][*j];
+        self.message = match open_url(&pr.url) {
+            Ok(()) => format!("opened #{}", pr.number),
+            Err(e) => format!("cannot open {}: {}", pr.url, e),
+        };
+    }
+
+    fn annotate(&mut self) {
+        let Some(i) = self.selected_stack() else {
+            return;
+        };
+        let stack = &self.stacks[i];
+        let result = load_template(self.config).and_then(|template| {
+            let nav_blocks = if stack.len() > 1 {
+                stack_nav_blocks(
+                    stack,
+                    (0, 0),
+                    &self.siblings,
+                    self.config,
+                    template.as_deref(),
+                )?
+            } else {
+                HashMap::new()
+            };
+            for pr in stack {
+                let nav_block = nav_blocks.get(&pr.number).cloned().unwrap_or_default();
+                update_pr_description(self.forge, pr.clone(), nav_block, None, self.config)?;
+            }
+            Ok(())
+        });
+        self.message = match result {
+            Ok(()) => format!("stack {} annotated", i + 1),
+            Err(e) => format!("cannot annotate stack {}: {}", i + 1, e),
+        };
+    }
+
+    fn retarget(&mut self) {
+        let Some(i) = self.selected_stack() else {
+            return;
+        };
+        let mut prs = self.stacks[i].clone();
+        self.message = match restack_prs(self.forge, &mut prs, self.config, true, |_| true) {
+            Ok(retargeted) if retargeted.is_empty() => "nothing to retarget".to_string(),
+            Ok(retargeted) => match self.reload() {
+                Ok(()) => format!("retargeted {} PRs", retargeted.len()),
+                Err(e) => format!("cannot reload: {}", e),
+            },
+            Err(e) => format!("cannot retarget stack {}: {}", i + 1, e),
+        };
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2024 http://github.com/cirello-io/jjstack U. Cirello
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software and associated documentation files (the “Software”), to deal in the Software without restriction, including without limitation the rights to use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED “AS IS”, WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+use std::collections::HashSet;
+
+use jjstack_core::config::Config;
+use jjstack_core::forge::Forge;
+use jjstack_core::jj::get_bookmarks;
+
+use crate::cli::UnstackArgs;
+use crate::{get_open_prs, strip_nav_blocks, table, PartialFailure};
+
+/// Strips the navigation block from the open PRs of the local bookmarks or,
+/// with --all, from every open PR of the repository, whether or not its
+/// branch still exists locally.
+pub fn run(
+    repo: String,
+    forge: &dyn Forge,
+    args: UnstackArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("repo: {:?}", repo);
+    let mut prs = if args.all {
+        forge.list_open_prs()?
+    } else {
+        let bookmark_idx: HashSet<String> = get_bookmarks(config)?.into_iter().collect();
+        get_open_prs(forge, bookmark_idx, &repo, config)?
+    };
+    if args.include_closed {
+        prs.extend(forge.list_closed_prs()?);
+    }
+    let reports = strip_nav_blocks(forge, prs, config, args.apply, args.jobs)?;
+    if reports.is_empty() {
+        println!("no navigation blocks found.");
+        return Ok(());
+    }
+    table::print_prs("PRs with a navigation block", &reports, args.apply);
+    let failed = reports.iter().filter(|pr| pr.error.is_some()).count();
+    if failed > 0 {
+        return Err(PartialFailure {
+            failed,
+            total: reports.len(),
+        }
+        .into());
+    }
+    Ok(())
+}